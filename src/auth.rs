@@ -1,54 +1,500 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{marker::PhantomData, sync::Arc, time::Duration};
 
 use axum::extract::FromRequestParts;
-use hmac::{Hmac, Mac};
-use http::request::Parts;
-use jwt::{FromBase64, SignWithKey, VerifyWithKey};
+use http::{
+    header::{SET_COOKIE, WWW_AUTHENTICATE},
+    request::Parts,
+    HeaderName, HeaderValue,
+};
+use jwt::FromBase64;
 use serde::{de::DeserializeOwned, Serialize};
-use sha2::Sha256;
+use serde_json::Value;
 
-use crate::errors::{ApiError, ApiResult};
+use crate::{
+    clock::{self, SharedClock},
+    errors::{ApiError, ApiResult},
+    principal::{AuthMethod, Principal},
+};
+
+mod api_key;
+mod basic;
+mod claims;
+mod cookie;
+mod encryption;
+mod event;
+mod guard;
+mod key;
+mod layer;
+#[cfg(feature = "paseto")]
+mod paseto;
+mod renewal;
+mod revocation;
+mod scopes;
+mod settings;
+mod throttle;
+pub use api_key::{hash_api_key, ApiKey, ApiKeyConfig, ApiKeyParam, ApiKeyPrincipal, ApiKeyStore};
+pub use basic::{constant_time_eq, Basic, BasicConfig, BasicParam, BasicVerifier};
+pub use claims::ClaimsValidation;
+pub use cookie::{CookieConfig, SameSite};
+use encryption::ClaimsEncryption;
+use event::AuthFailure;
+pub use event::{AuthEvent, AuthEventHook, AuthEventKind};
+pub use guard::{Guard, Require};
+use key::Jws;
+pub use key::{Algorithm, AuthKey, UnverifiedToken};
+pub use layer::{AuthLayer, AuthService};
+#[cfg(feature = "paseto")]
+pub use paseto::{PasetoAuth, PasetoAuthConfig, PasetoParam};
+pub use renewal::Renewal;
+pub use revocation::RevocationCheck;
+pub use scopes::{check_scopes, scopes_of, HasScopes, MissingScopesBody, RequireScopes, ScopeSet};
+pub use settings::{AuthSettings, KeySource};
+pub use throttle::{LoginThrottle, MemoryThrottleStore, ThrottleState, ThrottleStore};
+
+const REFRESH_TYPE: &str = "refresh";
+const ACTOR_CLAIM: &str = "act";
 
 pub struct AuthConfig<T: Serialize + DeserializeOwned + FromBase64> {
-    key: Hmac<Sha256>,
-    prefix: String,
+    key: AuthKey,
+    verification_keys: Vec<AuthKey>,
+    prefixes: Vec<String>,
+    prefix_case_insensitive: bool,
+    realm: Option<String>,
+    ttl: Duration,
+    clock: SharedClock,
+    claims: ClaimsValidation,
+    cookie: Option<CookieConfig>,
+    revocation: Option<Arc<dyn RevocationCheck<T>>>,
+    event_hook: Option<AuthEventHook>,
+    encryption: Option<ClaimsEncryption>,
+    renewal: Option<Renewal>,
     _t: PhantomData<T>,
 }
 
 impl<T: Serialize + DeserializeOwned + FromBase64> AuthConfig<T> {
+    /// Signs and verifies with HS256 and the shared secret `key`
     pub fn new(key: &[u8]) -> Self {
+        Self::from_key(AuthKey::hmac(key))
+    }
+
+    pub fn from_key(key: AuthKey) -> Self {
         AuthConfig {
-            key: Hmac::new_from_slice(key).unwrap(),
-            prefix: "Token ".to_string(),
+            key,
+            verification_keys: vec![],
+            prefixes: vec!["Token ".to_string()],
+            prefix_case_insensitive: false,
+            realm: None,
+            ttl: Duration::from_secs(60 * 60),
+            clock: clock::system(),
+            claims: ClaimsValidation::default(),
+            cookie: None,
+            revocation: None,
+            event_hook: None,
+            encryption: None,
+            renewal: None,
             _t: PhantomData,
         }
     }
 
-    pub fn with_prefix(mut self, mut prefix: String) -> Self {
+    /// [`AuthSettings::from_env`], see there for the variables read
+    pub fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        AuthSettings::from_env(prefix)?.build()
+    }
+
+    /// Also accepts tokens signed by `key`, e.g. the previous signing key during rotation. Tokens are checked
+    /// against the keys with the same [`AuthKey::id`] as their `kid` header.
+    pub fn with_verification_key(mut self, key: AuthKey) -> Self {
+        self.verification_keys.push(key);
+        self
+    }
+
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefixes.clear();
+        self.with_accepted_prefix(prefix)
+    }
+
+    /// Also accepts `Authorization` headers with this scheme, e.g. `Bearer` next to `Token`
+    pub fn with_accepted_prefix(mut self, mut prefix: String) -> Self {
         if !prefix.is_empty() {
             prefix.push(' ');
         }
-        self.prefix = prefix;
+        self.prefixes.push(prefix);
+        self
+    }
+
+    /// Matches the scheme of the `Authorization` header ignoring ASCII case, as some gateways normalize it
+    pub fn with_prefix_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.prefix_case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Realm of the `WWW-Authenticate` challenge on rejected requests
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = Some(realm.into());
+        self
+    }
+
+    /// Lifetime of tokens from [`AuthConfig::issue_default`] and [`AuthConfig::issue_cookie_default`], an hour by
+    /// default
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_claims_validation(mut self, claims: ClaimsValidation) -> Self {
+        self.claims = claims;
+        self
+    }
+
+    /// Reads the token from this cookie, falling back to the `Authorization` header when the cookie isn't sent
+    pub fn with_cookie(mut self, cookie: CookieConfig) -> Self {
+        self.cookie = Some(cookie);
+        self
+    }
+
+    pub fn cookie(&self) -> Option<&CookieConfig> {
+        self.cookie.as_ref()
+    }
+
+    /// `Set-Cookie` header with a token for `value` from [`AuthConfig::issue`], expiring along with it
+    pub fn issue_cookie(
+        &self,
+        value: &T,
+        ttl: Duration,
+    ) -> ApiResult<[(HeaderName, HeaderValue); 1]> {
+        Ok([self.token_cookie(&self.issue(value, ttl)?, ttl)?])
+    }
+
+    /// [`AuthConfig::issue_cookie`] with the configured [`AuthConfig::ttl`]
+    pub fn issue_cookie_default(&self, value: &T) -> ApiResult<[(HeaderName, HeaderValue); 1]> {
+        self.issue_cookie(value, self.ttl)
+    }
+
+    /// `Set-Cookie` header with `token`, expiring after `ttl`
+    fn token_cookie(&self, token: &str, ttl: Duration) -> ApiResult<(HeaderName, HeaderValue)> {
+        let mut cookie = self
+            .cookie
+            .clone()
+            .ok_or_else(|| ApiError::Other(anyhow::anyhow!("no auth cookie configured")))?;
+        cookie.max_age = Some(ttl);
+        Ok((SET_COOKIE, cookie.set_cookie(token)?))
+    }
+
+    /// Sliding expiration in [`AuthLayer`], see [`Renewal`]
+    pub fn with_renewal(mut self, renewal: Renewal) -> Self {
+        self.renewal = Some(renewal);
+        self
+    }
+
+    /// Header carrying a fresh token for `claims` when the verified `token` is due for [`Renewal`]. The `act` claim
+    /// of impersonation tokens is carried over.
+    pub fn renew(&self, token: &str, claims: &T) -> ApiResult<Option<(HeaderName, HeaderValue)>> {
+        let Some(renewal) = &self.renewal else {
+            return Ok(None);
+        };
+        let mut current = self.verify(token)?;
+        if !renewal.due(&current, self.clock.now()) {
+            return Ok(None);
+        }
+        let mut renewed = serde_json::to_value(claims)?;
+        if let (Some(actor), Some(object)) = (
+            current.get_mut(ACTOR_CLAIM).map(Value::take),
+            renewed.as_object_mut(),
+        ) {
+            object.insert(ACTOR_CLAIM.to_string(), actor);
+        }
+        let token = self.issue_claims(renewed, renewal.ttl, None)?;
+        if self.cookie.is_some() {
+            return Ok(Some(self.token_cookie(&token, renewal.ttl)?));
+        }
+        Ok(Some((
+            renewal.header.clone(),
+            HeaderValue::from_str(&token)?,
+        )))
+    }
+
+    /// Rejects tokens whose claims `check` reports revoked in [`Auth`] and the other extractors.
+    /// [`AuthConfig::validate`] and [`AuthConfig::exchange_refresh`] don't consult it, see
+    /// [`AuthConfig::is_revoked`].
+    pub fn with_revocation(mut self, check: impl RevocationCheck<T> + 'static) -> Self {
+        self.revocation = Some(Arc::new(check));
+        self
+    }
+
+    pub async fn is_revoked(&self, claims: &T) -> bool {
+        match &self.revocation {
+            Some(check) => check.is_revoked(claims).await,
+            None => false,
+        }
+    }
+
+    /// [`AuthConfig::validate`], then rejects revoked tokens
+    pub async fn authenticate(&self, value: &str) -> ApiResult<T> {
+        Ok(self.authenticate_claims(value).await?.0)
+    }
+
+    /// [`AuthConfig::authenticate`], along with the raw claims
+    async fn authenticate_claims(&self, value: &str) -> Result<(T, Value), AuthFailure> {
+        let claims = self.validate_claims(value)?;
+        let out = decode_claims(claims.clone())?;
+        if self.is_revoked(&out).await {
+            return Err(AuthFailure::new(
+                AuthEventKind::Revoked,
+                "revoked auth token",
+            ));
+        }
+        Ok((out, claims))
+    }
+
+    /// Encrypts the claims of issued tokens with AES-256-GCM under `key` before signing, so clients can't read
+    /// them. Unencrypted tokens are rejected.
+    pub fn with_encryption(mut self, key: &[u8; 32]) -> Self {
+        self.encryption = Some(ClaimsEncryption::new(key));
+        self
+    }
+
+    /// Signs `claims`, encrypting them first if configured
+    fn seal(&self, claims: &Value) -> ApiResult<String> {
+        match &self.encryption {
+            Some(encryption) => self.key.sign(&encryption.seal(claims)?),
+            None => self.key.sign(claims),
+        }
+    }
+
+    /// Reports each request authenticated by the extractors, successful or not, i.e. to feed failed attempts into
+    /// a SIEM
+    pub fn with_event_hook(mut self, hook: impl Fn(&AuthEvent) + Send + Sync + 'static) -> Self {
+        self.event_hook = Some(Arc::new(hook));
         self
     }
 
+    fn emit(&self, event: impl FnOnce() -> AuthEvent) {
+        if let Some(hook) = &self.event_hook {
+            hook(&event());
+        }
+    }
+
+    /// Authenticates the token of `req` if present, reporting the outcome to the event hook
+    async fn authenticate_request(&self, req: &Parts) -> ApiResult<Option<(T, Value)>> {
+        let result = match self.find_token(req) {
+            Ok(Some(token)) => self.authenticate_claims(token).await.map(Some),
+            Ok(None) => return Ok(None),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(x) => {
+                self.emit(|| AuthEvent::new(req, AuthEventKind::Success, None));
+                Ok(x)
+            }
+            Err(failure) => Err(self.reject(req, failure)),
+        }
+    }
+
+    /// Reports `failure` to the event hook, returning the error to respond with
+    fn reject(&self, req: &Parts, failure: AuthFailure) -> ApiError {
+        self.emit(|| AuthEvent::failure(req, &failure));
+        self.challenge(failure)
+    }
+
+    /// [`ApiError::Unauthorized`] with a `WWW-Authenticate` challenge with our first scheme, with
+    /// `error="invalid_token"` when a token was presented (RFC 6750)
+    fn challenge(&self, failure: AuthFailure) -> ApiError {
+        let error = ApiError::from(failure);
+        let quote = |x: &str| format!("\"{}\"", x.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut params = vec![];
+        if let Some(realm) = &self.realm {
+            params.push(format!("realm={}", quote(realm)));
+        }
+        if failure.kind != AuthEventKind::Missing {
+            params.push("error=\"invalid_token\"".to_string());
+            params.push(format!("error_description={}", quote(failure.message)));
+        }
+        let scheme = self
+            .prefixes
+            .first()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .unwrap_or("Bearer");
+        let challenge = if params.is_empty() {
+            scheme.to_string()
+        } else {
+            format!("{scheme} {}", params.join(", "))
+        };
+        match HeaderValue::from_str(&challenge) {
+            Ok(challenge) => error.with_header(WWW_AUTHENTICATE, challenge),
+            Err(_) => error,
+        }
+    }
+
+    pub fn key(&self) -> &AuthKey {
+        &self.key
+    }
+
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
     pub fn sign(&self, value: &T) -> ApiResult<String> {
-        Ok(value.sign_with_key(&self.key)?)
+        self.seal(&serde_json::to_value(value)?)
+    }
+
+    /// Signs `value` with `iat` and `exp` set from the clock and `ttl`, replacing any in `value`
+    pub fn issue(&self, value: &T, ttl: Duration) -> ApiResult<String> {
+        self.issue_typed(value, ttl, None)
+    }
+
+    /// [`AuthConfig::issue`] with the configured [`AuthConfig::ttl`]
+    pub fn issue_default(&self, value: &T) -> ApiResult<String> {
+        self.issue(value, self.ttl)
+    }
+
+    /// Token for `value` carrying the real principal `actor` in the `act` claim (RFC 8693), i.e. for an admin
+    /// logging in as a user. Accepted by [`Auth`] as `value`, [`Impersonation`] exposes both.
+    pub fn issue_impersonated(&self, value: &T, actor: &T, ttl: Duration) -> ApiResult<String> {
+        let mut claims = serde_json::to_value(value)?;
+        if let Some(object) = claims.as_object_mut() {
+            object.insert(ACTOR_CLAIM.to_string(), serde_json::to_value(actor)?);
+        }
+        self.issue_claims(claims, ttl, None)
+    }
+
+    /// Long lived token for [`AuthConfig::exchange_refresh`], marked with `typ: refresh` so it isn't accepted by
+    /// [`AuthConfig::validate`]
+    pub fn issue_refresh(&self, value: &T, ttl: Duration) -> ApiResult<String> {
+        self.issue_typed(value, ttl, Some(REFRESH_TYPE))
+    }
+
+    /// Issues a new token valid for `ttl` with the claims of a valid refresh token
+    pub fn exchange_refresh(&self, refresh_token: &str, ttl: Duration) -> ApiResult<String> {
+        let claims = self.verify(refresh_token)?;
+        if claims.get("typ").and_then(Value::as_str) != Some(REFRESH_TYPE) {
+            return Err(AuthFailure::rejected("not a refresh token").into());
+        }
+        self.issue(&decode_claims(claims)?, ttl)
+    }
+
+    fn issue_typed(&self, value: &T, ttl: Duration, typ: Option<&str>) -> ApiResult<String> {
+        self.issue_claims(serde_json::to_value(value)?, ttl, typ)
+    }
+
+    fn issue_claims(
+        &self,
+        mut claims: Value,
+        ttl: Duration,
+        typ: Option<&str>,
+    ) -> ApiResult<String> {
+        let Some(object) = claims.as_object_mut() else {
+            return Err(ApiError::Other(anyhow::anyhow!(
+                "auth token claims must serialize to an object"
+            )));
+        };
+        let now = self.clock.now();
+        let ttl = chrono::Duration::from_std(ttl).map_err(|e| ApiError::Other(e.into()))?;
+        object.insert("iat".to_string(), now.timestamp().into());
+        object.insert("exp".to_string(), (now + ttl).timestamp().into());
+        match typ {
+            Some(typ) => object.insert("typ".to_string(), typ.into()),
+            None => object.remove("typ"),
+        };
+        self.seal(&claims)
     }
 
+    /// Token from the cookie if configured and sent, otherwise the `Authorization` header
+    pub fn token<'a>(&self, req: &'a Parts) -> ApiResult<Option<&'a str>> {
+        Ok(self.find_token(req)?)
+    }
+
+    fn find_token<'a>(&self, req: &'a Parts) -> Result<Option<&'a str>, AuthFailure> {
+        if let Some(token) = self.cookie.as_ref().and_then(|x| x.find(req)) {
+            return Ok(Some(token));
+        }
+        let Some(auth) = req.headers.get("Authorization") else {
+            return Ok(None);
+        };
+        let auth = auth.to_str().map_err(|_| AuthFailure::MALFORMED)?;
+        let token = self.prefixes.iter().find_map(|prefix| {
+            let scheme = auth.get(..prefix.len())?;
+            let matches = if self.prefix_case_insensitive {
+                scheme.eq_ignore_ascii_case(prefix)
+            } else {
+                scheme == prefix
+            };
+            matches.then(|| auth[prefix.len()..].trim())
+        });
+        let Some(token) = token else {
+            return Err(AuthFailure::MALFORMED);
+        };
+        Ok(Some(token))
+    }
+
+    /// Verifies the signature and registered claims
+    fn verify(&self, value: &str) -> Result<Value, AuthFailure> {
+        let jws = Jws::parse(value)?;
+        let mut claims = Err(AuthFailure::new(
+            AuthEventKind::InvalidSignature,
+            "unknown auth token key",
+        ));
+        for key in std::iter::once(&self.key).chain(&self.verification_keys) {
+            if key.id() == jws.key_id() {
+                claims = key.verify(&jws);
+                if claims.is_ok() {
+                    break;
+                }
+            }
+        }
+        let mut claims = claims?;
+        if let Some(encryption) = &self.encryption {
+            claims = encryption.open(&claims)?;
+        }
+        self.claims.validate(&claims, self.clock.now())?;
+        Ok(claims)
+    }
+
+    /// Verifies the signature, then the registered claims per [`ClaimsValidation`]. Refresh tokens are rejected.
     pub fn validate(&self, value: &str) -> ApiResult<T> {
-        let out = value
-            .verify_with_key(&self.key)
-            .map_err(|_| ApiError::Unauthorized("malformed auth token".to_string()))?;
+        Ok(decode_claims(self.validate_claims(value)?)?)
+    }
+
+    fn validate_claims(&self, value: &str) -> Result<Value, AuthFailure> {
+        let claims = self.verify(value)?;
+        if claims.get("typ").and_then(Value::as_str) == Some(REFRESH_TYPE) {
+            return Err(AuthFailure::rejected(
+                "refresh token used for authentication",
+            ));
+        }
+        Ok(claims)
+    }
+}
 
-        Ok(out)
+fn attach_principal(req: &mut Parts, claims: &Value) {
+    if let Some(principal) = Principal::from_claims(claims, AuthMethod::Token) {
+        principal.attach(req);
     }
 }
 
+fn decode_claims<T: DeserializeOwned>(claims: Value) -> Result<T, AuthFailure> {
+    serde_json::from_value(claims).map_err(|_| AuthFailure::MALFORMED)
+}
+
 #[async_trait::async_trait]
 pub trait AuthParam<T: Serialize + DeserializeOwned + FromBase64> {
     fn config() -> Arc<AuthConfig<T>>;
 
+    /// Config verifying the token of `req`, i.e. with per-tenant keys chosen by a header or by the
+    /// [`UnverifiedToken`] found with [`AuthConfig::token`]. Defaults to [`AuthParam::config`].
+    fn config_for(_req: &Parts) -> ApiResult<Arc<AuthConfig<T>>> {
+        Ok(Self::config())
+    }
+
     async fn authenticated(req: &mut Parts, arg: &T) -> ApiResult<()>;
 }
 
@@ -67,17 +513,86 @@ impl<
     type Rejection = ApiError;
 
     async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
-        let Some(auth) = req.headers.get("Authorization") else {
-            return Err(ApiError::Unauthorized("missing auth token".to_string()));
-        };
-        let config = P::config();
-        let auth = auth.to_str()?;
-        let Some(auth) = auth.strip_prefix(&config.prefix).map(|x| x.trim()) else {
-            return Err(ApiError::Unauthorized("malformed auth token".to_string()));
+        let config = P::config_for(req)?;
+        let Some((out, claims)) = config.authenticate_request(req).await? else {
+            return Err(config.reject(req, AuthFailure::MISSING));
         };
-
-        let out = P::config().validate(auth)?;
         P::authenticated(req, &out).await?;
+        attach_principal(req, &claims);
         Ok(Self(out, PhantomData))
     }
 }
+
+/// Like [`Auth`], but `None` when the request carries no token. Invalid tokens are still rejected.
+pub struct OptionalAuth<T: Serialize + DeserializeOwned + FromBase64, P: AuthParam<T>>(
+    pub Option<T>,
+    pub PhantomData<P>,
+);
+
+#[async_trait::async_trait]
+impl<
+        T: Serialize + DeserializeOwned + FromBase64 + Send + Sync,
+        P: AuthParam<T>,
+        S: Send + Sync,
+    > FromRequestParts<S> for OptionalAuth<T, P>
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let config = P::config_for(req)?;
+        let Some((out, claims)) = config.authenticate_request(req).await? else {
+            return Ok(Self(None, PhantomData));
+        };
+        P::authenticated(req, &out).await?;
+        attach_principal(req, &claims);
+        Ok(Self(Some(out), PhantomData))
+    }
+}
+
+/// Like [`Auth`], also exposing the real principal of tokens from [`AuthConfig::issue_impersonated`]
+pub struct Impersonation<T: Serialize + DeserializeOwned + FromBase64, P: AuthParam<T>> {
+    /// Principal the request acts as
+    pub effective: T,
+    /// Principal that issued the impersonation, `None` for regular tokens
+    pub real: Option<T>,
+    _p: PhantomData<P>,
+}
+
+impl<T: Serialize + DeserializeOwned + FromBase64, P: AuthParam<T>> Impersonation<T, P> {
+    pub fn is_impersonated(&self) -> bool {
+        self.real.is_some()
+    }
+
+    /// Principal accountable for the request, i.e. for audit logs
+    pub fn real_or_effective(&self) -> &T {
+        self.real.as_ref().unwrap_or(&self.effective)
+    }
+}
+
+#[async_trait::async_trait]
+impl<
+        T: Serialize + DeserializeOwned + FromBase64 + Send + Sync,
+        P: AuthParam<T>,
+        S: Send + Sync,
+    > FromRequestParts<S> for Impersonation<T, P>
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let config = P::config_for(req)?;
+        let Some((effective, mut claims)) = config.authenticate_request(req).await? else {
+            return Err(config.reject(req, AuthFailure::MISSING));
+        };
+        let real = match claims.get_mut(ACTOR_CLAIM).map(Value::take) {
+            None | Some(Value::Null) => None,
+            Some(actor) => Some(decode_claims(actor)?),
+        };
+        P::authenticated(req, &effective).await?;
+        attach_principal(req, &claims);
+        Ok(Self {
+            effective,
+            real,
+            _p: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,135 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use axum::extract::FromRequestParts;
+use http::{request::Parts, HeaderName};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    errors::{ApiError, ApiResult},
+    principal::{AuthMethod, Principal},
+    redact,
+};
+
+/// Owner of an API key and the scopes granted to it
+#[derive(Clone, Debug)]
+pub struct ApiKeyPrincipal<T> {
+    pub principal: T,
+    pub scopes: Vec<String>,
+}
+
+impl<T> ApiKeyPrincipal<T> {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|x| x == scope)
+    }
+
+    /// [`ApiError::Forbidden`] without `scope`
+    pub fn require_scope(&self, scope: &str) -> ApiResult<()> {
+        if !self.has_scope(scope) {
+            return Err(ApiError::Forbidden(format!("missing scope {scope}")));
+        }
+        Ok(())
+    }
+}
+
+/// Resolves API keys, which are only ever handled as [`hash_api_key`] hashes so the store needn't keep them
+#[async_trait::async_trait]
+pub trait ApiKeyStore<T>: Send + Sync {
+    async fn lookup(&self, hash: &str) -> ApiResult<Option<ApiKeyPrincipal<T>>>;
+}
+
+/// Hex SHA-256 of `key`, as stored by an [`ApiKeyStore`]
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+pub struct ApiKeyConfig<T> {
+    header: HeaderName,
+    query_param: Option<String>,
+    store: Arc<dyn ApiKeyStore<T>>,
+}
+
+impl<T> ApiKeyConfig<T> {
+    pub fn new(store: impl ApiKeyStore<T> + 'static) -> Self {
+        Self {
+            header: HeaderName::from_static("x-api-key"),
+            query_param: None,
+            store: Arc::new(store),
+        }
+    }
+
+    /// Header carrying the key, `X-Api-Key` by default. Registered with [`redact`] to keep the key out of logs.
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        redact::register_header(header.clone());
+        self.header = header;
+        self
+    }
+
+    /// Also accepts the key in this query parameter when the header is missing. Registered with [`redact`] to
+    /// keep the key out of logs.
+    pub fn with_query_param(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        redact::register_query_param(name.clone());
+        self.query_param = Some(name);
+        self
+    }
+
+    fn key(&self, req: &Parts) -> ApiResult<Option<String>> {
+        if let Some(key) = req.headers.get(&self.header) {
+            return Ok(Some(key.to_str()?.trim().to_string()));
+        }
+        let (Some(name), Some(query)) = (&self.query_param, req.uri.query()) else {
+            return Ok(None);
+        };
+        Ok(url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned()))
+    }
+
+    /// Principal of the key in `req`, [`ApiError::Unauthorized`] if missing or unknown
+    pub async fn authenticate(&self, req: &Parts) -> ApiResult<ApiKeyPrincipal<T>> {
+        Ok(self.authenticate_hash(req).await?.1)
+    }
+
+    /// [`ApiKeyConfig::authenticate`], along with the key's hash
+    async fn authenticate_hash(&self, req: &Parts) -> ApiResult<(String, ApiKeyPrincipal<T>)> {
+        let Some(key) = self.key(req)?.filter(|x| !x.is_empty()) else {
+            return Err(ApiError::Unauthorized("missing api key".to_string()));
+        };
+        let hash = hash_api_key(&key);
+        let principal = self
+            .store
+            .lookup(&hash)
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized("invalid api key".to_string()))?;
+        Ok((hash, principal))
+    }
+}
+
+pub trait ApiKeyParam<T> {
+    fn config() -> Arc<ApiKeyConfig<T>>;
+
+    /// Subject of the shared [`Principal`], defaults to `api-key:` and a prefix of the key's hash
+    fn subject(_principal: &ApiKeyPrincipal<T>) -> Option<String> {
+        None
+    }
+}
+
+/// Request authenticated with an API key per the [`ApiKeyConfig`] of `P`
+pub struct ApiKey<T, P: ApiKeyParam<T>>(pub ApiKeyPrincipal<T>, pub PhantomData<P>);
+
+#[async_trait::async_trait]
+impl<T: Send, P: ApiKeyParam<T>, S: Send + Sync> FromRequestParts<S> for ApiKey<T, P> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let (hash, principal) = P::config().authenticate_hash(req).await?;
+        let subject = P::subject(&principal).unwrap_or_else(|| format!("api-key:{}", &hash[..16]));
+        Principal::new(subject, AuthMethod::ApiKey)
+            .with_scopes(principal.scopes.clone())
+            .attach(req);
+        Ok(Self(principal, PhantomData))
+    }
+}
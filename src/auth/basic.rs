@@ -0,0 +1,82 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use axum::extract::FromRequestParts;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::{header::WWW_AUTHENTICATE, request::Parts, HeaderValue};
+
+use crate::errors::{ApiError, ApiResult};
+
+/// Compares without exiting early on the first difference, so the time taken doesn't reveal how much of a
+/// secret was guessed, i.e. for checking passwords in a [`BasicVerifier`]
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    ring::constant_time::verify_slices_are_equal(a, b).is_ok()
+}
+
+/// Checks Basic credentials, returning the authenticated user or `None` to reject them
+#[async_trait::async_trait]
+pub trait BasicVerifier<T>: Send + Sync {
+    async fn verify(&self, username: &str, password: &str) -> ApiResult<Option<T>>;
+}
+
+pub struct BasicConfig<T> {
+    realm: String,
+    verifier: Arc<dyn BasicVerifier<T>>,
+}
+
+impl<T> BasicConfig<T> {
+    pub fn new(realm: impl Into<String>, verifier: impl BasicVerifier<T> + 'static) -> Self {
+        Self {
+            realm: realm.into(),
+            verifier: Arc::new(verifier),
+        }
+    }
+
+    /// [`ApiError::Unauthorized`] with a `WWW-Authenticate` challenge for our realm
+    fn challenge(&self, message: &str) -> ApiError {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let error = ApiError::Unauthorized(message.to_string());
+        match HeaderValue::from_str(&format!("Basic realm=\"{realm}\", charset=\"UTF-8\"")) {
+            Ok(challenge) => error.with_header(WWW_AUTHENTICATE, challenge),
+            Err(_) => error,
+        }
+    }
+
+    /// User for the Basic credentials in `req`
+    pub async fn authenticate(&self, req: &Parts) -> ApiResult<T> {
+        let Some(auth) = req.headers.get("Authorization") else {
+            return Err(self.challenge("missing credentials"));
+        };
+        let credentials = auth
+            .to_str()
+            .ok()
+            .and_then(|x| x.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+            .and_then(|(_, x)| STANDARD.decode(x.trim()).ok())
+            .and_then(|x| String::from_utf8(x).ok());
+        let Some((username, password)) = credentials.as_deref().and_then(|x| x.split_once(':'))
+        else {
+            return Err(self.challenge("malformed credentials"));
+        };
+        self.verifier
+            .verify(username, password)
+            .await?
+            .ok_or_else(|| self.challenge("invalid credentials"))
+    }
+}
+
+pub trait BasicParam<T> {
+    fn config() -> Arc<BasicConfig<T>>;
+}
+
+/// Request authenticated with HTTP Basic credentials per the [`BasicConfig`] of `P`
+pub struct Basic<T, P: BasicParam<T>>(pub T, pub PhantomData<P>);
+
+#[async_trait::async_trait]
+impl<T: Send, P: BasicParam<T>, S: Send + Sync> FromRequestParts<S> for Basic<T, P> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let user = P::config().authenticate(req).await?;
+        Ok(Self(user, PhantomData))
+    }
+}
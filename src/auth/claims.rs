@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use super::{event::AuthFailure, AuthEventKind};
+
+/// Checks on the registered claims of tokens, on top of the signature
+#[derive(Clone, Debug)]
+pub struct ClaimsValidation {
+    /// Reject tokens without `exp`. `exp`, `nbf`, and `iat` are always checked when present.
+    pub require_exp: bool,
+    /// Tolerated difference between our clock and the issuer's
+    pub clock_skew: Duration,
+    /// Required `iss`
+    pub issuer: Option<String>,
+    /// `aud` must include one of these, if not empty
+    pub audiences: Vec<String>,
+}
+
+impl Default for ClaimsValidation {
+    fn default() -> Self {
+        Self {
+            require_exp: false,
+            clock_skew: Duration::from_secs(60),
+            issuer: None,
+            audiences: vec![],
+        }
+    }
+}
+
+/// Numeric date claim, or RFC 3339 as in PASETO, `Ok(None)` if absent
+fn timestamp(claims: &Value, name: &str) -> Result<Option<i64>, AuthFailure> {
+    match claims.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(x)) => DateTime::parse_from_rfc3339(x)
+            .map(|x| Some(x.timestamp()))
+            .map_err(|_| AuthFailure::MALFORMED),
+        Some(x) => x
+            .as_i64()
+            .or_else(|| x.as_f64().map(|x| x as i64))
+            .map(Some)
+            .ok_or(AuthFailure::MALFORMED),
+    }
+}
+
+impl ClaimsValidation {
+    pub(super) fn validate(&self, claims: &Value, now: DateTime<Utc>) -> Result<(), AuthFailure> {
+        if !claims.is_object() {
+            return Err(AuthFailure::MALFORMED);
+        }
+        let now = now.timestamp();
+        let skew = self.clock_skew.as_secs() as i64;
+        match timestamp(claims, "exp")? {
+            Some(exp) if now > exp + skew => {
+                return Err(AuthFailure::new(
+                    AuthEventKind::Expired,
+                    "expired auth token",
+                ))
+            }
+            None if self.require_exp => {
+                return Err(AuthFailure::rejected("auth token has no expiry"))
+            }
+            _ => (),
+        }
+        if matches!(timestamp(claims, "nbf")?, Some(nbf) if now + skew < nbf) {
+            return Err(AuthFailure::rejected("auth token not yet valid"));
+        }
+        if matches!(timestamp(claims, "iat")?, Some(iat) if now + skew < iat) {
+            return Err(AuthFailure::rejected("auth token issued in the future"));
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+                return Err(AuthFailure::rejected("invalid auth token issuer"));
+            }
+        }
+        if !self.audiences.is_empty() {
+            let matches = match claims.get("aud") {
+                Some(Value::String(x)) => self.audiences.contains(x),
+                Some(Value::Array(x)) => x
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|x| self.audiences.iter().any(|y| y == x)),
+                _ => false,
+            };
+            if !matches {
+                return Err(AuthFailure::rejected("invalid auth token audience"));
+            }
+        }
+        Ok(())
+    }
+}
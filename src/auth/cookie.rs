@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use http::{header::COOKIE, request::Parts, HeaderValue};
+
+use crate::errors::ApiResult;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    #[default]
+    Lax,
+    /// Requires `secure`
+    None,
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Name and attributes of a cookie carrying a signed token
+#[derive(Clone, Debug)]
+pub struct CookieConfig {
+    pub name: String,
+    pub same_site: SameSite,
+    pub secure: bool,
+    pub http_only: bool,
+    pub path: String,
+    /// `None` for a browser-session cookie
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            name: "session".to_string(),
+            same_site: SameSite::Lax,
+            secure: true,
+            http_only: true,
+            path: "/".to_string(),
+            max_age: None,
+        }
+    }
+}
+
+impl CookieConfig {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// `Set-Cookie` value storing `value` for `max_age`
+    pub fn set_cookie(&self, value: &str) -> ApiResult<HeaderValue> {
+        self.build(value, self.max_age)
+    }
+
+    /// `Set-Cookie` value removing the cookie
+    pub fn clear_cookie(&self) -> ApiResult<HeaderValue> {
+        self.build("", Some(Duration::ZERO))
+    }
+
+    fn build(&self, value: &str, max_age: Option<Duration>) -> ApiResult<HeaderValue> {
+        let mut cookie = format!(
+            "{}={}; Path={}; SameSite={}",
+            self.name,
+            value,
+            self.path,
+            self.same_site.as_str()
+        );
+        if let Some(max_age) = max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if self.secure || self.same_site == SameSite::None {
+            cookie.push_str("; Secure");
+        }
+        if self.http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        Ok(HeaderValue::from_str(&cookie)?)
+    }
+
+    /// Value of this cookie in the request, unquoted, `None` if missing or empty
+    pub(crate) fn find<'a>(&self, parts: &'a Parts) -> Option<&'a str> {
+        parts
+            .headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(';'))
+            .filter_map(|x| x.split_once('='))
+            .find(|(key, _)| key.trim() == self.name)
+            .map(|(_, value)| {
+                let value = value.trim();
+                value
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    .unwrap_or(value)
+            })
+            .filter(|x| !x.is_empty())
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde_json::{json, Value};
+
+use super::event::AuthFailure;
+use crate::errors::{ApiError, ApiResult};
+
+/// AES-256-GCM key hiding token claims from their bearer. The signed payload becomes `{"enc": ...}` with the
+/// nonce and ciphertext of the claims.
+pub(super) struct ClaimsEncryption {
+    key: LessSafeKey,
+}
+
+impl ClaimsEncryption {
+    pub(super) fn new(key: &[u8; 32]) -> Self {
+        Self {
+            key: LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).unwrap()),
+        }
+    }
+
+    pub(super) fn seal(&self, claims: &Value) -> ApiResult<Value> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| ApiError::Other(anyhow!("failed to generate nonce")))?;
+        let mut sealed = serde_json::to_vec(claims)?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| ApiError::Other(anyhow!("failed to encrypt auth token")))?;
+        sealed.splice(0..0, nonce);
+        Ok(json!({ "enc": URL_SAFE_NO_PAD.encode(sealed) }))
+    }
+
+    pub(super) fn open(&self, claims: &Value) -> Result<Value, AuthFailure> {
+        let Some(sealed) = claims.get("enc").and_then(Value::as_str) else {
+            return Err(AuthFailure::rejected("auth token not encrypted"));
+        };
+        let mut sealed = URL_SAFE_NO_PAD
+            .decode(sealed)
+            .map_err(|_| AuthFailure::MALFORMED)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(AuthFailure::MALFORMED);
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&sealed[..NONCE_LEN]);
+        let opened = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed[NONCE_LEN..],
+            )
+            .map_err(|_| AuthFailure::MALFORMED)?;
+        serde_json::from_slice(opened).map_err(|_| AuthFailure::MALFORMED)
+    }
+}
@@ -0,0 +1,76 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::extract::ConnectInfo;
+use http::{request::Parts, Method};
+
+use crate::errors::ApiError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthEventKind {
+    Success,
+    /// No token on a route requiring one
+    Missing,
+    Malformed,
+    /// Bad signature, unknown `kid`, or unexpected algorithm
+    InvalidSignature,
+    Expired,
+    Revoked,
+    /// Any other failed check, i.e. of the issuer or audience
+    Rejected,
+}
+
+/// Outcome of authenticating a request, for audit logs
+#[derive(Clone, Debug)]
+pub struct AuthEvent {
+    pub kind: AuthEventKind,
+    /// Message of the rejection
+    pub reason: Option<String>,
+    /// Set when serving with `ConnectInfo<SocketAddr>`
+    pub remote_addr: Option<SocketAddr>,
+    pub method: Method,
+    pub path: String,
+}
+
+pub type AuthEventHook = Arc<dyn Fn(&AuthEvent) + Send + Sync>;
+
+impl AuthEvent {
+    pub(super) fn new(req: &Parts, kind: AuthEventKind, reason: Option<String>) -> Self {
+        Self {
+            kind,
+            reason,
+            remote_addr: req.extensions.get::<ConnectInfo<SocketAddr>>().map(|x| x.0),
+            method: req.method.clone(),
+            path: req.uri.path().to_string(),
+        }
+    }
+
+    pub(super) fn failure(req: &Parts, failure: &AuthFailure) -> Self {
+        Self::new(req, failure.kind, Some(failure.message.to_string()))
+    }
+}
+
+/// Rejected token, becoming [`ApiError::Unauthorized`] with `message`
+#[derive(Clone, Copy, Debug)]
+pub(super) struct AuthFailure {
+    pub kind: AuthEventKind,
+    pub message: &'static str,
+}
+
+impl AuthFailure {
+    pub const MISSING: Self = Self::new(AuthEventKind::Missing, "missing auth token");
+    pub const MALFORMED: Self = Self::new(AuthEventKind::Malformed, "malformed auth token");
+
+    pub const fn new(kind: AuthEventKind, message: &'static str) -> Self {
+        Self { kind, message }
+    }
+
+    pub const fn rejected(message: &'static str) -> Self {
+        Self::new(AuthEventKind::Rejected, message)
+    }
+}
+
+impl From<AuthFailure> for ApiError {
+    fn from(failure: AuthFailure) -> Self {
+        ApiError::Unauthorized(failure.message.to_string())
+    }
+}
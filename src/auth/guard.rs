@@ -0,0 +1,38 @@
+use std::marker::PhantomData;
+
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use jwt::FromBase64;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Auth, AuthParam};
+use crate::errors::{ApiError, ApiResult};
+
+/// Authorization check on the claims of an authenticated request, typically returning
+/// [`ApiError::Forbidden`]
+pub trait Guard<T> {
+    fn check(claims: &T) -> ApiResult<()>;
+}
+
+/// [`Auth`] that also passes the guard `G`
+pub struct Require<T: Serialize + DeserializeOwned + FromBase64, P: AuthParam<T>, G: Guard<T>>(
+    pub T,
+    pub PhantomData<(P, G)>,
+);
+
+#[async_trait::async_trait]
+impl<
+        T: Serialize + DeserializeOwned + FromBase64 + Send + Sync,
+        P: AuthParam<T>,
+        G: Guard<T>,
+        S: Send + Sync,
+    > FromRequestParts<S> for Require<T, P, G>
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, state: &S) -> ApiResult<Self> {
+        let Auth(claims, _) = Auth::<T, P>::from_request_parts(req, state).await?;
+        G::check(&claims)?;
+        Ok(Self(claims, PhantomData))
+    }
+}
@@ -0,0 +1,353 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use ring::{
+    rand::SystemRandom,
+    signature::{
+        self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, UnparsedPublicKey,
+        VerificationAlgorithm,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use spki::{ObjectIdentifier, SubjectPublicKeyInfoRef};
+
+use super::{event::AuthFailure, AuthEventKind};
+use crate::{
+    errors::{ApiError, ApiResult},
+    pem::parse_pem,
+};
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+/// JWS algorithm of an [`AuthKey`]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [
+            Algorithm::Hs256,
+            Algorithm::Rs256,
+            Algorithm::Es256,
+            Algorithm::EdDsa,
+        ]
+        .into_iter()
+        .find(|x| x.name() == s)
+        .ok_or_else(|| anyhow!("unsupported algorithm {s}"))
+    }
+}
+
+impl Algorithm {
+    /// Value of the `alg` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Hs256 => "HS256",
+            Algorithm::Rs256 => "RS256",
+            Algorithm::Es256 => "ES256",
+            Algorithm::EdDsa => "EdDSA",
+        }
+    }
+}
+
+enum Signer {
+    Rsa(RsaKeyPair),
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+enum Inner {
+    Hmac(Hmac<Sha256>),
+    Asymmetric {
+        algorithm: Algorithm,
+        verification: &'static dyn VerificationAlgorithm,
+        public: Vec<u8>,
+        private: Option<Signer>,
+    },
+}
+
+/// Key signing and verifying auth tokens: a shared HMAC secret, or an RSA, P-256, or Ed25519 key. Keys built
+/// from a public key only verify.
+pub struct AuthKey {
+    id: Option<String>,
+    inner: Inner,
+}
+
+/// Compact JWS split into its parts, before verification
+pub(super) struct Jws<'a> {
+    header: Value,
+    claims: &'a str,
+    message: &'a [u8],
+    signature: Vec<u8>,
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, AuthFailure> {
+    URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|_| AuthFailure::MALFORMED)
+}
+
+impl<'a> Jws<'a> {
+    pub(super) fn parse(token: &'a str) -> Result<Self, AuthFailure> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthFailure::MALFORMED);
+        };
+        Ok(Self {
+            header: serde_json::from_slice(&decode(header)?).map_err(|_| AuthFailure::MALFORMED)?,
+            claims,
+            message: &token.as_bytes()[..token.len() - signature.len() - 1],
+            signature: decode(signature)?,
+        })
+    }
+
+    /// `kid` header, naming the key that signed the token
+    pub(super) fn key_id(&self) -> Option<&str> {
+        self.header.get("kid").and_then(Value::as_str)
+    }
+}
+
+/// Header and claims of a token before its signature is checked, only to be trusted for choosing the key to
+/// verify it with, i.e. by `kid`, `iss`, or a tenant claim
+#[derive(Clone, Debug)]
+pub struct UnverifiedToken {
+    pub header: Value,
+    pub claims: Value,
+}
+
+impl UnverifiedToken {
+    pub fn decode(token: &str) -> ApiResult<Self> {
+        let jws = Jws::parse(token)?;
+        Ok(Self {
+            claims: serde_json::from_slice(&decode(jws.claims)?)
+                .map_err(|_| AuthFailure::MALFORMED)?,
+            header: jws.header,
+        })
+    }
+}
+
+impl AuthKey {
+    pub fn hmac(secret: &[u8]) -> Self {
+        Self::new(Inner::Hmac(Hmac::new_from_slice(secret).unwrap()))
+    }
+
+    fn new(inner: Inner) -> Self {
+        Self { id: None, inner }
+    }
+
+    /// Sets the `kid` header of tokens signed with this key, and only accepts tokens with this `kid`
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// First PKCS#8 or PKCS#1 (RSA) private key in `pem`. The algorithm follows from the key type.
+    pub fn from_private_pem(pem: &str) -> Result<Self> {
+        for (label, der) in parse_pem(pem)? {
+            match label.as_str() {
+                "PRIVATE KEY" => return Self::from_pkcs8(&der),
+                "RSA PRIVATE KEY" => {
+                    let key = RsaKeyPair::from_der(&der)
+                        .map_err(|e| anyhow!("invalid RSA private key: {e}"))?;
+                    return Ok(Self::signer(Signer::Rsa(key)));
+                }
+                "EC PRIVATE KEY" => {
+                    bail!("SEC1 EC private keys are unsupported, convert to PKCS#8")
+                }
+                _ => (),
+            }
+        }
+        bail!("no private key in PEM")
+    }
+
+    /// RSA, P-256, or Ed25519 private key in PKCS#8 DER
+    pub fn from_pkcs8(der: &[u8]) -> Result<Self> {
+        if let Ok(key) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+            return Ok(Self::signer(Signer::Ed25519(key)));
+        }
+        if let Ok(key) = EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, der)
+        {
+            return Ok(Self::signer(Signer::Ecdsa(key)));
+        }
+        let key = RsaKeyPair::from_pkcs8(der).map_err(|e| {
+            anyhow!("unsupported private key, expected RSA, P-256, or Ed25519: {e}")
+        })?;
+        Ok(Self::signer(Signer::Rsa(key)))
+    }
+
+    /// First SPKI or PKCS#1 (RSA) public key in `pem`, for verifying tokens signed by its private key
+    pub fn from_public_pem(pem: &str) -> Result<Self> {
+        for (label, der) in parse_pem(pem)? {
+            match label.as_str() {
+                "PUBLIC KEY" => return Self::from_spki(&der),
+                "RSA PUBLIC KEY" => return Ok(Self::public(Algorithm::Rs256, der)),
+                _ => (),
+            }
+        }
+        bail!("no public key in PEM")
+    }
+
+    /// RSA, P-256, or Ed25519 public key in SPKI DER
+    pub fn from_spki(der: &[u8]) -> Result<Self> {
+        let spki = SubjectPublicKeyInfoRef::try_from(der).context("invalid public key")?;
+        let algorithm = match spki.algorithm.oid {
+            RSA_ENCRYPTION => Algorithm::Rs256,
+            EC_PUBLIC_KEY => {
+                let curve = spki
+                    .algorithm
+                    .parameters_oid()
+                    .context("invalid EC public key")?;
+                if curve != SECP256R1 {
+                    bail!("unsupported EC curve {curve}, expected P-256");
+                }
+                Algorithm::Es256
+            }
+            ED25519 => Algorithm::EdDsa,
+            oid => bail!("unsupported public key algorithm {oid}"),
+        };
+        let public = spki
+            .subject_public_key
+            .as_bytes()
+            .context("invalid public key")?;
+        Ok(Self::public(algorithm, public.to_vec()))
+    }
+
+    fn signer(signer: Signer) -> Self {
+        let (algorithm, public) = match &signer {
+            Signer::Rsa(key) => (Algorithm::Rs256, key.public_key().as_ref().to_vec()),
+            Signer::Ecdsa(key) => (Algorithm::Es256, key.public_key().as_ref().to_vec()),
+            Signer::Ed25519(key) => (Algorithm::EdDsa, key.public_key().as_ref().to_vec()),
+        };
+        let mut out = Self::public(algorithm, public);
+        if let Inner::Asymmetric { private, .. } = &mut out.inner {
+            *private = Some(signer);
+        }
+        out
+    }
+
+    fn public(algorithm: Algorithm, public: Vec<u8>) -> Self {
+        let verification: &'static dyn VerificationAlgorithm = match algorithm {
+            Algorithm::Rs256 | Algorithm::Hs256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            Algorithm::Es256 => &signature::ECDSA_P256_SHA256_FIXED,
+            Algorithm::EdDsa => &signature::ED25519,
+        };
+        Self::new(Inner::Asymmetric {
+            algorithm,
+            verification,
+            public,
+            private: None,
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match &self.inner {
+            Inner::Hmac(_) => Algorithm::Hs256,
+            Inner::Asymmetric { algorithm, .. } => *algorithm,
+        }
+    }
+
+    /// Compact JWS of `claims`
+    pub(super) fn sign(&self, claims: &Value) -> ApiResult<String> {
+        let mut header = json!({ "alg": self.algorithm().name(), "typ": "JWT" });
+        if let Some(id) = &self.id {
+            header["kid"] = id.as_str().into();
+        }
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?)
+        );
+        let signature = self.sign_bytes(message.as_bytes())?;
+        Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+    }
+
+    /// Raw signature of `message` with the algorithm of this key
+    pub(super) fn sign_bytes(&self, message: &[u8]) -> ApiResult<Vec<u8>> {
+        match &self.inner {
+            Inner::Hmac(key) => {
+                let mut mac = key.clone();
+                mac.update(message);
+                Ok(mac.finalize().into_bytes().to_vec())
+            }
+            Inner::Asymmetric { private: None, .. } => Err(ApiError::Other(anyhow!(
+                "auth key has no private key to sign with"
+            ))),
+            Inner::Asymmetric {
+                private: Some(signer),
+                ..
+            } => sign_asymmetric(signer, message),
+        }
+    }
+
+    pub(super) fn verify_bytes(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.inner {
+            Inner::Hmac(key) => {
+                let mut mac = key.clone();
+                mac.update(message);
+                mac.verify_slice(signature).is_ok()
+            }
+            Inner::Asymmetric {
+                verification,
+                public,
+                ..
+            } => UnparsedPublicKey::new(*verification, public)
+                .verify(message, signature)
+                .is_ok(),
+        }
+    }
+
+    /// Claims of a compact JWS signed with this key and its algorithm
+    pub(super) fn verify(&self, jws: &Jws) -> Result<Value, AuthFailure> {
+        if jws.header.get("alg").and_then(Value::as_str) != Some(self.algorithm().name()) {
+            return Err(AuthFailure::new(
+                AuthEventKind::InvalidSignature,
+                "unexpected auth token algorithm",
+            ));
+        }
+        if !self.verify_bytes(jws.message, &jws.signature) {
+            return Err(AuthFailure::new(
+                AuthEventKind::InvalidSignature,
+                "invalid auth token signature",
+            ));
+        }
+        serde_json::from_slice(&decode(jws.claims)?).map_err(|_| AuthFailure::MALFORMED)
+    }
+}
+
+fn sign_asymmetric(signer: &Signer, message: &[u8]) -> ApiResult<Vec<u8>> {
+    let failed = |_| ApiError::Other(anyhow!("failed to sign auth token"));
+    let rng = SystemRandom::new();
+    Ok(match signer {
+        Signer::Rsa(key) => {
+            let mut signature = vec![0; key.public_modulus_len()];
+            key.sign(&signature::RSA_PKCS1_SHA256, &rng, message, &mut signature)
+                .map_err(failed)?;
+            signature
+        }
+        Signer::Ecdsa(key) => key.sign(&rng, message).map_err(failed)?.as_ref().to_vec(),
+        Signer::Ed25519(key) => key.sign(message).as_ref().to_vec(),
+    })
+}
@@ -0,0 +1,115 @@
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::BoxBody,
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+};
+use futures::Future;
+use http::Request;
+use jwt::FromBase64;
+use serde::{de::DeserializeOwned, Serialize};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::{Auth, AuthParam};
+
+/// Authenticates every request like [`Auth`], inserting the claims `T` as a request extension for handlers to
+/// read with [`axum::Extension`]. Unauthenticated requests are rejected before reaching the inner service.
+/// Tokens due for [`super::Renewal`] are replaced on the response.
+pub struct AuthLayer<T, P>(PhantomData<fn() -> (T, P)>);
+
+impl<T, P> AuthLayer<T, P> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T, P> Default for AuthLayer<T, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P> Clone for AuthLayer<T, P> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<S, T, P> Layer<S> for AuthLayer<T, P> {
+    type Service = AuthService<S, T, P>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AuthService {
+            inner: service,
+            _t: PhantomData,
+        }
+    }
+}
+
+pub struct AuthService<S, T, P> {
+    inner: S,
+    _t: PhantomData<fn() -> (T, P)>,
+}
+
+impl<S: Clone, T, P> Clone for AuthService<S, T, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<S, T, P, ReqBody> Service<Request<ReqBody>> for AuthService<S, T, P>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    T: Serialize + DeserializeOwned + FromBase64 + Send + Sync + 'static,
+    P: AuthParam<T> + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // the ready service must handle this request, leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let claims = match Auth::<T, P>::from_request_parts(&mut parts, &()).await {
+                Ok(Auth(claims, _)) => claims,
+                Err(e) => return Ok(e.into_response()),
+            };
+            let renewed = P::config_for(&parts).and_then(|config| match config.token(&parts)? {
+                Some(token) => config.renew(token, &claims),
+                None => Ok(None),
+            });
+            let renewed = match renewed {
+                Ok(renewed) => renewed,
+                Err(e) => return Ok(e.into_response()),
+            };
+            parts.extensions.insert(claims);
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some((name, value)) = renewed {
+                response.headers_mut().append(name, value);
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,458 @@
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use anyhow::bail;
+use axum::extract::FromRequestParts;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use blake2::{
+    digest::{
+        consts::{U32, U56, U64},
+        generic_array::{ArrayLength, GenericArray},
+        typenum::{IsLessOrEqual, LeEq, NonZero},
+        Mac,
+    },
+    Blake2bMac,
+};
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    XChaCha20,
+};
+use chrono::SecondsFormat;
+use http::request::Parts;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use super::{constant_time_eq, Algorithm, AuthKey, ClaimsValidation};
+use crate::{
+    clock::{self, SharedClock},
+    errors::{ApiError, ApiResult},
+};
+
+const V4_PUBLIC: &str = "v4.public.";
+const V4_LOCAL: &str = "v4.local.";
+const SIGNATURE_LEN: usize = 64;
+const NONCE_LEN: usize = 32;
+const TAG_LEN: usize = 32;
+
+/// Pre-authentication encoding, binding each piece with its length
+fn pae(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut out = (pieces.len() as u64).to_le_bytes().to_vec();
+    for piece in pieces {
+        out.extend_from_slice(&(piece.len() as u64).to_le_bytes());
+        out.extend_from_slice(piece);
+    }
+    out
+}
+
+fn malformed() -> ApiError {
+    ApiError::Unauthorized("malformed auth token".to_string())
+}
+
+/// Keyed BLAKE2b of `pieces` with an output of `N` bytes
+fn blake2b<N>(key: &[u8], pieces: &[&[u8]]) -> GenericArray<u8, N>
+where
+    N: ArrayLength<u8> + IsLessOrEqual<U64>,
+    LeEq<N, U64>: NonZero,
+{
+    let mut mac = Blake2bMac::<N>::new_from_slice(key).expect("BLAKE2b key longer than 64 bytes");
+    for piece in pieces {
+        mac.update(piece);
+    }
+    mac.finalize().into_bytes()
+}
+
+/// Encryption key and XChaCha20 nonce, and authentication key, derived for one token nonce
+fn local_keys(key: &[u8; 32], nonce: &[u8]) -> ([u8; 32], [u8; 24], [u8; 32]) {
+    let derived = blake2b::<U56>(key, &[b"paseto-encryption-key", nonce]);
+    let auth_key = blake2b::<U32>(key, &[b"paseto-auth-key-for-aead", nonce]);
+    (
+        derived[..32].try_into().unwrap(),
+        derived[32..].try_into().unwrap(),
+        auth_key.into(),
+    )
+}
+
+enum PasetoKey {
+    /// Ed25519 key of `v4.public` tokens
+    Public(Box<AuthKey>),
+    /// Symmetric key of `v4.local` tokens
+    Local([u8; 32]),
+}
+
+/// Issues and verifies PASETO v4 tokens as an alternative to [`super::AuthConfig`]: `v4.public`, signed with
+/// Ed25519, or `v4.local`, encrypted with XChaCha20 and authenticated with BLAKE2b
+pub struct PasetoAuthConfig<T: Serialize + DeserializeOwned> {
+    key: PasetoKey,
+    prefix: String,
+    footer: Vec<u8>,
+    implicit_assertion: Vec<u8>,
+    clock: SharedClock,
+    claims: ClaimsValidation,
+    _t: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> PasetoAuthConfig<T> {
+    /// `v4.public` tokens. `key` must be an Ed25519 [`AuthKey`], with its private key to issue tokens.
+    pub fn new(key: AuthKey) -> anyhow::Result<Self> {
+        if key.algorithm() != Algorithm::EdDsa {
+            bail!("PASETO v4.public requires an Ed25519 key");
+        }
+        Ok(Self::with_key(PasetoKey::Public(Box::new(key))))
+    }
+
+    /// `v4.local` tokens, encrypted with a 32 byte secret `key`
+    pub fn local(key: &[u8]) -> anyhow::Result<Self> {
+        let Ok(key) = key.try_into() else {
+            bail!("PASETO v4.local requires a 32 byte key");
+        };
+        Ok(Self::with_key(PasetoKey::Local(key)))
+    }
+
+    fn with_key(key: PasetoKey) -> Self {
+        Self {
+            key,
+            prefix: "Token ".to_string(),
+            footer: vec![],
+            implicit_assertion: vec![],
+            clock: clock::system(),
+            claims: ClaimsValidation::default(),
+            _t: PhantomData,
+        }
+    }
+
+    pub fn with_prefix(mut self, mut prefix: String) -> Self {
+        if !prefix.is_empty() {
+            prefix.push(' ');
+        }
+        self.prefix = prefix;
+        self
+    }
+
+    /// Unencrypted footer appended to issued tokens and required on verified ones, i.e. a key ID
+    pub fn with_footer(mut self, footer: impl Into<Vec<u8>>) -> Self {
+        self.footer = footer.into();
+        self
+    }
+
+    /// Context bound into the signature or tag without being part of the token
+    pub fn with_implicit_assertion(mut self, assertion: impl Into<Vec<u8>>) -> Self {
+        self.implicit_assertion = assertion.into();
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_claims_validation(mut self, claims: ClaimsValidation) -> Self {
+        self.claims = claims;
+        self
+    }
+
+    fn header(&self) -> &'static str {
+        match &self.key {
+            PasetoKey::Public(_) => V4_PUBLIC,
+            PasetoKey::Local(_) => V4_LOCAL,
+        }
+    }
+
+    pub fn sign(&self, value: &T) -> ApiResult<String> {
+        self.sign_claims(&serde_json::to_value(value)?)
+    }
+
+    fn sign_claims(&self, claims: &Value) -> ApiResult<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        self.encode(&serde_json::to_vec(claims)?, &nonce)
+    }
+
+    /// Token of `message`, with `nonce` used by `v4.local` only
+    fn encode(&self, message: &[u8], nonce: &[u8; NONCE_LEN]) -> ApiResult<String> {
+        let header = self.header();
+        let payload = match &self.key {
+            PasetoKey::Public(key) => {
+                let signature = key.sign_bytes(&pae(&[
+                    header.as_bytes(),
+                    message,
+                    &self.footer,
+                    &self.implicit_assertion,
+                ]))?;
+                [message, &signature].concat()
+            }
+            PasetoKey::Local(key) => {
+                let (encryption_key, encryption_nonce, auth_key) = local_keys(key, nonce);
+                let mut ciphertext = message.to_vec();
+                XChaCha20::new(&encryption_key.into(), &encryption_nonce.into())
+                    .apply_keystream(&mut ciphertext);
+                let tag = blake2b::<U32>(
+                    &auth_key,
+                    &[&pae(&[
+                        header.as_bytes(),
+                        nonce,
+                        &ciphertext,
+                        &self.footer,
+                        &self.implicit_assertion,
+                    ])],
+                );
+                [nonce, &ciphertext[..], &tag].concat()
+            }
+        };
+        let mut token = format!("{header}{}", URL_SAFE_NO_PAD.encode(payload));
+        if !self.footer.is_empty() {
+            token.push('.');
+            token.push_str(&URL_SAFE_NO_PAD.encode(&self.footer));
+        }
+        Ok(token)
+    }
+
+    /// Signs `value` with `iat` and `exp` set from the clock and `ttl` as RFC 3339, replacing any in `value`
+    pub fn issue(&self, value: &T, ttl: Duration) -> ApiResult<String> {
+        let mut claims = serde_json::to_value(value)?;
+        let Some(object) = claims.as_object_mut() else {
+            return Err(ApiError::Other(anyhow::anyhow!(
+                "auth token claims must serialize to an object"
+            )));
+        };
+        let now = self.clock.now();
+        let ttl = chrono::Duration::from_std(ttl).map_err(|e| ApiError::Other(e.into()))?;
+        let format =
+            |x: chrono::DateTime<chrono::Utc>| x.to_rfc3339_opts(SecondsFormat::Secs, true);
+        object.insert("iat".to_string(), format(now).into());
+        object.insert("exp".to_string(), format(now + ttl).into());
+        self.sign_claims(&claims)
+    }
+
+    /// Verifies the signature or tag and the footer, then the registered claims per [`ClaimsValidation`]
+    pub fn validate(&self, token: &str) -> ApiResult<T> {
+        let claims = self.decode(token)?;
+        self.claims.validate(&claims, self.clock.now())?;
+        serde_json::from_value(claims).map_err(|_| malformed())
+    }
+
+    /// Verified claims of `token`, before validating them
+    fn decode(&self, token: &str) -> ApiResult<Value> {
+        let header = self.header();
+        let Some(body) = token.strip_prefix(header) else {
+            return Err(malformed());
+        };
+        let (payload, footer) = match body.split_once('.') {
+            Some((payload, footer)) => (
+                payload,
+                URL_SAFE_NO_PAD.decode(footer).map_err(|_| malformed())?,
+            ),
+            None => (body, vec![]),
+        };
+        if footer != self.footer {
+            return Err(ApiError::Unauthorized(
+                "unexpected auth token footer".to_string(),
+            ));
+        }
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| malformed())?;
+        let message = match &self.key {
+            PasetoKey::Public(key) => {
+                if payload.len() < SIGNATURE_LEN {
+                    return Err(malformed());
+                }
+                let (message, signature) = payload.split_at(payload.len() - SIGNATURE_LEN);
+                let signed = pae(&[
+                    header.as_bytes(),
+                    message,
+                    &footer,
+                    &self.implicit_assertion,
+                ]);
+                if !key.verify_bytes(&signed, signature) {
+                    return Err(ApiError::Unauthorized(
+                        "invalid auth token signature".to_string(),
+                    ));
+                }
+                message.to_vec()
+            }
+            PasetoKey::Local(key) => {
+                if payload.len() < NONCE_LEN + TAG_LEN {
+                    return Err(malformed());
+                }
+                let (nonce, rest) = payload.split_at(NONCE_LEN);
+                let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+                let (encryption_key, encryption_nonce, auth_key) = local_keys(key, nonce);
+                let expected = blake2b::<U32>(
+                    &auth_key,
+                    &[&pae(&[
+                        header.as_bytes(),
+                        nonce,
+                        ciphertext,
+                        &footer,
+                        &self.implicit_assertion,
+                    ])],
+                );
+                if !constant_time_eq(&expected, tag) {
+                    return Err(ApiError::Unauthorized(
+                        "invalid auth token signature".to_string(),
+                    ));
+                }
+                let mut message = ciphertext.to_vec();
+                XChaCha20::new(&encryption_key.into(), &encryption_nonce.into())
+                    .apply_keystream(&mut message);
+                message
+            }
+        };
+        serde_json::from_slice(&message).map_err(|_| malformed())
+    }
+}
+
+/// [`super::AuthParam`] for [`PasetoAuth`]
+#[async_trait::async_trait]
+pub trait PasetoParam<T: Serialize + DeserializeOwned> {
+    fn config() -> Arc<PasetoAuthConfig<T>>;
+
+    async fn authenticated(req: &mut Parts, arg: &T) -> ApiResult<()>;
+}
+
+/// Like [`super::Auth`], for PASETO tokens
+pub struct PasetoAuth<T: Serialize + DeserializeOwned, P: PasetoParam<T>>(
+    pub T,
+    pub PhantomData<P>,
+);
+
+#[async_trait::async_trait]
+impl<T: Serialize + DeserializeOwned + Send + Sync, P: PasetoParam<T>, S: Send + Sync>
+    FromRequestParts<S> for PasetoAuth<T, P>
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let Some(auth) = req.headers.get("Authorization") else {
+            return Err(ApiError::Unauthorized("missing auth token".to_string()));
+        };
+        let config = P::config();
+        let Some(token) = auth
+            .to_str()?
+            .strip_prefix(&config.prefix)
+            .map(|x| x.trim())
+        else {
+            return Err(ApiError::Unauthorized("malformed auth token".to_string()));
+        };
+        let out = config.validate(token)?;
+        P::authenticated(req, &out).await?;
+        Ok(Self(out, PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // official PASETO v4 test vectors, https://github.com/paseto-standard/test-vectors
+    const LOCAL_KEY: &str = "707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f";
+    const PUBLIC_SEED: &str = "b4cbfb43df4ce210727d953e4a713307fa19bb7d9f85041438d9e11b942a3774";
+    const FOOTER: &str = r#"{"kid":"zVhMiPBP9fRf2snEcT7gFTioeA9COcNy9DfgL1W60haN"}"#;
+    const SECRET: &str = r#"{"data":"this is a secret message","exp":"2022-01-01T00:00:00+00:00"}"#;
+    const HIDDEN: &str = r#"{"data":"this is a hidden message","exp":"2022-01-01T00:00:00+00:00"}"#;
+    const SIGNED: &str = r#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+
+    fn hex(x: &str) -> Vec<u8> {
+        (0..x.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&x[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn local() -> PasetoAuthConfig<Value> {
+        PasetoAuthConfig::local(&hex(LOCAL_KEY)).unwrap()
+    }
+
+    fn public() -> PasetoAuthConfig<Value> {
+        // PKCS#8 v1 wrapping of the Ed25519 seed
+        let der = [hex("302e020100300506032b657004220420"), hex(PUBLIC_SEED)].concat();
+        PasetoAuthConfig::new(AuthKey::from_pkcs8(&der).unwrap()).unwrap()
+    }
+
+    fn check(config: &PasetoAuthConfig<Value>, nonce: &str, payload: &str, token: &str) {
+        let nonce = hex(nonce).try_into().unwrap();
+        assert_eq!(config.encode(payload.as_bytes(), &nonce).unwrap(), token);
+        let expected: Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(config.decode(token).unwrap(), expected);
+    }
+
+    const ZERO_NONCE: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+    #[test]
+    fn local_vectors() {
+        // 4-E-1
+        check(
+            &local(),
+            ZERO_NONCE,
+            SECRET,
+            "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_ZgesdkUMvSwscFlAl1pk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8XJ5hOb_4v9RmDkneN0S92dx0OW4pgy7omxgf3S8c3LlQg",
+        );
+        // 4-E-2
+        check(
+            &local(),
+            ZERO_NONCE,
+            HIDDEN,
+            "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_ZgesdkUMvS2csCgglvpk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8XIemu9chy3WVKvRBfg6t8wwYHK0ArLxxfZP73W_vfwt5A",
+        );
+    }
+
+    #[test]
+    fn public_vectors() {
+        // 4-S-1
+        check(
+            &public(),
+            ZERO_NONCE,
+            SIGNED,
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9bg_XBBzds8lTZShVlwwKSgeKpLT3yukTw6JUz3W4h_ExsQV-P0V54zemZDcAxFaSeef1QlXEFtkqxT1ciiQEDA",
+        );
+        // 4-S-2
+        check(
+            &public().with_footer(FOOTER),
+            ZERO_NONCE,
+            SIGNED,
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9v3Jt8mx_TdM2ceTGoqwrh4yDFn0XsHvvV_D0DtwQxVrJEBMl0F2caAdgnpKlt4p7xBnx1HcO-SPo8FPp214HDw.eyJraWQiOiJ6VmhNaVBCUDlmUmYyc25FY1Q3Z0ZUaW9lQTlDT2NOeTlEZmdMMVc2MGhhTiJ9",
+        );
+        // 4-S-3
+        check(
+            &public()
+                .with_footer(FOOTER)
+                .with_implicit_assertion(r#"{"test-vector":"4-S-3"}"#),
+            ZERO_NONCE,
+            SIGNED,
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9NPWciuD3d0o5eXJXG5pJy-DiVEoyPYWs1YSTwWHNJq6DZD3je5gf-0M4JR9ipdUSJbIovzmBECeaWmaqcaP0DQ.eyJraWQiOiJ6VmhNaVBCUDlmUmYyc25FY1Q3Z0ZUaW9lQTlDT2NOeTlEZmdMMVc2MGhhTiJ9",
+        );
+    }
+
+    #[test]
+    fn issue_sets_times_outside_of_claims_type() {
+        #[derive(Serialize, serde::Deserialize)]
+        struct Claims {
+            sub: String,
+        }
+
+        let config = PasetoAuthConfig::<Claims>::local(&hex(LOCAL_KEY)).unwrap();
+        let token = config
+            .issue(
+                &Claims {
+                    sub: "a".to_string(),
+                },
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        let claims = config.decode(&token).unwrap();
+        assert!(claims["iat"].is_string());
+        assert!(claims["exp"].is_string());
+        assert_eq!(config.validate(&token).unwrap().sub, "a");
+    }
+
+    #[test]
+    fn rejects_tampered_local() {
+        let config = local();
+        let token = config.sign(&serde_json::json!({ "sub": "a" })).unwrap();
+        let mut tampered = token.into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        assert!(config
+            .decode(std::str::from_utf8(&tampered).unwrap())
+            .is_err());
+        assert!(public().decode("v4.local.AAAA").is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use http::HeaderName;
+use serde_json::Value;
+
+/// Sliding expiration for [`super::AuthLayer`]: valid tokens close to expiry are replaced by a fresh token on the
+/// response, in the auth cookie if configured or otherwise in `header`
+#[derive(Clone, Debug)]
+pub struct Renewal {
+    /// Renew tokens expiring within this long
+    pub window: Duration,
+    /// Lifetime of renewed tokens
+    pub ttl: Duration,
+    pub header: HeaderName,
+}
+
+impl Renewal {
+    pub fn new(window: Duration, ttl: Duration) -> Self {
+        Self {
+            window,
+            ttl,
+            header: HeaderName::from_static("x-renewed-token"),
+        }
+    }
+
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Whether verified `claims` expire within the window. Tokens without a numeric `exp` aren't renewed.
+    pub(super) fn due(&self, claims: &Value, now: DateTime<Utc>) -> bool {
+        let Some(exp) = claims.get("exp").and_then(Value::as_i64) else {
+            return false;
+        };
+        exp - now.timestamp() <= self.window.as_secs() as i64
+    }
+}
@@ -0,0 +1,6 @@
+/// Deny-list consulted by the auth extractors after a token is verified, e.g. by its `jti` or session id, so
+/// stateless tokens can still be revoked on logout or compromise
+#[async_trait::async_trait]
+pub trait RevocationCheck<T>: Send + Sync {
+    async fn is_revoked(&self, claims: &T) -> bool;
+}
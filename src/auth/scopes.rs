@@ -0,0 +1,79 @@
+use std::marker::PhantomData;
+
+use axum::{response::IntoResponse, Json};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Guard;
+use crate::errors::{ApiError, ApiResult};
+
+/// Claims granting scopes, conventionally a space separated `scope` claim or a `permissions` array, see
+/// [`scopes_of`]
+pub trait HasScopes {
+    fn scopes(&self) -> Vec<String>;
+}
+
+impl HasScopes for Value {
+    fn scopes(&self) -> Vec<String> {
+        scopes_of(self)
+    }
+}
+
+/// Scopes in the `scope` (space separated) and `permissions` (array) claims
+pub fn scopes_of(claims: &Value) -> Vec<String> {
+    let scope = claims
+        .get("scope")
+        .and_then(Value::as_str)
+        .into_iter()
+        .flat_map(str::split_whitespace);
+    let permissions = claims
+        .get("permissions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str);
+    scope.chain(permissions).map(str::to_string).collect()
+}
+
+/// Body of the 403 from [`check_scopes`]
+#[derive(Serialize, Deserialize)]
+pub struct MissingScopesBody {
+    pub message: String,
+    pub missing_scopes: Vec<String>,
+}
+
+/// 403 listing the scopes of `required` that `claims` lacks
+pub fn check_scopes(claims: &impl HasScopes, required: &[&str]) -> ApiResult<()> {
+    let granted = claims.scopes();
+    let missing: Vec<String> = required
+        .iter()
+        .filter(|x| !granted.iter().any(|y| y == *x))
+        .map(|x| x.to_string())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let body = MissingScopesBody {
+        message: "missing scopes".to_string(),
+        missing_scopes: missing,
+    };
+    Err(ApiError::Response(Box::new(
+        (StatusCode::FORBIDDEN, Json(body)).into_response(),
+    )))
+}
+
+/// Scopes required by [`RequireScopes`], i.e. `struct OrdersWrite; impl ScopeSet for OrdersWrite { const SCOPES:
+/// &'static [&'static str] = &["orders:write"]; }`
+pub trait ScopeSet {
+    const SCOPES: &'static [&'static str];
+}
+
+/// [`Guard`] requiring every scope of `S`
+pub struct RequireScopes<S: ScopeSet>(PhantomData<S>);
+
+impl<T: HasScopes, S: ScopeSet> Guard<T> for RequireScopes<S> {
+    fn check(claims: &T) -> ApiResult<()> {
+        check_scopes(claims, S::SCOPES)
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt, path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use jwt::FromBase64;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Algorithm, AuthConfig, AuthKey};
+use crate::redact::REDACTED;
+
+/// Where the signing key of [`AuthSettings`] comes from, read on each [`AuthSettings::build`]. HS256 keys are
+/// the shared secret, other algorithms a PEM private key.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    Value(String),
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Value(_) => f.debug_tuple("Value").field(&REDACTED).finish(),
+            KeySource::File(path) => f.debug_tuple("File").field(path).finish(),
+            KeySource::Env(name) => f.debug_tuple("Env").field(name).finish(),
+        }
+    }
+}
+
+impl KeySource {
+    pub fn resolve(&self) -> Result<Vec<u8>> {
+        match self {
+            KeySource::Value(value) => Ok(value.as_bytes().to_vec()),
+            KeySource::File(path) => {
+                std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+            }
+            KeySource::Env(name) => std::env::var(name)
+                .map(String::into_bytes)
+                .with_context(|| format!("auth key environment variable {name} not set")),
+        }
+    }
+}
+
+/// Declarative [`AuthConfig`], i.e. a section of a service's config file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthSettings {
+    pub key: KeySource,
+    #[serde(default = "default_algorithm")]
+    pub algorithm: Algorithm,
+    /// `kid` of issued tokens
+    #[serde(default)]
+    pub key_id: Option<String>,
+    /// Scheme of the `Authorization` header
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Lifetime of tokens from [`AuthConfig::issue_default`] and [`AuthConfig::issue_cookie_default`]
+    #[serde(default = "default_ttl")]
+    pub ttl: Duration,
+}
+
+fn default_algorithm() -> Algorithm {
+    Algorithm::Hs256
+}
+
+fn default_prefix() -> String {
+    "Token".to_string()
+}
+
+fn default_ttl() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+impl AuthSettings {
+    /// Settings from `{prefix}_KEY` or `{prefix}_KEY_FILE`, and optionally `{prefix}_ALGORITHM`,
+    /// `{prefix}_KEY_ID`, `{prefix}_PREFIX`, and `{prefix}_TTL` in seconds
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let var = |name: &str| std::env::var(format!("{prefix}_{name}")).ok();
+        let key = match var("KEY_FILE") {
+            Some(path) => KeySource::File(path.into()),
+            None if var("KEY").is_some() => KeySource::Env(format!("{prefix}_KEY")),
+            None => bail!("neither {prefix}_KEY nor {prefix}_KEY_FILE is set"),
+        };
+        let algorithm = match var("ALGORITHM") {
+            Some(algorithm) => algorithm.parse()?,
+            None => default_algorithm(),
+        };
+        let ttl = match var("TTL") {
+            Some(ttl) => Duration::from_secs(
+                ttl.parse()
+                    .with_context(|| format!("invalid {prefix}_TTL {ttl}"))?,
+            ),
+            None => default_ttl(),
+        };
+        Ok(Self {
+            key,
+            algorithm,
+            key_id: var("KEY_ID"),
+            prefix: var("PREFIX").unwrap_or_else(default_prefix),
+            ttl,
+        })
+    }
+
+    pub fn build<T: Serialize + DeserializeOwned + FromBase64>(&self) -> Result<AuthConfig<T>> {
+        let material = self.key.resolve()?;
+        let mut key = match self.algorithm {
+            Algorithm::Hs256 => AuthKey::hmac(&material),
+            algorithm => {
+                let pem = String::from_utf8(material).context("auth key is not a PEM file")?;
+                let key = AuthKey::from_private_pem(&pem)?;
+                if key.algorithm() != algorithm {
+                    bail!(
+                        "auth key is {}, expected {}",
+                        key.algorithm().name(),
+                        algorithm.name()
+                    );
+                }
+                key
+            }
+        };
+        if let Some(id) = &self.key_id {
+            key = key.with_id(id.clone());
+        }
+        Ok(AuthConfig::from_key(key)
+            .with_prefix(self.prefix.clone())
+            .with_ttl(self.ttl))
+    }
+}
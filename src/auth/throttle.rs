@@ -0,0 +1,232 @@
+use std::{collections::HashMap, future::Future, net::IpAddr, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    clock::{self, SharedClock},
+    errors::{ApiError, ApiResult},
+};
+
+/// Recent failed logins for one key of a [`LoginThrottle`]
+#[derive(Clone, Debug, Default)]
+pub struct ThrottleState {
+    pub failures: u32,
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+/// Persistence for [`LoginThrottle`], shared between instances to throttle across a deployment. Records may be
+/// dropped once their TTL passes.
+#[async_trait::async_trait]
+pub trait ThrottleStore: Send + Sync + 'static {
+    async fn get(&self, key: &str) -> Result<Option<ThrottleState>>;
+
+    /// Counts a failure at `now` and keeps the record for `ttl`, returning the state before it. Must be atomic so
+    /// concurrent attempts each see the failures before them, i.e. `HINCRBY`, `HSET`, and `EXPIRE` in a script
+    /// for Redis.
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<ThrottleState>;
+
+    async fn remove(&self, key: &str) -> Result<()>;
+}
+
+/// Throttle state in process memory
+pub struct MemoryThrottleStore {
+    clock: SharedClock,
+    states: Mutex<HashMap<String, (ThrottleState, DateTime<Utc>)>>,
+}
+
+impl Default for MemoryThrottleStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryThrottleStore {
+    pub fn new() -> Self {
+        Self {
+            clock: clock::system(),
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl ThrottleStore for MemoryThrottleStore {
+    async fn get(&self, key: &str) -> Result<Option<ThrottleState>> {
+        let now = self.clock.now();
+        Ok(self
+            .states
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(state, _)| state.clone()))
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<ThrottleState> {
+        let expires = now + chrono::Duration::from_std(ttl)?;
+        let mut states = self.states.lock().unwrap();
+        states.retain(|_, (_, expires)| *expires > now);
+        let previous = states
+            .get(key)
+            .map(|(state, _)| state.clone())
+            .unwrap_or_default();
+        let state = ThrottleState {
+            failures: previous.failures.saturating_add(1),
+            last_failure: Some(now),
+        };
+        states.insert(key.to_string(), (state, expires));
+        Ok(previous)
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.states.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Brute-force protection for login endpoints, keyed by client IP and username. After `free_attempts` failures
+/// each further failure locks the key for exponentially longer, from `base_delay` up to `max_delay`.
+pub struct LoginThrottle {
+    store: Box<dyn ThrottleStore>,
+    clock: SharedClock,
+    free_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    reset_after: Duration,
+}
+
+impl LoginThrottle {
+    pub fn new(store: impl ThrottleStore) -> Self {
+        Self {
+            store: Box::new(store),
+            clock: clock::system(),
+            free_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(15 * 60),
+            reset_after: Duration::from_secs(60 * 60),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Failures allowed before the first lockout, 5 by default
+    pub fn with_free_attempts(mut self, free_attempts: u32) -> Self {
+        self.free_attempts = free_attempts;
+        self
+    }
+
+    /// Lockout after the first throttled failure, doubling with each further one up to `max_delay`
+    pub fn with_delays(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Failures are forgotten this long after the lockout of the last one could end, an hour by default
+    pub fn with_reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = reset_after;
+        self
+    }
+
+    fn key(ip: IpAddr, username: &str) -> String {
+        format!("{ip}|{}", username.to_lowercase())
+    }
+
+    /// End of the lockout after the failures in `state`, if any
+    pub fn locked_until(&self, state: &ThrottleState) -> Option<DateTime<Utc>> {
+        let excess = state
+            .failures
+            .checked_sub(self.free_attempts.saturating_add(1))?;
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(excess))
+            .min(self.max_delay);
+        state
+            .last_failure?
+            .checked_add_signed(chrono::Duration::from_std(delay).ok()?)
+    }
+
+    /// [`ApiError::TooManyRequests`] while `state` is locked out
+    fn enforce(&self, state: &ThrottleState) -> ApiResult<()> {
+        let Some(locked_until) = self.locked_until(state) else {
+            return Ok(());
+        };
+        match (locked_until - self.clock.now()).to_std() {
+            Ok(retry_after) if !retry_after.is_zero() => Err(ApiError::TooManyRequests {
+                retry_after: Some(retry_after),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// [`ApiError::TooManyRequests`] while the key is locked out
+    pub async fn check(&self, ip: IpAddr, username: &str) -> ApiResult<()> {
+        let state = self
+            .store
+            .get(&Self::key(ip, username))
+            .await
+            .map_err(ApiError::Other)?;
+        self.enforce(&state.unwrap_or_default())
+    }
+
+    /// Counts a failed attempt, returning the state before it
+    async fn reserve(&self, ip: IpAddr, username: &str) -> ApiResult<ThrottleState> {
+        self.store
+            .record_failure(
+                &Self::key(ip, username),
+                self.clock.now(),
+                self.max_delay.saturating_add(self.reset_after),
+            )
+            .await
+            .map_err(ApiError::Other)
+    }
+
+    pub async fn record_failure(&self, ip: IpAddr, username: &str) -> ApiResult<()> {
+        self.reserve(ip, username).await?;
+        Ok(())
+    }
+
+    pub async fn record_success(&self, ip: IpAddr, username: &str) -> ApiResult<()> {
+        self.store
+            .remove(&Self::key(ip, username))
+            .await
+            .map_err(ApiError::Other)
+    }
+
+    /// Runs `login` unless locked out. The attempt is counted as a failure before `login` runs, so concurrent
+    /// attempts can't get past the lockout, and forgotten again if it succeeds.
+    pub async fn guard<T>(
+        &self,
+        ip: IpAddr,
+        username: &str,
+        login: impl Future<Output = ApiResult<T>>,
+    ) -> ApiResult<T> {
+        self.check(ip, username).await?;
+        let previous = self.reserve(ip, username).await?;
+        self.enforce(&previous)?;
+        let result = login.await;
+        if result.is_ok() {
+            self.record_success(ip, username).await?;
+        }
+        result
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    convert::Infallible,
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, BoxBody, Bytes},
+    response::{IntoResponse, Response},
+};
+use futures::{Future, Stream};
+use http::{header::CONTENT_LENGTH, Request};
+use http_body::Body as HttpBody;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{errors::ApiError, rate_limit::route};
+
+/// Error of the request body stream once it passes the limit of [`BodyLimitLayer`]
+#[derive(Debug)]
+pub struct BodyLimitExceeded {
+    pub limit: u64,
+}
+
+impl fmt::Display for BodyLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyLimitExceeded {}
+
+#[pin_project::pin_project]
+struct LimitedBody<B> {
+    #[pin]
+    inner: B,
+    limit: u64,
+    read: u64,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl<B> Stream for LimitedBody<B>
+where
+    B: HttpBody<Data = Bytes>,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        match this.inner.poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                *this.read += data.len() as u64;
+                if *this.read > *this.limit {
+                    this.exceeded.store(true, Ordering::Relaxed);
+                    return Poll::Ready(Some(Err(Box::new(BodyLimitExceeded {
+                        limit: *this.limit,
+                    }))));
+                }
+                Poll::Ready(Some(Ok(data)))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e.into()))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct BodyLimitConfig {
+    limit: u64,
+    routes: Vec<(String, u64)>,
+}
+
+/// Caps request bodies at a number of bytes, checked against `Content-Length` up front and counted while the
+/// body streams. Oversized requests are answered with [`ApiError::PayloadTooLarge`] in place of the handler's
+/// response, whichever rejection it made when reading the body failed.
+#[derive(Clone)]
+pub struct BodyLimitLayer {
+    config: Arc<BodyLimitConfig>,
+}
+
+impl BodyLimitLayer {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            config: Arc::new(BodyLimitConfig {
+                limit,
+                routes: vec![],
+            }),
+        }
+    }
+
+    /// Separate `limit` for requests to `route`, a route as passed to the router or a path when layered outside
+    /// of it, i.e. for uploads
+    pub fn with_route(mut self, route: impl Into<String>, limit: u64) -> Self {
+        Arc::make_mut(&mut self.config)
+            .routes
+            .push((route.into(), limit));
+        self
+    }
+}
+
+impl<S> Layer<S> for BodyLimitLayer {
+    type Service = BodyLimit<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        BodyLimit {
+            config: self.config.clone(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BodyLimit<S> {
+    config: Arc<BodyLimitConfig>,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for BodyLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: HttpBody<Data = Bytes> + Send + 'static,
+    ReqBody::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+        let limit = self
+            .config
+            .routes
+            .iter()
+            .find(|(x, _)| x == route(&parts))
+            .map_or(self.config.limit, |(_, limit)| *limit);
+        let content_length = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse::<u64>().ok());
+        if content_length.is_some_and(|x| x > limit) {
+            return Box::pin(async move {
+                Ok(ApiError::PayloadTooLarge { limit: Some(limit) }.into_response())
+            });
+        }
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let body = Body::wrap_stream(LimitedBody {
+            inner: body,
+            limit,
+            read: 0,
+            exceeded: exceeded.clone(),
+        });
+        // the ready service must handle this request, leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let response = inner.call(Request::from_parts(parts, body)).await?;
+            if exceeded.load(Ordering::Relaxed) {
+                return Ok(ApiError::PayloadTooLarge { limit: Some(limit) }.into_response());
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::Once,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+
+use axum::response::{IntoResponse, Response};
+use futures::Future;
+use http::Request;
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_counter, IntCounter};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::errors::ApiError;
+
+thread_local! {
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "prometheus")]
+fn panics_total() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter!("handler_panics_total", "Panics caught in request handlers").unwrap()
+    })
+}
+
+/// Captures the backtrace of panics caught by [`CatchPanic`] instead of printing them, deferring to the previous
+/// hook for every other panic
+fn install_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(|x| x.get()) {
+                BACKTRACE.with(|x| *x.borrow_mut() = Some(Backtrace::force_capture()));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Turns panics in the inner service into [`ApiError::Other`] responses, logged with their backtrace
+#[derive(Clone, Default)]
+pub struct CatchPanicLayer;
+
+impl CatchPanicLayer {
+    pub fn new() -> Self {
+        install_hook();
+        Self
+    }
+}
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        CatchPanic::new(service)
+    }
+}
+
+#[derive(Clone)]
+pub struct CatchPanic<S> {
+    inner: S,
+}
+
+impl<S> CatchPanic<S> {
+    pub fn new(inner: S) -> Self {
+        install_hook();
+        Self { inner }
+    }
+}
+
+#[pin_project::pin_project]
+pub struct CatchPanicFuture<F> {
+    #[pin]
+    inner: F,
+    panicked: bool,
+}
+
+impl<F, E> Future for CatchPanicFuture<F>
+where
+    F: Future<Output = Result<Response, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if *this.panicked {
+            panic!("CatchPanicFuture polled after completion");
+        }
+        let catching = CATCHING.with(|x| x.replace(true));
+        let inner = this.inner;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx)));
+        CATCHING.with(|x| x.set(catching));
+        match result {
+            Ok(poll) => poll,
+            Err(payload) => {
+                *this.panicked = true;
+                #[cfg(feature = "prometheus")]
+                panics_total().inc();
+                let backtrace = BACKTRACE
+                    .with(|x| x.borrow_mut().take())
+                    .map(|x| x.to_string())
+                    .unwrap_or_default();
+                let error = anyhow::anyhow!(
+                    "handler panicked: {}\n{backtrace}",
+                    panic_message(&*payload)
+                );
+                Poll::Ready(Ok(ApiError::Other(error).into_response()))
+            }
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for CatchPanic<S>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = CatchPanicFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        CatchPanicFuture {
+            inner: self.inner.call(req),
+            panicked: false,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use http::{
+    header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    request::Parts,
+    HeaderValue, Method,
+};
+
+use crate::errors::ApiError;
+
+/// Entity tag of a representation, compared weakly as for `If-None-Match`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    /// Byte-for-byte identity of the representation. `tag` must not contain `"`.
+    pub fn strong(tag: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            weak: false,
+        }
+    }
+
+    /// Semantic equivalence of the representation. `tag` must not contain `"`.
+    pub fn weak(tag: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            weak: true,
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Whether any tag listed in an `If-None-Match` value matches
+    fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match.split(',').map(str::trim).any(|x| {
+            x == "*"
+                || x.strip_prefix("W/")
+                    .unwrap_or(x)
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    == Some(&self.tag)
+        })
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Returns [`ApiError::NotModified`] (with the `ETag` header) if a `GET` or `HEAD` request's `If-None-Match` or
+/// `If-Modified-Since` shows the client already has the current representation. `If-Modified-Since` is ignored when
+/// `If-None-Match` is present.
+pub fn check_conditional(
+    parts: &Parts,
+    etag: Option<&ETag>,
+    last_modified: Option<DateTime<Utc>>,
+) -> Option<ApiError> {
+    if parts.method != Method::GET && parts.method != Method::HEAD {
+        return None;
+    }
+    let not_modified = if parts.headers.contains_key(IF_NONE_MATCH) {
+        let etag = etag?;
+        parts
+            .headers
+            .get_all(IF_NONE_MATCH)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .any(|x| etag.matches(x))
+    } else {
+        let last_modified = last_modified?;
+        let since = parts
+            .headers
+            .get(IF_MODIFIED_SINCE)?
+            .to_str()
+            .ok()
+            .and_then(|x| DateTime::parse_from_rfc2822(x).ok())?;
+        // HTTP dates have second precision
+        last_modified.timestamp() <= since.timestamp()
+    };
+    if !not_modified {
+        return None;
+    }
+    let error = ApiError::NotModified;
+    Some(
+        match etag.and_then(|x| HeaderValue::try_from(x.to_string()).ok()) {
+            Some(etag) => error.with_header(ETAG, etag),
+            None => error,
+        },
+    )
+}
+
+/// Adds `ETag`, `Last-Modified`, and `Cache-Control` to the response of `T`, to pair with [`check_conditional`]
+pub struct Conditional<T> {
+    inner: T,
+    etag: Option<ETag>,
+    last_modified: Option<DateTime<Utc>>,
+    cache_control: Option<HeaderValue>,
+}
+
+impl<T> Conditional<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            etag: None,
+            last_modified: None,
+            cache_control: None,
+        }
+    }
+
+    pub fn with_etag(mut self, etag: ETag) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+
+    pub fn with_last_modified(mut self, last_modified: DateTime<Utc>) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    /// i.e. `no-cache` to have clients revalidate every time, or `max-age=60`
+    pub fn with_cache_control(mut self, cache_control: HeaderValue) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Conditional<T> {
+    fn into_response(self) -> Response {
+        let mut response = self.inner.into_response();
+        let headers = response.headers_mut();
+        if let Some(etag) = self
+            .etag
+            .and_then(|x| HeaderValue::try_from(x.to_string()).ok())
+        {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(last_modified) = self
+            .last_modified
+            .and_then(|x| HeaderValue::try_from(http_date(x)).ok())
+        {
+            headers.insert(LAST_MODIFIED, last_modified);
+        }
+        if let Some(cache_control) = self.cache_control {
+            headers.insert(CACHE_CONTROL, cache_control);
+        }
+        response
+    }
+}
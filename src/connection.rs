@@ -0,0 +1,635 @@
+use std::{
+    future::Future,
+    io,
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    future::{self, Either},
+    Stream, StreamExt,
+};
+use http::{header::CONNECTION, HeaderMap, HeaderValue, Request, Response, Version};
+use http_body::{Body as HttpBody, SizeHint};
+use hyper::{server::conn::Http, Body};
+use log::debug;
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{mpsc, watch},
+    time::{Instant, Sleep},
+};
+use tower_service::Service;
+
+use crate::incoming::shutdown_signal;
+
+#[derive(Clone, Default, Debug)]
+pub struct ConnectionLimits {
+    /// Closes connections that have neither read nor written for this long, including clients that stopped reading
+    /// a response. Must exceed the longest expected handler time, as a busy handler is indistinguishable from an idle
+    /// client here.
+    pub idle_timeout: Option<Duration>,
+    /// Gracefully closes connections this long after they were accepted: after the in-flight response for HTTP/1,
+    /// with a GOAWAY for HTTP/2. Servers other than this crate's need [`LifetimeMakeService`], which only closes
+    /// HTTP/1 connections.
+    pub max_lifetime: Option<Duration>,
+}
+
+#[derive(Default)]
+struct ConnectionCounts {
+    open: AtomicU64,
+    idle: AtomicU64,
+    idle_closed: AtomicU64,
+    lifetime_closed: AtomicU64,
+    #[cfg(feature = "prometheus")]
+    prometheus: Option<(IntGauge, IntGauge, IntCounter, IntCounter)>,
+}
+
+#[derive(Clone, Default)]
+pub struct ConnectionMetrics {
+    counts: Arc<ConnectionCounts>,
+}
+
+impl ConnectionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(feature = "prometheus")]
+    pub fn with_prometheus(metric_prefix: &str) -> Self {
+        Self {
+            counts: Arc::new(ConnectionCounts {
+                prometheus: Some((
+                    register_int_gauge!(
+                        format!("{metric_prefix}_open"),
+                        "currently open connections"
+                    )
+                    .unwrap(),
+                    register_int_gauge!(
+                        format!("{metric_prefix}_idle"),
+                        "currently idle connections"
+                    )
+                    .unwrap(),
+                    register_int_counter!(
+                        format!("{metric_prefix}_idle_closed"),
+                        "connections closed for exceeding the idle timeout"
+                    )
+                    .unwrap(),
+                    register_int_counter!(
+                        format!("{metric_prefix}_lifetime_closed"),
+                        "connections closed for exceeding the maximum lifetime"
+                    )
+                    .unwrap(),
+                )),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn open(&self) -> u64 {
+        self.counts.open.load(Ordering::Relaxed)
+    }
+
+    pub fn idle(&self) -> u64 {
+        self.counts.idle.load(Ordering::Relaxed)
+    }
+
+    pub fn idle_closed(&self) -> u64 {
+        self.counts.idle_closed.load(Ordering::Relaxed)
+    }
+
+    pub fn lifetime_closed(&self) -> u64 {
+        self.counts.lifetime_closed.load(Ordering::Relaxed)
+    }
+
+    fn opened(&self) {
+        self.counts.open.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "prometheus")]
+        if let Some((open, _, _, _)) = &self.counts.prometheus {
+            open.inc();
+        }
+    }
+
+    fn closed(&self) {
+        self.counts.open.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "prometheus")]
+        if let Some((open, _, _, _)) = &self.counts.prometheus {
+            open.dec();
+        }
+    }
+
+    fn set_idle(&self, idle: bool) {
+        if idle {
+            self.counts.idle.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counts.idle.fetch_sub(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "prometheus")]
+        if let Some((_, gauge, _, _)) = &self.counts.prometheus {
+            if idle {
+                gauge.inc();
+            } else {
+                gauge.dec();
+            }
+        }
+    }
+
+    fn idle_timed_out(&self) {
+        self.counts.idle_closed.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "prometheus")]
+        if let Some((_, _, counter, _)) = &self.counts.prometheus {
+            counter.inc();
+        }
+    }
+
+    fn lifetime_expired(&self) {
+        self.counts.lifetime_closed.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "prometheus")]
+        if let Some((_, _, _, counter)) = &self.counts.prometheus {
+            counter.inc();
+        }
+    }
+}
+
+#[derive(Default)]
+struct LifetimeState {
+    expired: AtomicBool,
+    /// Set once an HTTP/1 request was served through [`CloseExpired`], which tracks `in_flight`
+    http1: AtomicBool,
+    /// HTTP/1 responses not yet fully written
+    in_flight: AtomicUsize,
+}
+
+/// [`ConnectionLimits::max_lifetime`] of one [`TrackedConnection`], checked when its responses are sent
+#[derive(Clone)]
+pub struct ConnectionLifetime {
+    deadline: Option<Instant>,
+    state: Arc<LifetimeState>,
+    metrics: ConnectionMetrics,
+}
+
+impl ConnectionLifetime {
+    /// Whether the connection outlived its limit, counted in [`ConnectionMetrics::lifetime_closed`] once
+    pub fn expired(&self) -> bool {
+        if self.deadline.is_none_or(|x| Instant::now() < x) {
+            return false;
+        }
+        if !self.state.expired.swap(true, Ordering::Relaxed) {
+            self.metrics.lifetime_expired();
+        }
+        true
+    }
+
+    /// Resolves once the connection outlived its limit, never without one
+    async fn elapsed(&self) {
+        match self.deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => future::pending().await,
+        }
+    }
+
+    /// Whether the connection is between HTTP/1 requests, so it can be closed without cutting off a response
+    fn between_requests(&self) -> bool {
+        self.state.http1.load(Ordering::Relaxed)
+            && self.state.in_flight.load(Ordering::Relaxed) == 0
+    }
+
+    fn request(&self) -> InFlight {
+        self.state.http1.store(true, Ordering::Relaxed);
+        self.state.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.state.clone())
+    }
+}
+
+/// One HTTP/1 response being written, until its body is dropped
+struct InFlight(Arc<LifetimeState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Wraps an accepted connection to enforce [`ConnectionLimits`] and report [`ConnectionMetrics`]
+#[pin_project::pin_project(PinnedDrop)]
+pub struct TrackedConnection<S> {
+    #[pin]
+    inner: S,
+    limits: ConnectionLimits,
+    metrics: ConnectionMetrics,
+    idle: bool,
+    idle_deadline: Option<Pin<Box<Sleep>>>,
+    timed_out: bool,
+    lifetime: ConnectionLifetime,
+    lifetime_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> TrackedConnection<S> {
+    pub fn new(inner: S, limits: ConnectionLimits, metrics: ConnectionMetrics) -> Self {
+        metrics.opened();
+        let lifetime_deadline = limits.max_lifetime.map(|x| Instant::now() + x);
+        Self {
+            inner,
+            idle_deadline: limits.idle_timeout.map(|x| Box::pin(tokio::time::sleep(x))),
+            timed_out: false,
+            lifetime: ConnectionLifetime {
+                deadline: lifetime_deadline,
+                state: Default::default(),
+                metrics: metrics.clone(),
+            },
+            lifetime_deadline: lifetime_deadline.map(|x| Box::pin(tokio::time::sleep_until(x))),
+            limits,
+            metrics,
+            idle: false,
+        }
+    }
+
+    pub fn lifetime(&self) -> &ConnectionLifetime {
+        &self.lifetime
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn active(
+        limits: &ConnectionLimits,
+        metrics: &ConnectionMetrics,
+        idle: &mut bool,
+        idle_deadline: &mut Option<Pin<Box<Sleep>>>,
+    ) {
+        if *idle {
+            *idle = false;
+            metrics.set_idle(false);
+        }
+        if let (Some(deadline), Some(timeout)) = (idle_deadline, limits.idle_timeout) {
+            deadline.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    /// Whether the idle deadline passed, counted in [`ConnectionMetrics::idle_closed`] once
+    fn idle_expired(
+        metrics: &ConnectionMetrics,
+        timed_out: &mut bool,
+        idle_deadline: &mut Option<Pin<Box<Sleep>>>,
+        cx: &mut Context<'_>,
+    ) -> bool {
+        if !*timed_out {
+            let Some(deadline) = idle_deadline else {
+                return false;
+            };
+            if deadline.as_mut().poll(cx).is_pending() {
+                return false;
+            }
+            *timed_out = true;
+            metrics.idle_timed_out();
+        }
+        true
+    }
+}
+
+/// Fails a write the client hasn't taken for the idle timeout, i.e. as it stopped reading the response
+fn write_timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "connection idle timeout")
+}
+
+#[pin_project::pinned_drop]
+impl<S> PinnedDrop for TrackedConnection<S> {
+    fn drop(self: Pin<&mut Self>) {
+        if self.idle {
+            self.metrics.set_idle(false);
+        }
+        self.metrics.closed();
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for TrackedConnection<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        match this.inner.poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                if buf.filled().len() > filled {
+                    Self::active(this.limits, this.metrics, this.idle, this.idle_deadline);
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                if !*this.idle {
+                    *this.idle = true;
+                    this.metrics.set_idle(true);
+                }
+                // report EOF so the connection is shut down gracefully
+                if Self::idle_expired(this.metrics, this.timed_out, this.idle_deadline, cx) {
+                    return Poll::Ready(Ok(()));
+                }
+                if let Some(deadline) = this.lifetime_deadline {
+                    if deadline.as_mut().poll(cx).is_ready()
+                        && this.lifetime.between_requests()
+                        && this.lifetime.expired()
+                    {
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for TrackedConnection<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        match this.inner.poll_write(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                if n > 0 {
+                    Self::active(this.limits, this.metrics, this.idle, this.idle_deadline);
+                }
+                Poll::Ready(Ok(n))
+            }
+            Poll::Pending
+                if Self::idle_expired(this.metrics, this.timed_out, this.idle_deadline, cx) =>
+            {
+                Poll::Ready(Err(write_timed_out()))
+            }
+            result => result,
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        match this.inner.poll_write_vectored(cx, bufs) {
+            Poll::Ready(Ok(n)) => {
+                if n > 0 {
+                    Self::active(this.limits, this.metrics, this.idle, this.idle_deadline);
+                }
+                Poll::Ready(Ok(n))
+            }
+            Poll::Pending
+                if Self::idle_expired(this.metrics, this.timed_out, this.idle_deadline, cx) =>
+            {
+                Poll::Ready(Err(write_timed_out()))
+            }
+            result => result,
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        match this.inner.poll_flush(cx) {
+            Poll::Pending
+                if Self::idle_expired(this.metrics, this.timed_out, this.idle_deadline, cx) =>
+            {
+                Poll::Ready(Err(write_timed_out()))
+            }
+            result => result,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        match this.inner.poll_shutdown(cx) {
+            Poll::Pending
+                if Self::idle_expired(this.metrics, this.timed_out, this.idle_deadline, cx) =>
+            {
+                Poll::Ready(Err(write_timed_out()))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Serves each connection of `incoming` with the service `make_service` returns for it, as `hyper::Server` does,
+/// until `incoming` ends, then waits for open connections to finish. Connections are shut down gracefully once they
+/// pass [`ConnectionLimits::max_lifetime`], or when `shutdown` is set.
+pub(crate) async fn serve_connections<I, S, M, Svc, B>(
+    incoming: I,
+    make_service: M,
+    shutdown: Option<watch::Receiver<bool>>,
+) -> anyhow::Result<()>
+where
+    I: Stream<Item = io::Result<TrackedConnection<S>>>,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    M: Fn(&TrackedConnection<S>) -> Svc,
+    Svc: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+    Svc::Future: Send + 'static,
+    Svc::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let http = Http::new();
+    // each connection holds a sender, so `recv` returns once all of them finished
+    let (open, mut closed) = mpsc::channel::<()>(1);
+    let mut incoming = pin!(incoming);
+    while let Some(connection) = incoming.next().await {
+        let connection = connection?;
+        let lifetime = connection.lifetime().clone();
+        let service = CloseExpired::new(make_service(&connection), lifetime.clone());
+        let connection = http.serve_connection(connection, service).with_upgrades();
+        let mut shutdown = shutdown.clone();
+        let open = open.clone();
+        tokio::spawn(async move {
+            let mut connection = pin!(connection);
+            let expired = pin!(lifetime.elapsed());
+            let signal = pin!(shutdown_signal(&mut shutdown));
+            let close = future::select(expired, signal);
+            let result = match future::select(connection.as_mut(), close).await {
+                Either::Left((result, _)) => result,
+                Either::Right((close, _)) => {
+                    if let Either::Left(_) = close {
+                        lifetime.expired();
+                    }
+                    // `Connection: close` after the in-flight response for HTTP/1, GOAWAY for HTTP/2
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("error serving connection: {e}");
+            }
+            drop(open);
+        });
+    }
+    drop(open);
+    let _ = closed.recv().await;
+    Ok(())
+}
+
+/// Wraps a make service, i.e. [`crate::tls_acceptor::TlsMakeService`], so responses on connections past their
+/// [`ConnectionLimits::max_lifetime`] carry `Connection: close` and hyper closes them once the response is written
+#[derive(Clone)]
+pub struct LifetimeMakeService<M> {
+    inner: M,
+}
+
+impl<M> LifetimeMakeService<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, M, S> Service<&'a TrackedConnection<S>> for LifetimeMakeService<M>
+where
+    M: Service<&'a TrackedConnection<S>>,
+{
+    type Response = CloseExpired<M::Response>;
+    type Error = M::Error;
+    type Future = LifetimeMakeFuture<M::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, connection: &'a TrackedConnection<S>) -> Self::Future {
+        LifetimeMakeFuture {
+            lifetime: Some(connection.lifetime().clone()),
+            inner: self.inner.call(connection),
+        }
+    }
+}
+
+#[pin_project::pin_project]
+pub struct LifetimeMakeFuture<F> {
+    #[pin]
+    inner: F,
+    lifetime: Option<ConnectionLifetime>,
+}
+
+impl<F: Future<Output = Result<S, E>>, S, E> Future for LifetimeMakeFuture<F> {
+    type Output = Result<CloseExpired<S>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let inner = ready!(this.inner.poll(cx))?;
+        Poll::Ready(Ok(CloseExpired::new(
+            inner,
+            this.lifetime.take().expect("polled after completion"),
+        )))
+    }
+}
+
+/// Service of one connection made by [`LifetimeMakeService`]
+#[derive(Clone)]
+pub struct CloseExpired<S> {
+    inner: S,
+    lifetime: ConnectionLifetime,
+}
+
+impl<S> CloseExpired<S> {
+    fn new(inner: S, lifetime: ConnectionLifetime) -> Self {
+        Self { inner, lifetime }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for CloseExpired<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<InFlightBody<ResBody>>;
+    type Error = S::Error;
+    type Future = CloseExpiredFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // connection headers are invalid in HTTP/2, which has no way to close from a response
+        let lifetime = (req.version() < Version::HTTP_2)
+            .then(|| (self.lifetime.clone(), self.lifetime.request()));
+        CloseExpiredFuture {
+            inner: self.inner.call(req),
+            lifetime,
+        }
+    }
+}
+
+#[pin_project::pin_project]
+pub struct CloseExpiredFuture<F> {
+    #[pin]
+    inner: F,
+    lifetime: Option<(ConnectionLifetime, InFlight)>,
+}
+
+impl<F: Future<Output = Result<Response<B>, E>>, B, E> Future for CloseExpiredFuture<F> {
+    type Output = Result<Response<InFlightBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = ready!(this.inner.poll(cx))?;
+        let in_flight = this.lifetime.take().map(|(lifetime, in_flight)| {
+            if lifetime.expired() {
+                response
+                    .headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+            }
+            in_flight
+        });
+        Poll::Ready(Ok(response.map(|inner| InFlightBody {
+            inner,
+            _in_flight: in_flight,
+        })))
+    }
+}
+
+/// Response body of [`CloseExpired`], marking an HTTP/1 connection as between requests once dropped
+#[pin_project::pin_project]
+pub struct InFlightBody<B> {
+    #[pin]
+    inner: B,
+    _in_flight: Option<InFlight>,
+}
+
+impl<B: HttpBody> HttpBody for InFlightBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        self.project().inner.poll_data(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        self.project().inner.poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use anyhow::anyhow;
+use axum::{
+    body::BoxBody,
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+};
+use futures::Future;
+use http::{header::SET_COOKIE, request::Parts, HeaderName, HeaderValue, Method, Request};
+use rand::{distributions::Alphanumeric, Rng};
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[cfg(feature = "session")]
+use crate::session::Session;
+use crate::{
+    auth::{constant_time_eq, CookieConfig},
+    errors::{ApiError, ApiResult},
+};
+
+#[cfg(feature = "session")]
+const SESSION_KEY: &str = "csrf_token";
+
+/// Whether `path` is `prefix` or below it, matching whole path segments
+fn exempts(prefix: &str, path: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// CSRF token of the current request, to embed in pages submitting to unsafe methods
+#[derive(Clone, Debug)]
+pub struct CsrfToken(pub String);
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        req.extensions
+            .get::<CsrfToken>()
+            .cloned()
+            .ok_or_else(|| ApiError::Other(anyhow!("CsrfToken extracted without CsrfLayer")))
+    }
+}
+
+#[derive(Clone)]
+struct CsrfConfig {
+    cookie: CookieConfig,
+    header: HeaderName,
+    exempt: Vec<String>,
+    #[cfg(feature = "session")]
+    session: bool,
+}
+
+impl CsrfConfig {
+    /// Token of the request, and the `Set-Cookie` value if a new one was issued
+    async fn token(&self, req: &Parts) -> ApiResult<(String, Option<HeaderValue>)> {
+        #[cfg(feature = "session")]
+        if self.session {
+            let session = req.extensions.get::<Session>().ok_or_else(|| {
+                ApiError::Other(anyhow!("CsrfLayer with sessions used outside SessionLayer"))
+            })?;
+            if let Some(token) = session.get::<String>(SESSION_KEY)? {
+                return Ok((token, None));
+            }
+            let token = generate_token();
+            session.insert(SESSION_KEY, &token)?;
+            return Ok((token, None));
+        }
+        if let Some(token) = self.cookie.find(req) {
+            return Ok((token.to_string(), None));
+        }
+        let token = generate_token();
+        let cookie = self.cookie.set_cookie(&token)?;
+        Ok((token, Some(cookie)))
+    }
+
+    fn check(&self, req: &Parts, token: &str) -> ApiResult<()> {
+        if matches!(
+            req.method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) {
+            return Ok(());
+        }
+        let path = req.uri.path();
+        if self.exempt.iter().any(|x| exempts(x, path)) {
+            return Ok(());
+        }
+        let Some(presented) = req.headers.get(&self.header) else {
+            return Err(ApiError::Forbidden("missing csrf token".to_string()));
+        };
+        if !constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+            return Err(ApiError::Forbidden("invalid csrf token".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Requires unsafe methods to echo the CSRF token in the `X-CSRF-Token` header. By default the token is issued
+/// in a cookie readable by scripts (double-submit), with sessions it can be kept in the [`Session`] instead.
+#[derive(Clone)]
+pub struct CsrfLayer {
+    config: Arc<CsrfConfig>,
+}
+
+impl Default for CsrfLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsrfLayer {
+    pub fn new() -> Self {
+        Self {
+            config: Arc::new(CsrfConfig {
+                cookie: CookieConfig {
+                    http_only: false,
+                    ..CookieConfig::new("csrf_token")
+                },
+                header: HeaderName::from_static("x-csrf-token"),
+                exempt: vec![],
+                #[cfg(feature = "session")]
+                session: false,
+            }),
+        }
+    }
+
+    fn config_mut(&mut self) -> &mut CsrfConfig {
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Cookie issuing the token, which must not be `http_only` for scripts to read it
+    pub fn with_cookie(mut self, cookie: CookieConfig) -> Self {
+        self.config_mut().cookie = cookie;
+        self
+    }
+
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        self.config_mut().header = header;
+        self
+    }
+
+    /// Skips the check for `prefix` and the paths below it, i.e. webhooks authenticated by other means. `/webhook`
+    /// exempts `/webhook/github` but not `/webhooks-admin`.
+    pub fn with_exempt_path(mut self, prefix: impl Into<String>) -> Self {
+        self.config_mut().exempt.push(prefix.into());
+        self
+    }
+
+    /// Keeps the token in the [`Session`] (synchronizer token) instead of a cookie. Must be layered inside
+    /// [`crate::session::SessionLayer`].
+    #[cfg(feature = "session")]
+    pub fn with_session(mut self) -> Self {
+        self.config_mut().session = true;
+        self
+    }
+}
+
+impl<S> Layer<S> for CsrfLayer {
+    type Service = Csrf<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Csrf {
+            config: self.config.clone(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Csrf<S> {
+    config: Arc<CsrfConfig>,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Csrf<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let config = self.config.clone();
+        // the ready service must handle this request, leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let (token, cookie) = match config.token(&parts).await {
+                Ok(x) => x,
+                Err(e) => return Ok(e.into_response()),
+            };
+            if let Err(e) = config.check(&parts, &token) {
+                return Ok(e.into_response());
+            }
+            parts.extensions.insert(CsrfToken(token));
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some(cookie) = cookie {
+                response.headers_mut().append(SET_COOKIE, cookie);
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use anyhow::Context;
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    response::Response,
+    Router,
+};
+use http::{header::CONTENT_TYPE, HeaderValue, Method, Request, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_service::Service;
+
+use crate::errors::{ApiError, ApiResult, ErrorBody};
+
+/// Present in the extensions of requests sent through a [`Dispatcher`], so middleware can skip them
+#[derive(Clone, Copy, Debug)]
+pub struct InternalRequest;
+
+/// Sends synthetic requests through a router from within a handler or background job.
+/// Only the layers applied to the given router run, so the caller decides which middleware is shared.
+#[derive(Clone)]
+pub struct Dispatcher {
+    router: Router,
+}
+
+impl Dispatcher {
+    pub fn new(router: Router) -> Self {
+        Self { router }
+    }
+
+    pub async fn dispatch(&self, mut req: Request<Body>) -> Response {
+        req.extensions_mut().insert(InternalRequest);
+        if req.extensions().get::<ConnectInfo<SocketAddr>>().is_none() {
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))));
+        }
+        let mut router = self.router.clone();
+        match futures::future::poll_fn(|cx| router.poll_ready(cx)).await {
+            Ok(()) => (),
+            Err(e) => match e {},
+        }
+        match router.call(req).await {
+            Ok(x) => x,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Dispatches a request with an optional JSON body and decodes the JSON response.
+    /// Non-success responses are mapped back onto the matching [`ApiError`].
+    pub async fn json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<&B>,
+    ) -> ApiResult<T> {
+        let mut req = Request::builder().method(method).uri(uri);
+        let body = match body {
+            Some(body) => {
+                req = req.header(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                Body::from(serde_json::to_vec(body)?)
+            }
+            None => Body::empty(),
+        };
+        let response = self.dispatch(req.body(body)?).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| {
+                ApiError::Other(anyhow::anyhow!("failed to read internal response: {e}"))
+            })?;
+        if !status.is_success() {
+            return Err(status_error(status, &body));
+        }
+        serde_json::from_slice(&body)
+            .context("malformed internal response")
+            .map_err(ApiError::Other)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, uri: &str) -> ApiResult<T> {
+        self.json::<(), T>(Method::GET, uri, None).await
+    }
+}
+
+fn status_error(status: StatusCode, body: &Bytes) -> ApiError {
+    let message = serde_json::from_slice::<ErrorBody>(body)
+        .map(|x| x.message)
+        .unwrap_or_default();
+    match status {
+        StatusCode::BAD_REQUEST => ApiError::BadRequest(message),
+        StatusCode::UNAUTHORIZED => ApiError::Unauthorized(message),
+        StatusCode::FORBIDDEN => ApiError::Forbidden(message),
+        StatusCode::NOT_FOUND => ApiError::NotFound,
+        StatusCode::CONFLICT => ApiError::Conflict(message),
+        StatusCode::GONE => ApiError::Gone(message),
+        StatusCode::UNPROCESSABLE_ENTITY => ApiError::UnprocessableEntity(message),
+        status => ApiError::Other(anyhow::anyhow!(
+            "internal request failed with {status}: {message}"
+        )),
+    }
+}
@@ -1,19 +1,215 @@
-use std::fmt;
+#[cfg(feature = "prometheus")]
+use std::task::{Context, Poll};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock, RwLock,
+    },
+    time::Duration,
+};
 
+#[cfg(feature = "prometheus")]
+use axum::extract::MatchedPath;
 use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use http::{header::LOCATION, StatusCode};
-use log::error;
+#[cfg(feature = "prometheus")]
+use http::Request;
+use http::{
+    header::{CONTENT_TYPE, LOCATION, RETRY_AFTER},
+    HeaderMap, HeaderName, HeaderValue, StatusCode, Uri,
+};
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "prometheus")]
+use tokio::task::futures::TaskLocalFuture;
+#[cfg(feature = "prometheus")]
+use tower_layer::Layer;
+#[cfg(feature = "prometheus")]
+use tower_service::Service;
 use url::Url;
+#[cfg(feature = "validator")]
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+mod html;
+mod localized;
+mod report;
+pub use html::{ErrorPage, ErrorTemplate, HtmlErrorLayer, HtmlErrors};
+pub use localized::{set_translator, LocalizedError, LocalizedErrorBody, Translator};
+pub use report::{
+    set_error_reporter, use_json_error_logs, ErrorContext, ErrorContextLayer, ErrorContextService,
+    ErrorReporter,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct ErrorBody {
     pub message: String,
 }
 
+/// One invalid field of a request, as reported by [`ApiError::Validation`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Dotted path of the field, with list indices in brackets, i.e. `items[0].name`
+    pub field: String,
+    pub message: String,
+    /// Machine readable reason, i.e. `length` or `email`
+    pub code: String,
+}
+
+/// Body of internal errors, with nothing but an ID to find the logged error by
+#[derive(Serialize, Deserialize)]
+pub struct InternalErrorBody {
+    pub message: String,
+    pub error_id: String,
+    /// Full error chain, only sent after [`expose_internal_errors`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Short random ID correlating an internal error response with its log line
+fn generate_error_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ValidationErrorBody {
+    pub message: String,
+    pub errors: Vec<FieldError>,
+}
+
+#[cfg(feature = "validator")]
+fn flatten_validation_errors(prefix: &str, errors: ValidationErrors, out: &mut Vec<FieldError>) {
+    let mut errors: Vec<_> = errors.into_errors().into_iter().collect();
+    errors.sort_by_key(|(field, _)| *field);
+    for (field, kind) in errors {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{prefix}.{field}")
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => out.extend(errors.into_iter().map(|error| {
+                FieldError {
+                    field: path.clone(),
+                    message: error
+                        .message
+                        .map(|x| x.into_owned())
+                        .unwrap_or_else(|| format!("failed {} validation", error.code)),
+                    code: error.code.into_owned(),
+                }
+            })),
+            ValidationErrorsKind::Struct(errors) => flatten_validation_errors(&path, *errors, out),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    flatten_validation_errors(&format!("{path}[{index}]"), *errors, out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "validator")]
+impl ApiError {
+    pub fn validation(errors: ValidationErrors) -> Self {
+        let mut out = vec![];
+        flatten_validation_errors("", errors, &mut out);
+        ApiError::Validation(out)
+    }
+}
+
+/// RFC 7807 Problem Details, served as `application/problem+json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProblemDetails {
+    /// URI identifying the problem type, `about:blank` if only the status is meaningful
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// URI of this occurrence of the problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Extension member listing invalid fields for [`ApiError::Validation`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    /// Extension member correlating internal errors with the server logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_id: Option<String>,
+}
+
+impl ProblemDetails {
+    /// An `about:blank` problem titled with the reason phrase of `status`
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            type_: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            errors: vec![],
+            error_id: None,
+        }
+    }
+
+    pub fn with_type(mut self, type_: impl Into<String>, title: impl Into<String>) -> Self {
+        self.type_ = type_.into();
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = (status, Json(self)).into_response();
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
+
+static PROBLEM_DETAILS: AtomicBool = AtomicBool::new(false);
+
+/// Makes every [`ApiError`] error response a [`ProblemDetails`] body instead of an [`ErrorBody`]. Internal errors
+/// still carry no detail.
+pub fn use_problem_details(enabled: bool) {
+    PROBLEM_DETAILS.store(enabled, Ordering::Relaxed);
+}
+
+static EXPOSE_INTERNAL_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Sends the full error chain of internal errors to clients, for development only
+pub fn expose_internal_errors(enabled: bool) {
+    EXPOSE_INTERNAL_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+fn internal_detail(error: &anyhow::Error) -> Option<String> {
+    EXPOSE_INTERNAL_ERRORS
+        .load(Ordering::Relaxed)
+        .then(|| format!("{error:#}"))
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum RedirectMode {
     MovedPermanently,
@@ -39,33 +235,381 @@ impl RedirectMode {
 #[derive(Debug)]
 pub enum ApiError {
     Redirect(RedirectMode, Url),
+    /// Redirect to a path on this server (or any URI reference), i.e. `/login?next=...`
+    RelativeRedirect(RedirectMode, Uri),
     NotModified,
     BadRequest(String),
     Unauthorized(String),
     Forbidden(String),
     NotFound,
-    Response(Response),
+    Conflict(String),
+    Gone(String),
+    UnprocessableEntity(String),
+    /// Request body over `limit` bytes, if known
+    PayloadTooLarge {
+        limit: Option<u64>,
+    },
+    /// Sets `Retry-After` in whole seconds, rounded up, if known
+    TooManyRequests {
+        retry_after: Option<Duration>,
+    },
+    ServiceUnavailable(String),
+    /// Message translated per request, see [`set_translator`]
+    Localized(LocalizedError),
+    /// Any other status, with its reason phrase as the message
+    Status(StatusCode),
+    /// 422 listing every invalid field
+    Validation(Vec<FieldError>),
+    /// Adds headers to the response of any other variant, see [`ApiError::with_header`]
+    WithHeaders(Box<ApiError>, HeaderMap),
+    /// Boxed to keep `ApiResult` small
+    Response(Box<Response>),
     Other(anyhow::Error),
 }
 
+// `From<StatusCode>` would conflict with the blanket `From` impl for errors
+impl ApiError {
+    /// Closest variant for `status`, falling back to [`ApiError::Status`]
+    pub fn from_status(status: StatusCode) -> Self {
+        let message = || status_message(status);
+        match status {
+            StatusCode::NOT_MODIFIED => ApiError::NotModified,
+            StatusCode::BAD_REQUEST => ApiError::BadRequest(message()),
+            StatusCode::UNAUTHORIZED => ApiError::Unauthorized(message()),
+            StatusCode::FORBIDDEN => ApiError::Forbidden(message()),
+            StatusCode::NOT_FOUND => ApiError::NotFound,
+            StatusCode::CONFLICT => ApiError::Conflict(message()),
+            StatusCode::GONE => ApiError::Gone(message()),
+            StatusCode::UNPROCESSABLE_ENTITY => ApiError::UnprocessableEntity(message()),
+            StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge { limit: None },
+            StatusCode::TOO_MANY_REQUESTS => ApiError::TooManyRequests { retry_after: None },
+            StatusCode::SERVICE_UNAVAILABLE => ApiError::ServiceUnavailable(message()),
+            status => ApiError::Status(status),
+        }
+    }
+}
+
+/// Lowercase reason phrase, as in the messages of other variants
+fn status_message(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .map(|x| x.to_ascii_lowercase())
+        .unwrap_or_else(|| status.as_str().to_string())
+}
+
+fn payload_too_large_message(limit: Option<u64>) -> String {
+    match limit {
+        Some(limit) => format!("request body exceeds the limit of {limit} bytes"),
+        None => status_message(StatusCode::PAYLOAD_TOO_LARGE),
+    }
+}
+
+/// Telemetry label of errors with an arbitrary status, matching the variant for that status
+fn status_kind(status: StatusCode) -> Option<&'static str> {
+    Some(match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::GONE => "gone",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+        StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+        status if status.is_server_error() => "internal",
+        status if status.is_client_error() => "client_error",
+        _ => return None,
+    })
+}
+
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         <Self as fmt::Debug>::fmt(self, f)
     }
 }
 
+/// Under the `validator` feature, `validator::ValidationErrors` becomes [`ApiError::Validation`] rather than an internal
+/// error (it can't have its own `From` impl next to this one)
 impl<E: std::error::Error + Send + Sync + 'static> From<E> for ApiError {
     fn from(error: E) -> Self {
-        Self::Other(anyhow::Error::from(error))
+        let error = anyhow::Error::from(error);
+        #[cfg(feature = "validator")]
+        let error = match error.downcast::<ValidationErrors>() {
+            Ok(errors) => return Self::validation(errors),
+            Err(error) => error,
+        };
+        Self::Other(error)
+    }
+}
+
+type ErrorMapping = Box<dyn Fn(&anyhow::Error) -> Option<ApiError> + Send + Sync>;
+
+fn error_mappings() -> &'static RwLock<Vec<ErrorMapping>> {
+    static MAPPINGS: OnceLock<RwLock<Vec<ErrorMapping>>> = OnceLock::new();
+    MAPPINGS.get_or_init(Default::default)
+}
+
+/// Responds to internal errors caused by an `E` anywhere in their chain with the [`ApiError`] returned by `map`,
+/// i.e. `sqlx::Error::RowNotFound` as [`ApiError::NotFound`]. Returning `None` leaves the error internal. Mappings
+/// are tried in registration order.
+pub fn register_error_mapping<E: std::error::Error + Send + Sync + 'static>(
+    map: impl Fn(&E) -> Option<ApiError> + Send + Sync + 'static,
+) {
+    error_mappings()
+        .write()
+        .unwrap()
+        .push(Box::new(move |error: &anyhow::Error| {
+            error
+                .chain()
+                .find_map(|x| x.downcast_ref::<E>())
+                .and_then(&map)
+        }));
+}
+
+#[cfg(feature = "prometheus")]
+tokio::task_local! {
+    static ERROR_ROUTE: String;
+}
+
+#[cfg(feature = "prometheus")]
+fn error_counter() -> &'static IntCounterVec {
+    static COUNTER: OnceLock<IntCounterVec> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter_vec!(
+            "api_errors_total",
+            "count of error responses by kind and route",
+            &["kind", "route"]
+        )
+        .unwrap()
+    })
+}
+
+/// Attached to the extensions of responses produced from an [`ApiError`], so outer layers can observe the error
+#[derive(Clone, Debug)]
+pub struct ErrorInfo {
+    pub kind: &'static str,
+    /// Includes the full error chain for internal errors, not to be shown to clients
+    pub message: String,
+    /// ID sent to the client for internal errors
+    pub error_id: Option<String>,
+}
+
+/// Absolute or relative destination of a redirect
+pub enum RedirectLocation {
+    Url(Url),
+    Uri(Uri),
+}
+
+impl From<Url> for RedirectLocation {
+    fn from(url: Url) -> Self {
+        RedirectLocation::Url(url)
+    }
+}
+
+impl From<Uri> for RedirectLocation {
+    fn from(uri: Uri) -> Self {
+        RedirectLocation::Uri(uri)
+    }
+}
+
+impl ApiError {
+    pub fn redirect(mode: RedirectMode, location: impl Into<RedirectLocation>) -> Self {
+        match location.into() {
+            RedirectLocation::Url(url) => ApiError::Redirect(mode, url),
+            RedirectLocation::Uri(uri) => ApiError::RelativeRedirect(mode, uri),
+        }
+    }
+
+    /// 301
+    pub fn moved_permanently(location: impl Into<RedirectLocation>) -> Self {
+        Self::redirect(RedirectMode::MovedPermanently, location)
+    }
+
+    /// 302
+    pub fn found(location: impl Into<RedirectLocation>) -> Self {
+        Self::redirect(RedirectMode::Found, location)
+    }
+
+    /// 303, switching to `GET`, i.e. after a form `POST`
+    pub fn see_other(location: impl Into<RedirectLocation>) -> Self {
+        Self::redirect(RedirectMode::SeeOther, location)
+    }
+
+    /// 307, keeping the method and body
+    pub fn temporary_redirect(location: impl Into<RedirectLocation>) -> Self {
+        Self::redirect(RedirectMode::TemporaryRedirect, location)
+    }
+
+    /// 308, keeping the method and body
+    pub fn permanent_redirect(location: impl Into<RedirectLocation>) -> Self {
+        Self::redirect(RedirectMode::PermanentRedirect, location)
+    }
+
+    /// Sets a header on the response, i.e. `WWW-Authenticate` or `Cache-Control`, replacing any set by the variant
+    pub fn with_header(self, name: HeaderName, value: HeaderValue) -> Self {
+        match self {
+            ApiError::WithHeaders(error, mut headers) => {
+                headers.append(name, value);
+                ApiError::WithHeaders(error, headers)
+            }
+            error => {
+                let mut headers = HeaderMap::new();
+                headers.insert(name, value);
+                ApiError::WithHeaders(Box::new(error), headers)
+            }
+        }
+    }
+
+    /// Label used for telemetry, `None` for variants that aren't errors
+    pub fn kind(&self) -> Option<&'static str> {
+        match self {
+            ApiError::Redirect(..)
+            | ApiError::RelativeRedirect(..)
+            | ApiError::NotModified
+            | ApiError::Response(_) => None,
+            ApiError::BadRequest(_) => Some("bad_request"),
+            ApiError::Unauthorized(_) => Some("unauthorized"),
+            ApiError::Forbidden(_) => Some("forbidden"),
+            ApiError::NotFound => Some("not_found"),
+            ApiError::Conflict(_) => Some("conflict"),
+            ApiError::Gone(_) => Some("gone"),
+            ApiError::UnprocessableEntity(_) => Some("unprocessable_entity"),
+            ApiError::PayloadTooLarge { .. } => Some("payload_too_large"),
+            ApiError::TooManyRequests { .. } => Some("too_many_requests"),
+            ApiError::ServiceUnavailable(_) => Some("service_unavailable"),
+            ApiError::Validation(_) => Some("validation"),
+            ApiError::Localized(error) => status_kind(error.status),
+            ApiError::Status(status) => status_kind(*status),
+            ApiError::WithHeaders(error, _) => error.kind(),
+            ApiError::Other(_) => Some("internal"),
+        }
+    }
+
+    /// Status of the response this becomes
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Redirect(mode, _) | ApiError::RelativeRedirect(mode, _) => mode.status_code(),
+            ApiError::NotModified => StatusCode::NOT_MODIFIED,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::UnprocessableEntity(_) | ApiError::Validation(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Localized(error) => error.status,
+            ApiError::Status(status) => *status,
+            ApiError::WithHeaders(error, _) => error.status_code(),
+            ApiError::Response(response) => response.status(),
+            ApiError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn info(&self, error_id: Option<String>) -> Option<ErrorInfo> {
+        let message = match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::Conflict(message)
+            | ApiError::Gone(message)
+            | ApiError::UnprocessableEntity(message)
+            | ApiError::ServiceUnavailable(message) => message.clone(),
+            ApiError::NotFound => "not found".to_string(),
+            ApiError::PayloadTooLarge { limit } => payload_too_large_message(*limit),
+            ApiError::TooManyRequests { .. } => "too many requests".to_string(),
+            ApiError::Localized(error) => error.message(),
+            ApiError::Status(status) => status_message(*status),
+            ApiError::Validation(errors) => errors
+                .iter()
+                .map(|x| format!("{}: {}", x.field, x.message))
+                .collect::<Vec<_>>()
+                .join(", "),
+            ApiError::Other(e) => format!("{e:#}"),
+            ApiError::WithHeaders(error, _) => return error.info(error_id),
+            _ => String::new(),
+        };
+        Some(ErrorInfo {
+            kind: self.kind()?,
+            message,
+            error_id,
+        })
     }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
+        let mut this = self;
+        let mut headers = vec![];
+        while let ApiError::WithHeaders(error, extra) = this {
+            headers.push(extra);
+            this = *error;
+        }
+        let this = match this {
+            ApiError::Other(e) => {
+                let mapped = error_mappings().read().unwrap().iter().find_map(|x| x(&e));
+                mapped.unwrap_or(ApiError::Other(e))
+            }
+            this => this,
+        };
+        let mut response = this.into_mapped_response();
+        // outermost last, so its headers take precedence
+        for headers in headers.into_iter().rev() {
+            response.headers_mut().extend(headers);
+        }
+        response
+    }
+}
+
+impl ApiError {
+    fn into_mapped_response(self) -> Response {
+        #[cfg(feature = "prometheus")]
+        if let Some(kind) = self.kind() {
+            let route = ERROR_ROUTE.try_with(|x| x.clone()).unwrap_or_default();
+            error_counter().with_label_values(&[kind, &route]).inc();
+        }
+        let error_id = matches!(self, ApiError::Other(_)).then(generate_error_id);
+        let info = self.info(error_id.clone());
+        let retry_after = match &self {
+            ApiError::TooManyRequests { retry_after } => *retry_after,
+            _ => None,
+        };
+        let html = info
+            .as_ref()
+            .and_then(|info| html::render(&self, info, self.status_code()));
+        let mut response = if PROBLEM_DETAILS.load(Ordering::Relaxed) {
+            self.into_problem_response(error_id)
+        } else {
+            self.into_body_response(error_id)
+        };
+        if let Some(html) = html {
+            response = html::into_html(response, html);
+        }
+        if let Some(retry_after) = retry_after {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        if let Some(info) = info {
+            response.extensions_mut().insert(info);
+        }
+        response
+    }
+
+    fn into_body_response(self, error_id: Option<String>) -> Response {
         match self {
             ApiError::Redirect(mode, destination) => {
                 (mode.status_code(), [(LOCATION, destination.to_string())]).into_response()
             }
+            ApiError::RelativeRedirect(mode, destination) => {
+                (mode.status_code(), [(LOCATION, destination.to_string())]).into_response()
+            }
             ApiError::NotModified => StatusCode::NOT_MODIFIED.into_response(),
             ApiError::BadRequest(message) => {
                 (StatusCode::BAD_REQUEST, Json(ErrorBody { message })).into_response()
@@ -83,13 +627,208 @@ impl IntoResponse for ApiError {
                 }),
             )
                 .into_response(),
-            ApiError::Response(response) => response,
+            ApiError::Conflict(message) => {
+                (StatusCode::CONFLICT, Json(ErrorBody { message })).into_response()
+            }
+            ApiError::Gone(message) => {
+                (StatusCode::GONE, Json(ErrorBody { message })).into_response()
+            }
+            ApiError::UnprocessableEntity(message) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorBody { message }),
+            )
+                .into_response(),
+            ApiError::PayloadTooLarge { limit } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(ErrorBody {
+                    message: payload_too_large_message(limit),
+                }),
+            )
+                .into_response(),
+            ApiError::TooManyRequests { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ErrorBody {
+                    message: "too many requests".to_string(),
+                }),
+            )
+                .into_response(),
+            ApiError::ServiceUnavailable(message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorBody { message })).into_response()
+            }
+            ApiError::Validation(errors) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ValidationErrorBody {
+                    message: "validation failed".to_string(),
+                    errors,
+                }),
+            )
+                .into_response(),
+            ApiError::Localized(error) => (
+                error.status,
+                Json(LocalizedErrorBody {
+                    message: error.message(),
+                    key: error.key,
+                    args: error.args,
+                }),
+            )
+                .into_response(),
+            ApiError::Status(status) if status.is_client_error() || status.is_server_error() => (
+                status,
+                Json(ErrorBody {
+                    message: status_message(status),
+                }),
+            )
+                .into_response(),
+            ApiError::Status(status) => status.into_response(),
+            error @ ApiError::WithHeaders(..) => error.into_response(),
+            ApiError::Response(response) => *response,
             ApiError::Other(e) => {
-                error!("internal error: {:#}", e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                let error_id = error_id.unwrap_or_else(generate_error_id);
+                report::report_internal(&e, &error_id);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(InternalErrorBody {
+                        message: "internal error".to_string(),
+                        error_id,
+                        detail: internal_detail(&e),
+                    }),
+                )
+                    .into_response()
             }
         }
     }
+
+    fn into_problem_response(self, error_id: Option<String>) -> Response {
+        if let ApiError::Validation(errors) = self {
+            let mut problem = ProblemDetails::new(StatusCode::UNPROCESSABLE_ENTITY)
+                .with_detail("validation failed");
+            problem.errors = errors;
+            return problem.into_response();
+        }
+        let (status, detail) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, Some(message)),
+            ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, Some(message)),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, Some(message)),
+            ApiError::NotFound => (StatusCode::NOT_FOUND, None),
+            ApiError::Conflict(message) => (StatusCode::CONFLICT, Some(message)),
+            ApiError::Gone(message) => (StatusCode::GONE, Some(message)),
+            ApiError::UnprocessableEntity(message) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Some(message))
+            }
+            ApiError::PayloadTooLarge { limit } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Some(payload_too_large_message(limit)),
+            ),
+            ApiError::TooManyRequests { .. } => (StatusCode::TOO_MANY_REQUESTS, None),
+            ApiError::ServiceUnavailable(message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, Some(message))
+            }
+            ApiError::Localized(error) => (error.status, Some(error.message())),
+            ApiError::Status(status) if status.is_client_error() || status.is_server_error() => {
+                (status, None)
+            }
+            ApiError::Other(e) => {
+                let error_id = error_id.unwrap_or_else(generate_error_id);
+                report::report_internal(&e, &error_id);
+                let mut problem = ProblemDetails::new(StatusCode::INTERNAL_SERVER_ERROR);
+                problem.error_id = Some(error_id);
+                problem.detail = internal_detail(&e);
+                return problem.into_response();
+            }
+            other => return other.into_body_response(error_id),
+        };
+        let mut problem = ProblemDetails::new(status);
+        problem.detail = detail;
+        problem.into_response()
+    }
 }
 
 pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Converts a failed `Result` or empty `Option` into an [`ApiError`], discarding the original error
+pub trait ApiResultExt<T>: Sized {
+    fn or_api_error(self, error: impl FnOnce() -> ApiError) -> ApiResult<T>;
+
+    fn or_bad_request(self, message: impl Into<String>) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::BadRequest(message.into()))
+    }
+
+    fn or_unauthorized(self, message: impl Into<String>) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::Unauthorized(message.into()))
+    }
+
+    fn or_forbidden(self, message: impl Into<String>) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::Forbidden(message.into()))
+    }
+
+    fn or_not_found(self) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::NotFound)
+    }
+
+    fn or_conflict(self, message: impl Into<String>) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::Conflict(message.into()))
+    }
+
+    fn or_gone(self, message: impl Into<String>) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::Gone(message.into()))
+    }
+
+    fn or_unprocessable(self, message: impl Into<String>) -> ApiResult<T> {
+        self.or_api_error(|| ApiError::UnprocessableEntity(message.into()))
+    }
+}
+
+impl<T, E> ApiResultExt<T> for Result<T, E> {
+    fn or_api_error(self, error: impl FnOnce() -> ApiError) -> ApiResult<T> {
+        self.map_err(|_| error())
+    }
+}
+
+impl<T> ApiResultExt<T> for Option<T> {
+    fn or_api_error(self, error: impl FnOnce() -> ApiError) -> ApiResult<T> {
+        self.ok_or_else(error)
+    }
+}
+
+/// Labels `api_errors_total` with the matched route of the request being handled
+#[cfg(feature = "prometheus")]
+#[derive(Clone, Default)]
+pub struct ErrorMetricsLayer;
+
+#[cfg(feature = "prometheus")]
+impl<S> Layer<S> for ErrorMetricsLayer {
+    type Service = ErrorMetrics<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ErrorMetrics { inner: service }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+#[derive(Clone)]
+pub struct ErrorMetrics<S> {
+    inner: S,
+}
+
+#[cfg(feature = "prometheus")]
+impl<S, ReqBody> Service<Request<ReqBody>> for ErrorMetrics<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TaskLocalFuture<String, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|x| x.as_str().to_string())
+            .unwrap_or_default();
+        ERROR_ROUTE.scope(route, self.inner.call(req))
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::response::{Html, IntoResponse, Response};
+use http::{
+    header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderMap, Request, StatusCode,
+};
+use tokio::task::futures::TaskLocalFuture;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::{internal_detail, ApiError, ErrorInfo};
+
+/// What an HTML error page shows, without internal details unless exposed by [`super::expose_internal_errors`]
+pub struct ErrorPage<'a> {
+    pub status: StatusCode,
+    pub title: &'a str,
+    pub message: &'a str,
+    pub error_id: Option<&'a str>,
+}
+
+pub type ErrorTemplate = Arc<dyn Fn(&ErrorPage<'_>) -> String + Send + Sync>;
+
+tokio::task_local! {
+    static HTML_TEMPLATE: Option<ErrorTemplate>;
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn default_template(page: &ErrorPage<'_>) -> String {
+    let title = escape(page.title);
+    let error_id = page
+        .error_id
+        .map(|x| format!("<p>Error ID: <code>{}</code></p>", escape(x)))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{status} {title}</title></head><body><h1>{status} {title}</h1><p>{message}</p>{error_id}</body></html>",
+        status = page.status.as_u16(),
+        message = escape(page.message),
+    )
+}
+
+/// Weight of `media_type` in an `Accept` header, ignoring wildcards
+fn quality(accept: &str, media_type: &str) -> f32 {
+    accept
+        .split(',')
+        .filter_map(|x| {
+            let mut params = x.split(';').map(str::trim);
+            if !params.next()?.eq_ignore_ascii_case(media_type) {
+                return None;
+            }
+            Some(
+                params
+                    .find_map(|x| x.strip_prefix("q="))
+                    .and_then(|x| x.parse().ok())
+                    .unwrap_or(1.0),
+            )
+        })
+        .fold(0.0, f32::max)
+}
+
+fn prefers_html(headers: &HeaderMap) -> bool {
+    let accept = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    quality(&accept, "text/html") > quality(&accept, "application/json")
+}
+
+/// HTML page for `error` if the request being handled prefers HTML
+pub(super) fn render(error: &ApiError, info: &ErrorInfo, status: StatusCode) -> Option<String> {
+    let template = HTML_TEMPLATE.try_with(|x| x.clone()).ok()??;
+    let message = match error {
+        ApiError::Other(e) => internal_detail(e).unwrap_or_else(|| "internal error".to_string()),
+        _ => info.message.clone(),
+    };
+    Some(template(&ErrorPage {
+        status,
+        title: status.canonical_reason().unwrap_or_default(),
+        message: &message,
+        error_id: info.error_id.as_deref(),
+    }))
+}
+
+/// Replaces the body of `response`, keeping its status and other headers
+pub(super) fn into_html(response: Response, html: String) -> Response {
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(CONTENT_TYPE);
+    parts.headers.remove(CONTENT_LENGTH);
+    (parts, Html(html)).into_response()
+}
+
+/// Renders [`ApiError`] responses as HTML pages for requests preferring `text/html` over JSON, i.e. browsers
+#[derive(Clone)]
+pub struct HtmlErrorLayer {
+    template: ErrorTemplate,
+}
+
+impl Default for HtmlErrorLayer {
+    fn default() -> Self {
+        Self {
+            template: Arc::new(default_template),
+        }
+    }
+}
+
+impl HtmlErrorLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the built in page. Values in [`ErrorPage`] are not escaped.
+    pub fn with_template(
+        mut self,
+        template: impl Fn(&ErrorPage<'_>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.template = Arc::new(template);
+        self
+    }
+}
+
+impl<S> Layer<S> for HtmlErrorLayer {
+    type Service = HtmlErrors<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HtmlErrors {
+            template: self.template.clone(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HtmlErrors<S> {
+    template: ErrorTemplate,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for HtmlErrors<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TaskLocalFuture<Option<ErrorTemplate>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let template = prefers_html(req.headers()).then(|| self.template.clone());
+        HTML_TEMPLATE.scope(template, self.inner.call(req))
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::{report, ApiError};
+
+/// Error message identified by a translation key, with arguments interpolated into `{name}` placeholders of the
+/// translated text
+#[derive(Debug, Clone)]
+pub struct LocalizedError {
+    pub status: StatusCode,
+    pub key: String,
+    pub args: BTreeMap<String, String>,
+}
+
+impl LocalizedError {
+    pub fn new(status: StatusCode, key: impl Into<String>) -> Self {
+        Self {
+            status,
+            key: key.into(),
+            args: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_arg(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.args.insert(name.into(), value.to_string());
+        self
+    }
+
+    /// Translated with the request's languages, or the key itself without a matching translation
+    pub(super) fn message(&self) -> String {
+        let Some(translator) = translator().read().unwrap().clone() else {
+            return self.key.clone();
+        };
+        let Some(template) = translator(&self.key, &report::request_languages()) else {
+            return self.key.clone();
+        };
+        self.args.iter().fold(template, |x, (name, value)| {
+            x.replace(&format!("{{{name}}}"), value)
+        })
+    }
+}
+
+impl From<LocalizedError> for ApiError {
+    fn from(error: LocalizedError) -> Self {
+        ApiError::Localized(error)
+    }
+}
+
+/// Body of [`ApiError::Localized`], with the key and arguments for clients translating on their own
+#[derive(Serialize, Deserialize)]
+pub struct LocalizedErrorBody {
+    pub message: String,
+    pub key: String,
+    pub args: BTreeMap<String, String>,
+}
+
+/// Looks up the message template of a key in the first supported of the given languages, most preferred first
+pub type Translator = Arc<dyn Fn(&str, &[String]) -> Option<String> + Send + Sync>;
+
+fn translator() -> &'static RwLock<Option<Translator>> {
+    static TRANSLATOR: OnceLock<RwLock<Option<Translator>>> = OnceLock::new();
+    TRANSLATOR.get_or_init(Default::default)
+}
+
+/// Translates every [`LocalizedError`]. Languages come from `Accept-Language` behind
+/// [`super::ErrorContextLayer`].
+pub fn set_translator(
+    translate: impl Fn(&str, &[String]) -> Option<String> + Send + Sync + 'static,
+) {
+    *translator().write().unwrap() = Some(Arc::new(translate));
+}
@@ -0,0 +1,199 @@
+use std::{
+    backtrace::BacktraceStatus,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    task::{Context, Poll},
+};
+
+use http::{header::ACCEPT_LANGUAGE, HeaderMap, HeaderName, Method, Request};
+use log::error;
+use serde::Serialize;
+use tokio::task::futures::TaskLocalFuture;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Request an error occurred in, filled in behind [`ErrorContextLayer`]
+#[derive(Clone, Debug, Default)]
+pub struct ErrorContext {
+    pub method: Option<Method>,
+    pub path: Option<String>,
+    pub request_id: Option<String>,
+    /// `Accept-Language` tags, most preferred first
+    pub languages: Vec<String>,
+    /// ID sent to the client and logged with the error
+    pub error_id: String,
+}
+
+pub type ErrorReporter = Arc<dyn Fn(&anyhow::Error, &ErrorContext) + Send + Sync>;
+
+fn reporter() -> &'static RwLock<Option<ErrorReporter>> {
+    static REPORTER: OnceLock<RwLock<Option<ErrorReporter>>> = OnceLock::new();
+    REPORTER.get_or_init(Default::default)
+}
+
+/// Called with every internal error (including caught panics) as it becomes a response, i.e. to forward to Sentry.
+/// Runs on the request task, so slow reporting should be spawned off.
+pub fn set_error_reporter(report: impl Fn(&anyhow::Error, &ErrorContext) + Send + Sync + 'static) {
+    *reporter().write().unwrap() = Some(Arc::new(report));
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: ErrorContext;
+}
+
+static JSON_ERROR_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Logs internal errors as one JSON object per line, for log pipelines parsing JSON
+pub fn use_json_error_logs(enabled: bool) {
+    JSON_ERROR_LOGS.store(enabled, Ordering::Relaxed);
+}
+
+#[derive(Serialize)]
+struct ErrorLog<'a> {
+    error_id: &'a str,
+    message: String,
+    causes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backtrace: Option<String>,
+}
+
+/// Logs the error chain one cause per line, followed by the backtrace if captured (see `RUST_BACKTRACE`)
+fn log_internal(error: &anyhow::Error, error_id: &str) {
+    let backtrace = error.backtrace();
+    let backtrace =
+        (backtrace.status() == BacktraceStatus::Captured).then(|| backtrace.to_string());
+    if JSON_ERROR_LOGS.load(Ordering::Relaxed) {
+        let log = ErrorLog {
+            error_id,
+            message: error.to_string(),
+            causes: error.chain().skip(1).map(|x| x.to_string()).collect(),
+            backtrace,
+        };
+        match serde_json::to_string(&log) {
+            Ok(log) => error!("{log}"),
+            Err(_) => error!("internal error [{error_id}]: {error:#}"),
+        }
+        return;
+    }
+    let mut log = format!("internal error [{error_id}]: {error}");
+    for cause in error.chain().skip(1) {
+        let _ = write!(log, "\n  caused by: {cause}");
+    }
+    if let Some(backtrace) = backtrace {
+        let _ = write!(log, "\nbacktrace:\n{backtrace}");
+    }
+    error!("{log}");
+}
+
+/// Logs an internal error and passes it to the [`set_error_reporter`] callback
+pub(super) fn report_internal(error: &anyhow::Error, error_id: &str) {
+    log_internal(error, error_id);
+    let Some(report) = reporter().read().unwrap().clone() else {
+        return;
+    };
+    let mut context = REQUEST_CONTEXT.try_with(|x| x.clone()).unwrap_or_default();
+    context.error_id = error_id.to_string();
+    report(error, &context);
+}
+
+/// Languages of the request being handled, empty outside of [`ErrorContextLayer`]
+pub(super) fn request_languages() -> Vec<String> {
+    REQUEST_CONTEXT
+        .try_with(|x| x.languages.clone())
+        .unwrap_or_default()
+}
+
+fn accept_languages(headers: &HeaderMap) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = headers
+        .get_all(ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .filter_map(|x| {
+            let mut params = x.split(';').map(str::trim);
+            let tag = params.next().filter(|x| !x.is_empty() && *x != "*")?;
+            let quality = params
+                .find_map(|x| x.strip_prefix("q="))
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(1.0);
+            (quality > 0.0).then(|| (tag.to_string(), quality))
+        })
+        .collect();
+    // stable, so equally weighted tags keep their order
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Records the method, path, request ID, and languages of each request for [`ErrorContext`]
+#[derive(Clone)]
+pub struct ErrorContextLayer {
+    request_id_header: HeaderName,
+}
+
+impl Default for ErrorContextLayer {
+    fn default() -> Self {
+        Self {
+            request_id_header: HeaderName::from_static("x-request-id"),
+        }
+    }
+}
+
+impl ErrorContextLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults to `x-request-id`
+    pub fn with_request_id_header(mut self, request_id_header: HeaderName) -> Self {
+        self.request_id_header = request_id_header;
+        self
+    }
+}
+
+impl<S> Layer<S> for ErrorContextLayer {
+    type Service = ErrorContextService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ErrorContextService {
+            request_id_header: self.request_id_header.clone(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ErrorContextService<S> {
+    request_id_header: HeaderName,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for ErrorContextService<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TaskLocalFuture<ErrorContext, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let context = ErrorContext {
+            method: Some(req.method().clone()),
+            path: Some(req.uri().path().to_string()),
+            request_id: req
+                .headers()
+                .get(&self.request_id_header)
+                .and_then(|x| x.to_str().ok())
+                .map(str::to_string),
+            languages: accept_languages(req.headers()),
+            error_id: String::new(),
+        };
+        REQUEST_CONTEXT.scope(context, self.inner.call(req))
+    }
+}
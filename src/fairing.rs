@@ -0,0 +1,111 @@
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::body::BoxBody;
+use futures::Future;
+use http::{request, response, Request, Response};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{errors::ErrorInfo, response_hook::RequestInfo};
+
+/// Lifecycle callbacks for cross-cutting concerns too small for a dedicated tower layer
+#[async_trait::async_trait]
+pub trait Fairing: Send + Sync + 'static {
+    /// Runs before routing to the handler, extensions inserted here are visible to extractors
+    async fn on_request(&self, _request: &mut request::Parts) {}
+
+    async fn on_response(&self, _request: &RequestInfo, _response: &mut response::Parts) {}
+
+    /// Runs before `on_response` when the response was produced from an [`crate::errors::ApiError`]
+    async fn on_error(
+        &self,
+        _request: &RequestInfo,
+        _error: &ErrorInfo,
+        _response: &mut response::Parts,
+    ) {
+    }
+}
+
+/// Runs registered [`Fairing`]s in order of attachment
+#[derive(Clone, Default)]
+pub struct FairingLayer {
+    fairings: Vec<Arc<dyn Fairing>>,
+}
+
+impl FairingLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attach(mut self, fairing: impl Fairing) -> Self {
+        self.fairings.push(Arc::new(fairing));
+        self
+    }
+}
+
+impl<S> Layer<S> for FairingLayer {
+    type Service = Fairings<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Fairings {
+            fairings: self.fairings.clone().into(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Fairings<S> {
+    fairings: Arc<[Arc<dyn Fairing>]>,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Fairings<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let fairings = self.fairings.clone();
+        // the ready service must handle this request, leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            for fairing in fairings.iter() {
+                fairing.on_request(&mut parts).await;
+            }
+            let info =
+                RequestInfo::new(&parts.method, &parts.uri, &parts.extensions, &parts.headers);
+            let response = inner.call(Request::from_parts(parts, body)).await?;
+
+            let (mut parts, body) = response.into_parts();
+            if let Some(error) = parts.extensions.get::<ErrorInfo>().cloned() {
+                for fairing in fairings.iter() {
+                    fairing.on_error(&info, &error, &mut parts).await;
+                }
+            }
+            for fairing in fairings.iter() {
+                fairing.on_response(&info, &mut parts).await;
+            }
+            Ok(Response::from_parts(parts, body))
+        })
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+use std::sync::{Arc, RwLock};
+
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use http::StatusCode;
+use indexmap::IndexMap;
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SubsystemHealth {
+    Starting,
+    Healthy,
+    /// Serving, but with reduced functionality or stale data
+    Degraded {
+        reason: String,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+impl SubsystemHealth {
+    /// Whether the subsystem can serve requests
+    pub fn is_ready(&self) -> bool {
+        matches!(
+            self,
+            SubsystemHealth::Healthy | SubsystemHealth::Degraded { .. }
+        )
+    }
+
+    /// Ordered from worst to best, as reported in the `subsystem_health` gauge
+    pub fn level(&self) -> i64 {
+        match self {
+            SubsystemHealth::Failed { .. } => 0,
+            SubsystemHealth::Starting => 1,
+            SubsystemHealth::Degraded { .. } => 2,
+            SubsystemHealth::Healthy => 3,
+        }
+    }
+}
+
+pub trait HealthCheck: Send + Sync + 'static {
+    fn health(&self) -> SubsystemHealth;
+}
+
+#[cfg(feature = "prometheus")]
+fn health_gauge() -> &'static IntGaugeVec {
+    static GAUGE: OnceLock<IntGaugeVec> = OnceLock::new();
+    GAUGE.get_or_init(|| {
+        register_int_gauge_vec!(
+            "subsystem_health",
+            "0 = failed, 1 = starting, 2 = degraded, 3 = healthy",
+            &["subsystem"]
+        )
+        .unwrap()
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HealthReport {
+    pub ready: bool,
+    pub subsystems: IndexMap<String, SubsystemHealth>,
+}
+
+/// Aggregates the health of dependent subsystems for readiness endpoints and metrics
+#[derive(Default)]
+pub struct HealthRegistry {
+    checks: RwLock<IndexMap<String, Arc<dyn HealthCheck>>>,
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, name: impl Into<String>, check: Arc<dyn HealthCheck>) {
+        self.checks.write().unwrap().insert(name.into(), check);
+    }
+
+    pub fn unregister(&self, name: &str) {
+        self.checks.write().unwrap().shift_remove(name);
+    }
+
+    /// Polls every subsystem, updating the `subsystem_health` gauge
+    pub fn report(&self) -> HealthReport {
+        let subsystems: IndexMap<String, SubsystemHealth> = self
+            .checks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, check)| (name.clone(), check.health()))
+            .collect();
+        #[cfg(feature = "prometheus")]
+        for (name, health) in &subsystems {
+            health_gauge()
+                .with_label_values(&[name])
+                .set(health.level());
+        }
+        HealthReport {
+            ready: subsystems.values().all(|x| x.is_ready()),
+            subsystems,
+        }
+    }
+
+    /// Routes `GET /healthz` (always 200) and `GET /readyz` (503 unless every subsystem is ready)
+    pub fn router<S>(self: Arc<Self>) -> Router<S> {
+        Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .with_state(self)
+    }
+}
+
+async fn healthz(State(registry): State<Arc<HealthRegistry>>) -> Json<HealthReport> {
+    Json(registry.report())
+}
+
+async fn readyz(State(registry): State<Arc<HealthRegistry>>) -> impl IntoResponse {
+    let report = registry.report();
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
@@ -0,0 +1,458 @@
+use std::{
+    future::Future,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use axum::{
+    extract::{connect_info::Connected, ConnectInfo},
+    Extension, Router,
+};
+use futures::{
+    future::{self, Either},
+    Stream,
+};
+use log::{debug, error, warn};
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use tower_layer::Layer;
+
+use crate::connection::{
+    serve_connections, ConnectionLimits, ConnectionMetrics, TrackedConnection,
+};
+
+mod listener;
+use listener::Listeners;
+pub use listener::{ClientStream, ListenAddr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptErrorKind {
+    /// Only the connection being accepted was affected
+    Connection,
+    /// Out of file descriptors or memory
+    ResourceExhausted,
+    /// The listening socket itself is failing
+    Listener,
+}
+
+impl AcceptErrorKind {
+    pub fn classify(error: &std::io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut => AcceptErrorKind::Connection,
+            ErrorKind::OutOfMemory => AcceptErrorKind::ResourceExhausted,
+            // ENOMEM, ENFILE, EMFILE
+            _ if matches!(error.raw_os_error(), Some(12 | 23 | 24)) => {
+                AcceptErrorKind::ResourceExhausted
+            }
+            _ => AcceptErrorKind::Listener,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AcceptAlert {
+    FdPressure {
+        open_fds: Option<u64>,
+        fd_limit: Option<u64>,
+    },
+    BudgetExhausted {
+        consecutive_errors: u32,
+        last_error: String,
+    },
+    Rebound {
+        listen: Vec<ListenAddr>,
+    },
+    RebindFailed {
+        listen: Vec<ListenAddr>,
+        error: String,
+    },
+}
+
+pub type AcceptAlertHandler = Arc<dyn Fn(&AcceptAlert) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Close the connection before reading from it
+    Reject,
+}
+
+/// Decides on each TCP client by remote address right after accepting, i.e. for IP allow/deny lists
+pub type ConnectionFilter = Arc<dyn Fn(SocketAddr) -> Verdict + Send + Sync>;
+
+#[derive(Clone)]
+pub struct AcceptPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Number of consecutive non-connection errors tolerated before alerting (and rebinding, if enabled)
+    pub error_budget: u32,
+    /// Close and bind the listeners again once the error budget is exhausted. No clients are accepted until
+    /// binding succeeds, which is retried after each backoff.
+    pub rebind: bool,
+    pub alert: Option<AcceptAlertHandler>,
+}
+
+impl Default for AcceptPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            error_budget: 16,
+            rebind: false,
+            alert: None,
+        }
+    }
+}
+
+impl AcceptPolicy {
+    fn alert(&self, alert: AcceptAlert) {
+        warn!("accept loop alert: {alert:?}");
+        if let Some(handler) = &self.alert {
+            handler(&alert);
+        }
+    }
+}
+
+fn fd_usage() -> (Option<u64>, Option<u64>) {
+    let open_fds = std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|x| x.count() as u64);
+    let fd_limit = std::fs::read_to_string("/proc/self/limits")
+        .ok()
+        .and_then(|limits| {
+            limits
+                .lines()
+                .find(|x| x.starts_with("Max open files"))
+                .and_then(|x| x.split_whitespace().nth(3))
+                .and_then(|x| x.parse().ok())
+        });
+    (open_fds, fd_limit)
+}
+
+/// Resolves once `shutdown` is set to `true`, never if there is no shutdown signal or its sender is dropped
+pub(crate) async fn shutdown_signal(shutdown: &mut Option<watch::Receiver<bool>>) {
+    if let Some(shutdown) = shutdown {
+        while !*shutdown.borrow_and_update() {
+            if shutdown.changed().await.is_err() {
+                break;
+            }
+        }
+        if *shutdown.borrow() {
+            return;
+        }
+    }
+    future::pending().await
+}
+
+/// Accepts clients from every listener, backing off, alerting, and rebinding on errors per [`AcceptPolicy`]
+pub(crate) struct AcceptLoop {
+    incoming: Listeners,
+    listen: Vec<ListenAddr>,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    accept_policy: AcceptPolicy,
+    filter: Option<ConnectionFilter>,
+    consecutive_errors: u32,
+    backoff: Duration,
+}
+
+impl AcceptLoop {
+    pub(crate) fn bind(
+        listen: impl IntoIterator<Item = ListenAddr>,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+    ) -> Result<Self> {
+        let listen: Vec<ListenAddr> = listen.into_iter().collect();
+        let accept_policy = AcceptPolicy::default();
+        Ok(Self {
+            incoming: Listeners::bind(&listen, nodelay, keepalive)?,
+            listen,
+            nodelay,
+            keepalive,
+            consecutive_errors: 0,
+            backoff: accept_policy.initial_backoff,
+            accept_policy,
+            filter: None,
+        })
+    }
+
+    pub(crate) fn set_accept_policy(&mut self, accept_policy: AcceptPolicy) {
+        self.backoff = accept_policy.initial_backoff;
+        self.accept_policy = accept_policy;
+    }
+
+    pub(crate) fn set_filter(&mut self, filter: ConnectionFilter) {
+        self.filter = Some(filter);
+    }
+
+    pub(crate) fn listen(&self) -> &[ListenAddr] {
+        &self.listen
+    }
+
+    pub(crate) fn local_addrs(&self) -> &[ListenAddr] {
+        self.incoming.local_addrs()
+    }
+
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().iter().find_map(|x| match x {
+            ListenAddr::Tcp(x) => Some(*x),
+            #[cfg(unix)]
+            ListenAddr::Unix(_) => None,
+        })
+    }
+
+    /// Next accepted client, or `None` once `shutdown` is signalled
+    pub(crate) async fn accept(
+        &mut self,
+        shutdown: &mut Option<watch::Receiver<bool>>,
+    ) -> Option<ClientStream> {
+        loop {
+            if self.incoming.is_closed() {
+                // a failed rebind left no listeners, keep trying after each backoff
+                if !self.back_off(shutdown).await {
+                    return None;
+                }
+                self.rebind();
+                continue;
+            }
+            let next = match future::select(
+                std::pin::pin!(self.incoming.next()),
+                std::pin::pin!(shutdown_signal(shutdown)),
+            )
+            .await
+            {
+                Either::Left((next, _)) => next,
+                Either::Right(_) => return None,
+            };
+            match next? {
+                Ok(client) => {
+                    self.consecutive_errors = 0;
+                    self.backoff = self.accept_policy.initial_backoff;
+                    return Some(client);
+                }
+                Err(e) => {
+                    if self.handle_accept_error(e) && !self.back_off(shutdown).await {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sleeps for the current backoff, then doubles it. Returns `false` if `shutdown` was signalled first.
+    async fn back_off(&mut self, shutdown: &mut Option<watch::Receiver<bool>>) -> bool {
+        let sleep = tokio::time::sleep(self.backoff);
+        if let Either::Right(_) = future::select(
+            std::pin::pin!(sleep),
+            std::pin::pin!(shutdown_signal(shutdown)),
+        )
+        .await
+        {
+            return false;
+        }
+        self.backoff = (self.backoff * 2).min(self.accept_policy.max_backoff);
+        true
+    }
+
+    /// Whether the [`ConnectionFilter`], if any, accepts `client`. Unix domain socket clients are always accepted.
+    pub(crate) fn admit(&self, client: &ClientStream) -> bool {
+        match (&self.filter, client.remote_addr()) {
+            (Some(filter), Some(remote)) => filter(remote) == Verdict::Accept,
+            _ => true,
+        }
+    }
+
+    /// Alerts and rebinds per the [`AcceptPolicy`], returning whether to back off before accepting again
+    fn handle_accept_error(&mut self, error: std::io::Error) -> bool {
+        let kind = AcceptErrorKind::classify(&error);
+        if kind == AcceptErrorKind::Connection {
+            debug!("accepted TCP client already errored: {error}");
+            return false;
+        }
+        error!("error during accepting TCP client: {error}");
+        if kind == AcceptErrorKind::ResourceExhausted {
+            let (open_fds, fd_limit) = fd_usage();
+            self.accept_policy
+                .alert(AcceptAlert::FdPressure { open_fds, fd_limit });
+        }
+        self.consecutive_errors += 1;
+        if self.consecutive_errors >= self.accept_policy.error_budget {
+            self.accept_policy.alert(AcceptAlert::BudgetExhausted {
+                consecutive_errors: self.consecutive_errors,
+                last_error: error.to_string(),
+            });
+            self.consecutive_errors = 0;
+            if self.accept_policy.rebind {
+                self.rebind();
+            }
+        }
+        true
+    }
+
+    fn rebind(&mut self) {
+        // the addresses are held until the failing listeners are dropped, binding before fails with EADDRINUSE
+        self.incoming.close();
+        match Listeners::bind(&self.listen, self.nodelay, self.keepalive) {
+            Ok(incoming) => {
+                self.incoming = incoming;
+                self.accept_policy.alert(AcceptAlert::Rebound {
+                    listen: self.listen.clone(),
+                });
+            }
+            Err(e) => {
+                self.accept_policy.alert(AcceptAlert::RebindFailed {
+                    listen: self.listen.clone(),
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+}
+
+/// Shared by every [`PlainIncoming`] without its own [`ConnectionMetrics`], exported as `http_connections_open` etc.
+/// under the `prometheus` feature
+fn connection_metrics() -> ConnectionMetrics {
+    #[cfg(feature = "prometheus")]
+    {
+        static METRICS: OnceLock<ConnectionMetrics> = OnceLock::new();
+        METRICS
+            .get_or_init(|| ConnectionMetrics::with_prometheus("http_connections"))
+            .clone()
+    }
+    #[cfg(not(feature = "prometheus"))]
+    ConnectionMetrics::default()
+}
+
+impl<'a> Connected<&'a TrackedConnection<ClientStream>> for SocketAddr {
+    fn connect_info(target: &'a TrackedConnection<ClientStream>) -> Self {
+        // Unix domain socket clients are reported as localhost, as in `dispatch`
+        target
+            .get_ref()
+            .remote_addr()
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+    }
+}
+
+/// Plain HTTP counterpart to `TlsIncoming`, for deployments terminating TLS in front of the server
+pub struct PlainIncoming {
+    accept: AcceptLoop,
+    connection_limits: ConnectionLimits,
+    connection_metrics: ConnectionMetrics,
+    shutdown: Option<watch::Receiver<bool>>,
+}
+
+impl PlainIncoming {
+    pub fn new(listen: SocketAddr, nodelay: bool, keepalive: Option<Duration>) -> Result<Self> {
+        Self::new_multi([listen.into()], nodelay, keepalive)
+    }
+
+    /// Listens on every address, accepting from all of them into one stream. `nodelay` and `keepalive` only apply
+    /// to TCP.
+    pub fn new_multi(
+        listen: impl IntoIterator<Item = ListenAddr>,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+    ) -> Result<Self> {
+        Ok(Self {
+            accept: AcceptLoop::bind(listen, nodelay, keepalive)?,
+            connection_limits: ConnectionLimits::default(),
+            connection_metrics: connection_metrics(),
+            shutdown: None,
+        })
+    }
+
+    pub fn with_accept_policy(mut self, accept_policy: AcceptPolicy) -> Self {
+        self.accept.set_accept_policy(accept_policy);
+        self
+    }
+
+    /// Idle timeout and maximum lifetime enforced on each connection
+    pub fn with_connection_limits(mut self, connection_limits: ConnectionLimits) -> Self {
+        self.connection_limits = connection_limits;
+        self
+    }
+
+    /// Replaces the shared `http_connections_*` metrics
+    pub fn with_connection_metrics(mut self, connection_metrics: ConnectionMetrics) -> Self {
+        self.connection_metrics = connection_metrics;
+        self
+    }
+
+    /// Unix domain socket clients aren't filtered
+    pub fn with_filter(mut self, filter: ConnectionFilter) -> Self {
+        self.accept.set_filter(filter);
+        self
+    }
+
+    /// Stops accepting once `shutdown` is set to `true`, ending the stream returned by `start`
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Bound addresses, with the port chosen by the OS for any listening on port 0
+    pub fn local_addrs(&self) -> &[ListenAddr] {
+        self.accept.local_addrs()
+    }
+
+    /// First bound TCP address
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.accept.local_addr()
+    }
+
+    /// Serves `router` with `ConnectInfo<SocketAddr>` until the shutdown signal, if any, then waits for open
+    /// connections to finish. Returns the bound address along with the server future.
+    pub fn serve(
+        self,
+        router: Router,
+    ) -> (Option<SocketAddr>, impl Future<Output = Result<()>> + Send) {
+        let local_addr = self.local_addr();
+        let shutdown = self.shutdown.clone();
+        let server = serve_connections(
+            self.start(),
+            move |connection| {
+                Extension(ConnectInfo(SocketAddr::connect_info(connection))).layer(router.clone())
+            },
+            shutdown,
+        );
+        (local_addr, server)
+    }
+
+    pub fn start(
+        mut self,
+    ) -> impl Stream<Item = Result<TrackedConnection<ClientStream>, std::io::Error>> {
+        let shutdown = self.shutdown.take();
+        futures::stream::unfold((self, shutdown), |(mut this, mut shutdown)| async move {
+            loop {
+                let Some(client) = this.accept.accept(&mut shutdown).await else {
+                    debug!("HTTP acceptor on {:?} shutting down", this.accept.listen());
+                    return None;
+                };
+                if !this.accept.admit(&client) {
+                    debug!(
+                        "rejected connection from {:?} by filter",
+                        client.remote_addr()
+                    );
+                    continue;
+                }
+                let connection = TrackedConnection::new(
+                    client,
+                    this.connection_limits.clone(),
+                    this.connection_metrics.clone(),
+                );
+                return Some((Ok(connection), (this, shutdown)));
+            }
+        })
+    }
+}
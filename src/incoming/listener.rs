@@ -0,0 +1,291 @@
+use std::{
+    fmt,
+    io::{self, IoSlice},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use anyhow::Result;
+use futures::Stream;
+use hyper::server::{
+    accept::Accept,
+    conn::{AddrIncoming, AddrStream},
+};
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+};
+
+/// An address [`super::PlainIncoming`] or `TlsIncoming` listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// A stale socket file at this path, one refusing connections, is replaced when binding
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => addr.fmt(f),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(addr: SocketAddr) -> Self {
+        ListenAddr::Tcp(addr)
+    }
+}
+
+#[cfg(unix)]
+impl From<PathBuf> for ListenAddr {
+    fn from(path: PathBuf) -> Self {
+        ListenAddr::Unix(path)
+    }
+}
+
+/// An accepted connection from any of the listeners of a [`super::PlainIncoming`] or `TlsIncoming`
+pub enum ClientStream {
+    Tcp(AddrStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    /// `None` for Unix domain socket clients
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        match self {
+            ClientStream::Tcp(stream) => Some(stream.remote_addr()),
+            #[cfg(unix)]
+            ClientStream::Unix(_) => None,
+        }
+    }
+
+    /// Unix domain sockets can't be peeked in tokio, so always report nothing there
+    #[cfg(feature = "tls")]
+    pub(crate) fn poll_peek(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<usize>> {
+        match self {
+            ClientStream::Tcp(stream) => stream.poll_peek(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(_) => Poll::Ready(Ok(0)),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            ClientStream::Tcp(stream) => stream.is_write_vectored(),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Like `TcpListener::bind`, but IPv6 addresses only accept IPv6 so `[::]` and `0.0.0.0` can be bound together
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+enum Listener {
+    Tcp(AddrIncoming),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(listen: &ListenAddr, nodelay: bool, keepalive: Option<Duration>) -> Result<Self> {
+        Ok(match listen {
+            ListenAddr::Tcp(addr) => {
+                let mut incoming = AddrIncoming::from_listener(bind_tcp(*addr)?)?;
+                incoming.set_nodelay(nodelay);
+                incoming.set_keepalive(keepalive);
+                // errors are classified and backed off in the accept loop instead
+                incoming.set_sleep_on_errors(false);
+                Listener::Tcp(incoming)
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                if std::fs::symlink_metadata(path)
+                    .map(|x| x.file_type().is_socket())
+                    .unwrap_or(false)
+                {
+                    // only a socket nothing listens on anymore is stale, never take over a live one
+                    match std::os::unix::net::UnixStream::connect(path) {
+                        Ok(_) => anyhow::bail!("{} is in use by another listener", path.display()),
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            std::fs::remove_file(path)?
+                        }
+                        Err(_) => (),
+                    }
+                }
+                Listener::Unix(UnixListener::bind(path)?)
+            }
+        })
+    }
+
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn local_addr(&self, listen: &ListenAddr) -> ListenAddr {
+        match self {
+            Listener::Tcp(incoming) => ListenAddr::Tcp(incoming.local_addr()),
+            #[cfg(unix)]
+            Listener::Unix(_) => listen.clone(),
+        }
+    }
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<ClientStream>> {
+        match self {
+            Listener::Tcp(incoming) => match Pin::new(incoming).poll_accept(cx) {
+                Poll::Ready(Some(x)) => Poll::Ready(x.map(ClientStream::Tcp)),
+                // AddrIncoming never ends
+                Poll::Ready(None) | Poll::Pending => Poll::Pending,
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, _)| ClientStream::Unix(stream)),
+        }
+    }
+}
+
+/// All listeners of an incoming merged into one accept stream, polled round-robin
+pub(super) struct Listeners {
+    listeners: Vec<Listener>,
+    local_addrs: Vec<ListenAddr>,
+    next: usize,
+}
+
+impl Listeners {
+    pub(super) fn bind(
+        listen: &[ListenAddr],
+        nodelay: bool,
+        keepalive: Option<Duration>,
+    ) -> Result<Self> {
+        if listen.is_empty() {
+            anyhow::bail!("no listen addresses configured");
+        }
+        let listeners: Vec<Listener> = listen
+            .iter()
+            .map(|x| Listener::bind(x, nodelay, keepalive))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            local_addrs: listeners
+                .iter()
+                .zip(listen)
+                .map(|(listener, listen)| listener.local_addr(listen))
+                .collect(),
+            listeners,
+            next: 0,
+        })
+    }
+
+    pub(super) fn local_addrs(&self) -> &[ListenAddr] {
+        &self.local_addrs
+    }
+
+    /// Drops every listener, releasing the addresses for binding again
+    pub(super) fn close(&mut self) {
+        self.listeners.clear();
+    }
+
+    pub(super) fn is_closed(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
+
+impl Stream for Listeners {
+    type Item = io::Result<ClientStream>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let count = self.listeners.len();
+        for offset in 0..count {
+            let index = (self.next + offset) % count;
+            if let Poll::Ready(x) = self.listeners[index].poll_accept(cx) {
+                self.next = (index + 1) % count;
+                return Poll::Ready(Some(x));
+            }
+        }
+        Poll::Pending
+    }
+}
@@ -1,9 +1,37 @@
 #[cfg(feature = "auth")]
 pub mod auth;
+pub mod body_limit;
+pub mod catch_panic;
+pub mod clock;
+pub mod conditional;
+pub mod connection;
 pub mod cors;
+#[cfg(feature = "auth")]
+pub mod csrf;
+pub mod dispatch;
 pub mod errors;
+pub mod fairing;
+pub mod health;
+pub mod incoming;
 pub mod logger;
 #[cfg(feature = "oidc")]
 pub mod oidc;
+#[cfg(feature = "password")]
+pub mod password;
+#[cfg(any(feature = "tls", feature = "auth"))]
+mod pem;
+pub mod principal;
+pub mod progress;
+pub mod rate_limit;
+pub mod redact;
+pub mod reload;
+pub mod response_hook;
+#[cfg(feature = "session")]
+pub mod session;
+pub mod snapshot;
+pub mod timeout;
 #[cfg(feature = "tls")]
 pub mod tls_acceptor;
+
+#[cfg(feature = "tls")]
+pub use tls_acceptor::serve_tls;
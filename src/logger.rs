@@ -16,6 +16,8 @@ use prometheus::{register_histogram_vec, HistogramVec};
 use tower_layer::Layer;
 use tower_service::Service;
 
+use crate::{principal::PrincipalSlot, redact, timeout::TimedOut};
+
 #[derive(Clone)]
 pub struct LoggerConfig {
     pub log_level_filter: Arc<dyn Fn(&str) -> log::Level + Send + Sync>,
@@ -96,12 +98,21 @@ where
     level: log::Level,
     method: Method,
     start: Instant,
+    principal: PrincipalSlot,
     #[cfg(feature = "prometheus")]
     metric: Arc<HistogramVec>,
     #[pin]
     inner: S::Future,
 }
 
+/// ` subject` of the authenticated principal, if any
+fn subject(principal: &PrincipalSlot) -> String {
+    principal
+        .get()
+        .map(|x| format!(" {}", x.subject))
+        .unwrap_or_default()
+}
+
 impl<S, ReqBody, ResBody> Future for LoggerFuture<S, ReqBody, ResBody>
 where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
@@ -114,19 +125,25 @@ where
         match this.inner.poll(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Ok(response)) => {
-                //TODO: include a filtered query parameter list
                 let elapsed = this.start.elapsed().as_secs_f64() * 1000.0;
                 #[cfg(feature = "prometheus")]
                 this.metric
                     .with_label_values(&[&*this.matched_path, response.status().as_str()])
                     .observe(elapsed);
+                let timed_out = response
+                    .extensions()
+                    .get::<TimedOut>()
+                    .map(|x| format!(" (timed out after {:?})", x.0))
+                    .unwrap_or_default();
                 log!(
                     *this.level,
-                    "[{}] {} {} -> {} [{:.02} ms]",
+                    "[{}{}] {} {} -> {}{} [{:.02} ms]",
                     this.remote_addr,
+                    subject(this.principal),
                     this.method,
                     this.path,
                     response.status(),
+                    timed_out,
                     elapsed
                 );
                 Poll::Ready(Ok(response))
@@ -140,8 +157,9 @@ where
 
                 log!(
                     *this.level,
-                    "[{}] {} {} -> FAIL {} [{:.02} ms]",
+                    "[{}{}] {} {} -> FAIL {} [{:.02} ms]",
                     this.remote_addr,
+                    subject(this.principal),
                     this.method,
                     this.path,
                     e,
@@ -169,10 +187,17 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
         let start = Instant::now();
+        let principal = PrincipalSlot::default();
+        req.extensions_mut().insert(principal.clone());
 
-        let path = req.uri().path().to_string();
+        let path = match req.uri().query() {
+            Some(query) if !query.is_empty() => {
+                format!("{}?{}", req.uri().path(), redact::redact_query(query))
+            }
+            _ => req.uri().path().to_string(),
+        };
         let mut remote_addr = req
             .extensions()
             .get::<ConnectInfo<SocketAddr>>()
@@ -202,6 +227,7 @@ where
 
         LoggerFuture {
             start,
+            principal,
             level,
             method,
             remote_addr,
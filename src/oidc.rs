@@ -1,102 +1,592 @@
 // use always_cell::AlwaysCell;
-use anyhow::{Context, Result};
+use crate::{
+    clock::{self, SharedClock},
+    errors::{ApiError, ApiResult, RedirectMode},
+    health::{HealthCheck, SubsystemHealth},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Router,
+};
+use biscuit::ClaimsSet;
 use chrono::{DateTime, Utc};
 use indexmap::IndexMap;
 use log::warn;
 use openid::{
-    error::ClientError, Bearer, Client, DiscoveredClient, OAuth2Error, OAuth2ErrorCode, Options,
-    StandardClaims, Token, Userinfo,
+    error::ClientError, Bearer, Client, Discovered, DiscoveredClient, OAuth2Error, OAuth2ErrorCode,
+    Options, StandardClaims, Token, Userinfo,
 };
-use serde::{Deserialize, Serialize};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{Mutex, OnceCell, RwLock, RwLockReadGuard};
 use url::Url;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+mod bearer;
+mod client_credentials;
+mod http;
+mod id_token;
+mod introspection;
+mod jwks;
+mod logout;
+mod metrics;
+#[cfg(feature = "auth")]
+mod session;
+mod token_exchange;
+pub use bearer::*;
+use client_credentials::ClientCredentialsCache;
+use id_token::{generate_nonce, SessionClaims};
+pub use introspection::IntrospectionResponse;
+use jwks::JwksCache;
+pub use logout::{LogoutCallback, LogoutEvent};
+use metrics::Outcome;
+#[cfg(feature = "auth")]
+pub use session::*;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct OidcConfig {
     pub name: String,
     pub client_id: String,
     pub client_secret: String,
     pub issuer: Url,
     pub redirect: Url,
+    /// Additional redirect URIs registered with the provider that may be passed as overrides, matched exactly
+    #[serde(default)]
+    pub allowed_redirects: Vec<Url>,
     pub refresh_cycle: Duration,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// Appended to the authorization URL, i.e. `prompt`, `max_age`, `login_hint`, `acr_values`, `audience`
+    #[serde(default)]
+    pub extra_auth_params: IndexMap<String, String>,
+    #[serde(default)]
+    pub discovery_retry: DiscoveryRetry,
+    /// Accepted `aud` values of ID and bearer tokens, defaults to the client id
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// Tolerance for `exp`, `iat`, and `auth_time` checks
+    #[serde(default = "default_clock_skew")]
+    pub clock_skew: Duration,
+    /// Requires reauthentication at the provider after this long, checked against `auth_time`
+    #[serde(default)]
+    pub max_age: Option<Duration>,
+    /// Skips the userinfo request in `validate_code`, for callers relying only on ID token claims
+    #[serde(default)]
+    pub skip_userinfo: bool,
+    /// Proxy for all requests to the provider, i.e. an egress proxy
+    #[serde(default)]
+    pub proxy: Option<Url>,
+    /// PEM bundle of additional root certificates trusted for requests to the provider
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    /// Bounds each request to the provider so a slow IdP can't stall login handlers
+    #[serde(default)]
+    pub timeouts: OidcTimeouts,
+    /// Skips `/.well-known/openid-configuration` discovery when set
+    #[serde(default)]
+    pub endpoints: Option<OidcEndpoints>,
+}
+
+/// Per-request limits for calls to the provider, `None` waits indefinitely
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OidcTimeouts {
+    /// Includes fetching the JWKS
+    pub discovery: Option<Duration>,
+    /// Code, refresh, client credentials, token exchange, and introspection requests
+    pub token: Option<Duration>,
+    pub userinfo: Option<Duration>,
+}
+
+impl Default for OidcTimeouts {
+    fn default() -> Self {
+        Self {
+            discovery: Some(Duration::from_secs(10)),
+            token: Some(Duration::from_secs(10)),
+            userinfo: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// Provider endpoints for issuers whose discovery document is unreachable or nonstandard
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OidcEndpoints {
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    pub userinfo_endpoint: Option<Url>,
+    pub jwks_uri: Option<Url>,
+    pub end_session_endpoint: Option<Url>,
+    pub introspection_endpoint: Option<Url>,
+}
+
+/// Retry policy for the initial discovery in [`OidcHandler::new`]. Background rediscovery retries indefinitely using the same backoff.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DiscoveryRetry {
+    /// `None` retries until `deadline`
+    pub max_attempts: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// `None` retries until `max_attempts`, if both are `None` discovery is retried forever
+    pub deadline: Option<Duration>,
+}
+
+impl Default for DiscoveryRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            deadline: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+fn default_clock_skew() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
 }
 
 pub struct OidcController {
-    handlers: IndexMap<String, OidcHandler>,
+    handlers: std::sync::RwLock<IndexMap<String, OidcHandler>>,
+    /// Providers that failed to be added, never discovered
+    failures: std::sync::RwLock<IndexMap<String, OidcStatus>>,
 }
 
 impl OidcController {
-    pub async fn new(configs: &[OidcConfig]) -> Self {
+    pub async fn new(configs: &[OidcConfig]) -> Result<Self> {
         let mut handlers = IndexMap::new();
         for config in configs {
-            handlers.insert(config.name.clone(), OidcHandler::new(config).await);
+            handlers.insert(config.name.clone(), OidcHandler::new(config).await?);
         }
-        Self { handlers }
+        Ok(Self {
+            handlers: std::sync::RwLock::new(handlers),
+            failures: Default::default(),
+        })
     }
 
-    pub fn handler(&self, name: &str) -> Option<&OidcHandler> {
-        self.handlers.get(name)
+    pub fn handler(&self, name: &str) -> Option<OidcHandler> {
+        self.handlers.read().unwrap().get(name).cloned()
     }
 
-    pub fn handlers(&self) -> impl Iterator<Item = &String> {
-        self.handlers.keys()
+    pub fn handlers(&self) -> Vec<String> {
+        self.handlers.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Discovers and registers a provider, replacing any existing one of the same name
+    pub async fn add_handler(&self, config: &OidcConfig) -> Result<()> {
+        let handler = self.discover(config).await?;
+        self.handlers
+            .write()
+            .unwrap()
+            .insert(config.name.clone(), handler);
+        Ok(())
+    }
+
+    /// Returns `false` if no provider was registered under `name`
+    pub fn remove_handler(&self, name: &str) -> bool {
+        self.failures.write().unwrap().shift_remove(name);
+        self.handlers.write().unwrap().shift_remove(name).is_some()
+    }
+
+    /// Converges on `configs`: unchanged providers are kept, changed and new ones are rediscovered, and the rest are removed.
+    /// Nothing is changed if any discovery fails.
+    pub async fn reload(&self, configs: &[OidcConfig]) -> Result<()> {
+        let current = self.handlers.read().unwrap().clone();
+        let mut handlers = IndexMap::new();
+        for config in configs {
+            let handler = match current.get(&config.name) {
+                Some(handler) if handler.config == *config => handler.clone(),
+                _ => self.discover(config).await?,
+            };
+            handlers.insert(config.name.clone(), handler);
+        }
+        // failures of providers dropped from `configs` would otherwise keep `ready` false
+        self.failures
+            .write()
+            .unwrap()
+            .retain(|name, _| handlers.contains_key(name));
+        *self.handlers.write().unwrap() = handlers;
+        Ok(())
+    }
+
+    async fn discover(&self, config: &OidcConfig) -> Result<OidcHandler> {
+        match OidcHandler::new(config).await {
+            Ok(handler) => {
+                self.failures.write().unwrap().shift_remove(&config.name);
+                Ok(handler)
+            }
+            Err(e) => {
+                self.failures.write().unwrap().insert(
+                    config.name.clone(),
+                    OidcStatus {
+                        discovered: false,
+                        last_refresh: None,
+                        last_error: Some(format!("{e:#}")),
+                    },
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// Per-provider discovery state, including providers that failed to be added
+    pub fn status(&self) -> IndexMap<String, OidcStatus> {
+        let mut out: IndexMap<String, OidcStatus> = self
+            .handlers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, handler)| (name.clone(), handler.status()))
+            .collect();
+        for (name, status) in self.failures.read().unwrap().iter() {
+            out.entry(name.clone()).or_insert_with(|| status.clone());
+        }
+        out
+    }
+
+    /// `true` if every known provider has been discovered at least once
+    pub fn ready(&self) -> bool {
+        self.status().values().all(|x| x.discovered)
+    }
+
+    /// Provider configuration with secrets redacted, for [`crate::snapshot::SnapshotRegistry`]
+    pub fn config_snapshot(&self) -> serde_json::Value {
+        let mut out = serde_json::Map::new();
+        for (name, handler) in self.handlers.read().unwrap().iter() {
+            let mut config = handler.config.clone();
+            config.client_secret = crate::redact::REDACTED.to_string();
+            // proxy credentials and extra parameters (i.e. resource keys) are as sensitive as the client secret
+            if let Some(proxy) = &mut config.proxy {
+                let _ = proxy.set_username("");
+                let _ = proxy.set_password(None);
+            }
+            for value in config.extra_auth_params.values_mut() {
+                *value = crate::redact::REDACTED.to_string();
+            }
+            out.insert(
+                name.clone(),
+                serde_json::to_value(config).unwrap_or_default(),
+            );
+        }
+        out.into()
+    }
+
+    /// Routes `GET /:provider/logout` to the provider's end session endpoint
+    pub fn logout_router<S>(self: Arc<Self>) -> Router<S> {
+        Router::new()
+            .route("/:provider/logout", get(logout))
+            .with_state(self)
     }
 }
 
+impl HealthCheck for OidcController {
+    fn health(&self) -> SubsystemHealth {
+        let status = self.status();
+        let failed: Vec<&String> = status
+            .iter()
+            .filter(|(_, x)| !x.discovered)
+            .map(|(name, _)| name)
+            .collect();
+        if !failed.is_empty() {
+            return SubsystemHealth::Failed {
+                reason: format!("providers never discovered: {failed:?}"),
+            };
+        }
+        let degraded: Vec<&String> = status
+            .iter()
+            .filter(|(_, x)| x.last_error.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        if !degraded.is_empty() {
+            return SubsystemHealth::Degraded {
+                reason: format!("providers failing rediscovery: {degraded:?}"),
+            };
+        }
+        SubsystemHealth::Healthy
+    }
+}
+
+#[derive(Deserialize)]
+struct LogoutQuery {
+    id_token_hint: Option<String>,
+    post_logout_redirect_uri: Option<Url>,
+}
+
+async fn logout(
+    State(controller): State<Arc<OidcController>>,
+    Path(provider): Path<String>,
+    Query(query): Query<LogoutQuery>,
+) -> ApiResult<()> {
+    let handler = controller.handler(&provider).ok_or(ApiError::NotFound)?;
+    // an open redirect otherwise, as the provider sends the user on to whatever we pass
+    if let Some(redirect) = &query.post_logout_redirect_uri {
+        if !handler.is_allowed_redirect(redirect) {
+            return Err(ApiError::BadRequest(
+                "post_logout_redirect_uri is not allowed".to_string(),
+            ));
+        }
+    }
+    let Some(url) = handler
+        .logout_url(
+            query.id_token_hint.as_deref(),
+            query.post_logout_redirect_uri.as_ref(),
+        )
+        .await
+    else {
+        return Err(ApiError::NotFound);
+    };
+    Err(ApiError::Redirect(RedirectMode::Found, url))
+}
+
 #[derive(Clone)]
 pub struct OidcHandler {
     client: Arc<RwLock<(DateTime<Utc>, Client)>>,
+    jwks: Arc<RwLock<JwksCache>>,
+    client_credentials: Arc<Mutex<ClientCredentialsCache>>,
+    introspection_endpoint: Arc<OnceCell<Option<Url>>>,
     config: OidcConfig,
+    clock: SharedClock,
+    status: Arc<std::sync::RwLock<OidcStatus>>,
+}
+
+/// Discovery state of a provider, for readiness checks
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OidcStatus {
+    /// Whether discovery ever succeeded, a discovered provider stays usable while rediscovery fails
+    pub discovered: bool,
+    pub last_refresh: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// Fails with a timeout error if `future` doesn't complete within `limit`
+async fn timeout<F: std::future::Future>(
+    limit: Option<Duration>,
+    what: &str,
+    future: F,
+) -> Result<F::Output> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await.map_err(|e| {
+            anyhow::Error::new(e).context(format!("OIDC {what} request timed out after {limit:?}"))
+        }),
+        None => Ok(future.await),
+    }
+}
+
+async fn discover(config: &OidcConfig) -> Result<Client> {
+    let observation = metrics::observe(&config.name, "discovery");
+    let result = timeout(
+        config.timeouts.discovery,
+        "discovery",
+        discover_inner(config),
+    )
+    .await
+    .and_then(|x| x);
+    observation.finish_with(result)
+}
+
+async fn discover_inner(config: &OidcConfig) -> Result<Client> {
+    match &config.endpoints {
+        Some(endpoints) => manual_client(config, endpoints).await,
+        None => Ok(DiscoveredClient::discover_with_client(
+            http::http_client(config)?,
+            config.client_id.clone(),
+            config.client_secret.clone(),
+            Some(config.redirect.to_string()),
+            config.issuer.clone(),
+        )
+        .await?),
+    }
+}
+
+/// Builds a client from `endpoints` as if they were discovered, only the JWKS is fetched
+async fn manual_client(config: &OidcConfig, endpoints: &OidcEndpoints) -> Result<Client> {
+    let provider: openid::Config = serde_json::from_value(serde_json::json!({
+        "issuer": config.issuer,
+        "authorization_endpoint": endpoints.authorization_endpoint,
+        "token_endpoint": endpoints.token_endpoint,
+        "userinfo_endpoint": endpoints.userinfo_endpoint,
+        "jwks_uri": endpoints.jwks_uri,
+        "end_session_endpoint": endpoints.end_session_endpoint,
+        "introspection_endpoint": endpoints.introspection_endpoint,
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+    .context("invalid manual OIDC endpoints")?;
+    let http_client = http::http_client(config)?;
+    let jwks = match &endpoints.jwks_uri {
+        Some(jwks_uri) => Some(
+            http_client
+                .get(jwks_uri.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?,
+        ),
+        None => None,
+    };
+    Ok(Client::new(
+        Discovered(provider),
+        config.client_id.clone(),
+        config.client_secret.clone(),
+        Some(config.redirect.to_string()),
+        http_client,
+        jwks,
+    ))
+}
+
+/// Spreads out refreshes of handlers that were created together
+fn jitter(max: Duration) -> Duration {
+    let nanos = Utc::now().timestamp_subsec_nanos() as u128;
+    Duration::from_nanos((nanos % max.as_nanos().max(1)) as u64)
 }
 
 impl OidcHandler {
-    pub async fn new(config: &OidcConfig) -> Self {
+    pub async fn new(config: &OidcConfig) -> Result<Self> {
+        Self::new_with_clock(config, clock::system()).await
+    }
+
+    /// Fails once `config.discovery_retry` is exhausted
+    pub async fn new_with_clock(config: &OidcConfig, clock: SharedClock) -> Result<Self> {
+        let retry = &config.discovery_retry;
+        let deadline = retry.deadline.map(|x| tokio::time::Instant::now() + x);
+        let mut backoff = retry.initial_backoff;
+        let mut attempts = 0u32;
         let client = loop {
-            match DiscoveredClient::discover(
-                config.client_id.to_string(),
-                config.client_secret.to_string(),
-                Some(config.redirect.to_string()),
-                config.issuer.clone(),
-            )
-            .await
-            {
+            attempts += 1;
+            let error = match discover(config).await {
                 Ok(x) => break x,
-                Err(e) => {
-                    warn!("failed to discover OIDC: {e:?}");
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
+                Err(e) => e,
+            };
+            warn!("failed to discover OIDC (attempt {attempts}): {error:#}");
+            if retry.max_attempts.is_some_and(|x| attempts >= x)
+                || deadline.is_some_and(|x| tokio::time::Instant::now() + backoff > x)
+            {
+                return Err(error.context(format!(
+                    "failed to discover OIDC provider {} after {attempts} attempts",
+                    config.name
+                )));
             }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(retry.max_backoff);
         };
-        Self {
+        let handler = Self {
+            jwks: Arc::new(RwLock::new(JwksCache::new(client.jwks.clone()))),
+            client_credentials: Default::default(),
+            introspection_endpoint: Default::default(),
             client: Arc::new(RwLock::new((
-                Utc::now() + chrono::Duration::from_std(config.refresh_cycle).unwrap(),
+                clock.now() + chrono::Duration::from_std(config.refresh_cycle).unwrap(),
                 client,
             ))),
             config: config.clone(),
-        }
+            status: Arc::new(std::sync::RwLock::new(OidcStatus {
+                discovered: true,
+                last_refresh: Some(clock.now()),
+                last_error: None,
+            })),
+            clock,
+        };
+        handler.spawn_discovery_refresh();
+        handler.spawn_jwks_refresh();
+        Ok(handler)
     }
 
-    async fn recreate(&self) -> Client {
-        loop {
-            match DiscoveredClient::discover(
-                self.config.client_id.clone(),
-                self.config.client_secret.clone(),
-                Some(self.config.redirect.to_string()),
-                self.config.issuer.clone(),
-            )
-            .await
-            {
-                Ok(x) => break x,
-                Err(e) => {
-                    warn!("failed to rediscover OIDC: {e:?}");
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
+    /// Rediscovers the provider every `refresh_cycle` and swaps in the new client, until all clones of this handler are dropped.
+    /// Failures keep the previous client in service and are retried with exponential backoff.
+    fn spawn_discovery_refresh(&self) {
+        let client = Arc::downgrade(&self.client);
+        let jwks = Arc::downgrade(&self.jwks);
+        let config = self.config.clone();
+        let clock = self.clock.clone();
+        let status = Arc::downgrade(&self.status);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(config.refresh_cycle + jitter(config.refresh_cycle / 10)).await;
+                let mut backoff = config.discovery_retry.initial_backoff;
+                let new_client = loop {
+                    if client.strong_count() == 0 {
+                        return;
+                    }
+                    match discover(&config).await {
+                        Ok(x) => break x,
+                        Err(e) => {
+                            warn!("failed to rediscover OIDC: {e:#}");
+                            if let Some(status) = status.upgrade() {
+                                status.write().unwrap().last_error = Some(format!("{e:#}"));
+                            }
+                            tokio::time::sleep(backoff + jitter(backoff / 2)).await;
+                            backoff = (backoff * 2).min(config.discovery_retry.max_backoff);
+                        }
+                    }
+                };
+                let (Some(client), Some(jwks), Some(status)) =
+                    (client.upgrade(), jwks.upgrade(), status.upgrade())
+                else {
+                    return;
+                };
+                let keys = new_client.jwks.clone();
+                *client.write().await = (
+                    clock.now() + chrono::Duration::from_std(config.refresh_cycle).unwrap(),
+                    new_client,
+                );
+                jwks.write().await.replace(keys);
+                *status.write().unwrap() = OidcStatus {
+                    discovered: true,
+                    last_refresh: Some(clock.now()),
+                    last_error: None,
+                };
             }
+        });
+    }
+
+    pub fn status(&self) -> OidcStatus {
+        self.status.read().unwrap().clone()
+    }
+}
+
+impl HealthCheck for OidcHandler {
+    fn health(&self) -> SubsystemHealth {
+        let status = self.status();
+        match status.last_error {
+            _ if !status.discovered => SubsystemHealth::Starting,
+            Some(reason) => SubsystemHealth::Degraded { reason },
+            None => SubsystemHealth::Healthy,
         }
     }
+}
 
+impl OidcHandler {
+    async fn client(&self) -> RwLockReadGuard<'_, (DateTime<Utc>, Client)> {
+        self.client.read().await
+    }
+
+    /// Unregistered `redirect` overrides are ignored in favor of the configured redirect URI
     pub async fn auth_url(&self, redirect: Option<&Url>) -> Url {
+        self.build_auth_url(redirect, None).await
+    }
+
+    /// Includes a generated nonce, which must be passed back to `validate_code`
+    pub async fn auth_url_with_nonce(&self, redirect: Option<&Url>) -> (Url, String) {
+        let nonce = generate_nonce();
+        (self.build_auth_url(redirect, Some(&nonce)).await, nonce)
+    }
+
+    async fn build_auth_url(&self, redirect: Option<&Url>, nonce: Option<&str>) -> Url {
+        let redirect = redirect.filter(|redirect| {
+            let allowed = self.is_allowed_redirect(redirect);
+            if !allowed {
+                warn!("ignoring unregistered OIDC redirect URI {redirect}");
+            }
+            allowed
+        });
         let client = self.client.read().await;
         let mut tclient;
         let client = if let Some(redirect) = redirect {
@@ -106,33 +596,39 @@ impl OidcHandler {
         } else {
             &client.1
         };
-        client.auth_url(&Options {
-            scope: Some("openid email profile".into()),
+        let mut url = client.auth_url(&Options {
+            scope: Some(self.config.scopes.join(" ")),
             state: None,
+            nonce: nonce.map(String::from),
             ..Default::default()
-        })
+        });
+        if let Some(max_age) = self.config.max_age {
+            url.query_pairs_mut()
+                .append_pair("max_age", &max_age.as_secs().to_string());
+        }
+        if !self.config.extra_auth_params.is_empty() {
+            url.query_pairs_mut()
+                .extend_pairs(self.config.extra_auth_params.iter());
+        }
+        url
     }
 
-    pub async fn validate_code(
-        &self,
-        code: &str,
-        redirect: Option<&Url>,
-    ) -> Result<Option<(Bearer, StandardClaims, Userinfo)>> {
-        let mut client = self.client.read().await;
-        let now = Utc::now();
-        if client.0 < now {
-            drop(client);
-            let mut old_client = self.client.write().await;
-            if old_client.0 < now {
-                let new_client = self.recreate().await;
-                *old_client = (
-                    now + chrono::Duration::from_std(self.config.refresh_cycle).unwrap(),
-                    new_client,
-                )
+    /// Whether `redirect` is the configured redirect URI or in `allowed_redirects`
+    pub fn is_allowed_redirect(&self, redirect: &Url) -> bool {
+        *redirect == self.config.redirect || self.config.allowed_redirects.contains(redirect)
+    }
+
+    /// `None` if the code was rejected by the provider
+    async fn request_token(&self, code: &str, redirect: Option<&Url>) -> Result<Option<Bearer>> {
+        if let Some(redirect) = redirect {
+            if !self.is_allowed_redirect(redirect) {
+                bail!(
+                    "redirect URI {redirect} is not registered for OIDC provider {}",
+                    self.config.name
+                );
             }
-            drop(old_client);
-            client = self.client.read().await;
         }
+        let client = self.client().await;
         let mut tclient;
         let client = if let Some(redirect) = redirect {
             tclient = client.1.clone();
@@ -141,29 +637,54 @@ impl OidcHandler {
         } else {
             &client.1
         };
-        let mut token: Token = match client.request_token(code).await {
-            Ok(x) => x.into(),
-            Err(ClientError::OAuth2(OAuth2Error {
-                error: OAuth2ErrorCode::InvalidGrant,
-                ..
-            })) => {
-                return Ok(None);
-            }
-            Err(e) => return Err(e.into()),
+        let observation = metrics::observe(&self.config.name, "token");
+        let result = timeout(
+            self.config.timeouts.token,
+            "token",
+            client.request_token(code),
+        )
+        .await;
+        Self::finish_grant(observation, result)
+    }
+
+    /// `nonce` is the one returned by `auth_url_with_nonce`, if used
+    pub async fn validate_code(
+        &self,
+        code: &str,
+        redirect: Option<&Url>,
+        nonce: Option<&str>,
+    ) -> Result<Option<(Bearer, StandardClaims, Option<Userinfo>)>> {
+        let Some(bearer) = self.request_token(code, redirect).await? else {
+            return Ok(None);
         };
+        let client = self.client().await;
+        let client = &client.1;
+        let mut token: Token = bearer.into();
 
         if let Some(id_token) = &mut token.id_token {
             client
                 .decode_token(id_token)
                 .context("failed to decode token")?;
-            client
-                .validate_token(id_token, None, None)
+            let claims = id_token.payload().context("failed to decode token")?;
+            self.check_standard_claims(client, claims, nonce)
                 .context("failed to validate token")?;
         } else {
             return Ok(None);
         };
 
-        let info = client.request_userinfo(&token).await?;
+        let info = if self.config.skip_userinfo {
+            None
+        } else {
+            let observation = metrics::observe(&self.config.name, "userinfo");
+            let result = timeout(
+                self.config.timeouts.userinfo,
+                "userinfo",
+                client.request_userinfo(&token),
+            )
+            .await
+            .and_then(|x| x.map_err(anyhow::Error::from));
+            Some(observation.finish_with(result)?)
+        };
 
         Ok(Some((
             token.bearer,
@@ -171,4 +692,137 @@ impl OidcHandler {
             info,
         )))
     }
+
+    /// Like [`Self::validate_code`], but deserializes the ID token into custom claims (groups, roles, tenant IDs, ...)
+    pub async fn validate_code_with_claims<C: Serialize + DeserializeOwned>(
+        &self,
+        code: &str,
+        redirect: Option<&Url>,
+        nonce: Option<&str>,
+    ) -> Result<Option<(Bearer, ClaimsSet<C>, Option<Userinfo>)>> {
+        let Some(bearer) = self.request_token(code, redirect).await? else {
+            return Ok(None);
+        };
+        let Some(id_token) = &bearer.id_token else {
+            return Ok(None);
+        };
+        let audiences = self.audiences();
+        let claims = match self.decode_jwt::<C>(id_token, &audiences).await {
+            Ok(x) => x,
+            Err(ApiError::Other(e)) => return Err(e.context("failed to decode token")),
+            Err(e) => return Err(anyhow!("failed to validate token: {e}")),
+        };
+        let session = match self.decode_jwt::<SessionClaims>(id_token, &audiences).await {
+            Ok(x) => x.private,
+            Err(e) => return Err(anyhow!("failed to validate token: {e}")),
+        };
+        if let Some(audience) = &claims.registered.audience {
+            self.check_authorized_party(audience, session.azp.as_deref())
+                .context("failed to validate token")?;
+        }
+        self.check_session_claims(&session, nonce)
+            .context("failed to validate token")?;
+
+        // the ID token was validated above, don't let openid try to decode it again
+        let mut token: Token = bearer.into();
+        token.id_token = None;
+        if self.config.skip_userinfo {
+            return Ok(Some((token.bearer, claims, None)));
+        }
+        let observation = metrics::observe(&self.config.name, "userinfo");
+        let result = timeout(
+            self.config.timeouts.userinfo,
+            "userinfo",
+            self.client().await.1.request_userinfo(&token),
+        )
+        .await
+        .and_then(|x| x.map_err(anyhow::Error::from));
+        let info = observation.finish_with(result)?;
+        if info.sub.is_some() && info.sub != claims.registered.subject {
+            bail!("userinfo subject does not match ID token");
+        }
+
+        Ok(Some((token.bearer, claims, Some(info))))
+    }
+
+    fn check_standard_claims(
+        &self,
+        client: &Client,
+        claims: &StandardClaims,
+        nonce: Option<&str>,
+    ) -> Result<()> {
+        if claims.iss != client.config().issuer {
+            bail!("ID token issuer mismatch");
+        }
+        if !id_token::audience_matches(&claims.aud, &self.audiences()) {
+            bail!("ID token audience mismatch");
+        }
+        self.check_authorized_party(&claims.aud, claims.azp.as_deref())?;
+        self.check_times(claims.exp, claims.iat)?;
+        self.check_session_claims(
+            &SessionClaims {
+                nonce: claims.nonce.clone(),
+                auth_time: claims.auth_time,
+                azp: claims.azp.clone(),
+            },
+            nonce,
+        )
+    }
+
+    pub async fn refresh(&self, bearer: &Bearer) -> Result<Option<Bearer>> {
+        if bearer.refresh_token.is_none() {
+            return Ok(None);
+        }
+        let client = self.client().await;
+        let observation = metrics::observe(&self.config.name, "refresh");
+        let result = timeout(
+            self.config.timeouts.token,
+            "token",
+            client.1.refresh_token(bearer.clone(), None),
+        )
+        .await;
+        Self::finish_grant(observation, result)
+    }
+
+    /// `None` if the provider rejected the grant
+    fn finish_grant(
+        observation: metrics::Observation,
+        result: Result<Result<Bearer, ClientError>>,
+    ) -> Result<Option<Bearer>> {
+        let result = match result {
+            Ok(Ok(x)) => Ok(Some(x)),
+            Ok(Err(ClientError::OAuth2(OAuth2Error {
+                error: OAuth2ErrorCode::InvalidGrant,
+                ..
+            }))) => Ok(None),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e),
+        };
+        observation.finish(match &result {
+            Ok(None) => Outcome::Rejected,
+            x => Outcome::of(x),
+        });
+        result
+    }
+
+    /// Returns `None` if the provider does not advertise an `end_session_endpoint`
+    pub async fn logout_url(
+        &self,
+        id_token_hint: Option<&str>,
+        post_logout_redirect: Option<&Url>,
+    ) -> Option<Url> {
+        let client = self.client().await;
+        let mut url = client.1.config().end_session_endpoint.clone()?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("client_id", &self.config.client_id);
+            if let Some(id_token_hint) = id_token_hint {
+                query.append_pair("id_token_hint", id_token_hint);
+            }
+            if let Some(post_logout_redirect) = post_logout_redirect {
+                query.append_pair("post_logout_redirect_uri", post_logout_redirect.as_str());
+            }
+        }
+        Some(url)
+    }
 }
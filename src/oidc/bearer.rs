@@ -0,0 +1,263 @@
+use std::marker::PhantomData;
+
+use axum::extract::FromRequestParts;
+use biscuit::{
+    jwa::SignatureAlgorithm, jws::Compact, ClaimPresenceOptions, ClaimsSet, Empty, Presence,
+    RegisteredClaims, TemporalOptions, ValidationOptions,
+};
+use chrono::{DateTime, Utc};
+use http::{header::AUTHORIZATION, request::Parts};
+use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
+
+use super::{id_token::audience_matches, OidcHandler};
+use crate::errors::{ApiError, ApiResult};
+#[cfg(feature = "auth")]
+use crate::{
+    auth::{Guard, HasScopes},
+    principal::{AuthMethod, Principal},
+};
+
+pub trait OidcBearerParam {
+    fn handler() -> OidcHandler;
+
+    /// Expected `aud` claim, defaults to the configured audiences
+    fn audience() -> Option<String> {
+        None
+    }
+}
+
+/// Validates an `Authorization: Bearer` JWT against the provider's JWKS
+pub struct OidcBearer<C, P: OidcBearerParam>(pub ClaimsSet<C>, pub PhantomData<P>);
+
+/// [`OidcBearer`] that also passes the guard `G`, i.e. [`crate::auth::RequireScopes`]
+#[cfg(feature = "auth")]
+pub struct OidcRequire<C, P: OidcBearerParam, G: Guard<ClaimsSet<C>>>(
+    pub ClaimsSet<C>,
+    pub PhantomData<(P, G)>,
+);
+
+#[cfg(feature = "auth")]
+impl<C: HasScopes> HasScopes for ClaimsSet<C> {
+    fn scopes(&self) -> Vec<String> {
+        self.private.scopes()
+    }
+}
+
+/// Header algorithms accepted, as providers sign with asymmetric keys. `none` and shared-secret algorithms would
+/// let a token be forged from the public JWKS.
+fn allowed_algorithm(algorithm: SignatureAlgorithm) -> bool {
+    use SignatureAlgorithm::*;
+    matches!(
+        algorithm,
+        RS256 | RS384 | RS512 | ES256 | ES384 | ES512 | PS256 | PS384 | PS512
+    )
+}
+
+/// Checks issuer, audience, and expiry, all of which must be present
+fn check_registered(
+    registered: &RegisteredClaims,
+    issuer: &Url,
+    audiences: &[String],
+    epsilon: chrono::Duration,
+    now: DateTime<Utc>,
+) -> ApiResult<()> {
+    // compared as URLs, trailing slashes are inconsistent across providers
+    let token_issuer = registered
+        .issuer
+        .as_deref()
+        .and_then(|x| Url::parse(x).ok());
+    if token_issuer.as_ref() != Some(issuer) {
+        return Err(ApiError::Unauthorized("invalid bearer token".to_string()));
+    }
+    if !registered
+        .audience
+        .as_ref()
+        .is_some_and(|x| audience_matches(x, audiences))
+    {
+        return Err(ApiError::Unauthorized("invalid bearer token".to_string()));
+    }
+    registered
+        .validate(ValidationOptions {
+            claim_presence_options: ClaimPresenceOptions {
+                expiry: Presence::Required,
+                issuer: Presence::Required,
+                audience: Presence::Required,
+                ..Default::default()
+            },
+            temporal_options: TemporalOptions {
+                epsilon,
+                now: Some(now),
+            },
+            ..Default::default()
+        })
+        .map_err(|e| {
+            debug!("rejected bearer token claims: {e}");
+            ApiError::Unauthorized("invalid bearer token".to_string())
+        })
+}
+
+impl OidcHandler {
+    pub async fn validate_bearer<C: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        audience: Option<&str>,
+    ) -> ApiResult<ClaimsSet<C>> {
+        let audiences = match audience {
+            Some(audience) => vec![audience.to_string()],
+            None => self.audiences(),
+        };
+        self.decode_jwt(token, &audiences).await
+    }
+
+    /// Verifies signature, issuer, audience, and expiry (with the configured clock skew)
+    pub(super) async fn decode_jwt<C: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        audiences: &[String],
+    ) -> ApiResult<ClaimsSet<C>> {
+        let token: Compact<ClaimsSet<C>, Empty> = Compact::new_encoded(token);
+        let header = token
+            .unverified_header()
+            .map_err(|_| ApiError::Unauthorized("malformed bearer token".to_string()))?
+            .registered;
+        // passed as the expected algorithm, as some providers publish keys without `alg`
+        if !allowed_algorithm(header.algorithm) {
+            return Err(ApiError::Unauthorized("invalid bearer token".to_string()));
+        }
+        let Some(jwks) = self.jwks_for_kid(header.key_id.as_deref()).await else {
+            return Err(ApiError::Other(anyhow::anyhow!(
+                "OIDC provider did not publish a JWKS"
+            )));
+        };
+        let token = token
+            .decode_with_jwks(&jwks, Some(header.algorithm))
+            .map_err(|e| {
+                debug!("rejected bearer token: {e}");
+                ApiError::Unauthorized("invalid bearer token".to_string())
+            })?;
+        let client = self.client().await;
+        let claims = token
+            .payload()
+            .map_err(|_| ApiError::Unauthorized("invalid bearer token".to_string()))?;
+        check_registered(
+            &claims.registered,
+            &client.1.config().issuer,
+            audiences,
+            self.clock_skew(),
+            self.clock.now(),
+        )?;
+        Ok(claims.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl<C, P, S> FromRequestParts<S> for OidcBearer<C, P>
+where
+    C: Serialize + DeserializeOwned + Clone + Send,
+    P: OidcBearerParam,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let Some(auth) = req.headers.get(AUTHORIZATION) else {
+            return Err(ApiError::Unauthorized("missing bearer token".to_string()));
+        };
+        let Some(token) = auth.to_str()?.strip_prefix("Bearer ").map(|x| x.trim()) else {
+            return Err(ApiError::Unauthorized("malformed bearer token".to_string()));
+        };
+        let claims = P::handler()
+            .validate_bearer(token, P::audience().as_deref())
+            .await?;
+        #[cfg(feature = "auth")]
+        if let Some(principal) = serde_json::to_value(&claims)
+            .ok()
+            .and_then(|x| Principal::from_claims(&x, AuthMethod::OidcBearer))
+        {
+            principal.attach(req);
+        }
+        Ok(Self(claims, PhantomData))
+    }
+}
+
+#[cfg(feature = "auth")]
+#[async_trait::async_trait]
+impl<C, P, G, S> FromRequestParts<S> for OidcRequire<C, P, G>
+where
+    C: Serialize + DeserializeOwned + Clone + Send,
+    P: OidcBearerParam,
+    G: Guard<ClaimsSet<C>>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, state: &S) -> ApiResult<Self> {
+        let OidcBearer(claims, _) = OidcBearer::<C, P>::from_request_parts(req, state).await?;
+        G::check(&claims)?;
+        Ok(Self(claims, PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::SingleOrMultiple;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn claims(expiry: Option<DateTime<Utc>>) -> RegisteredClaims {
+        RegisteredClaims {
+            issuer: Some("https://issuer.example/".to_string()),
+            audience: Some(SingleOrMultiple::Single("api".to_string())),
+            expiry: expiry.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    fn check(registered: &RegisteredClaims, now: DateTime<Utc>) -> bool {
+        check_registered(
+            registered,
+            &Url::parse("https://issuer.example").unwrap(),
+            &["api".to_string()],
+            chrono::Duration::zero(),
+            now,
+        )
+        .is_ok()
+    }
+
+    #[test]
+    fn requires_expiry() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert!(check(
+            &claims(Some(now + chrono::Duration::minutes(5))),
+            now
+        ));
+        assert!(!check(
+            &claims(Some(now - chrono::Duration::minutes(5))),
+            now
+        ));
+        assert!(!check(&claims(None), now));
+    }
+
+    #[test]
+    fn requires_issuer_and_audience() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let valid = claims(Some(now + chrono::Duration::minutes(5)));
+        let mut registered = valid.clone();
+        registered.issuer = None;
+        assert!(!check(&registered, now));
+        let mut registered = valid;
+        registered.audience = Some(SingleOrMultiple::Single("other".to_string()));
+        assert!(!check(&registered, now));
+    }
+
+    #[test]
+    fn rejects_symmetric_algorithms() {
+        assert!(allowed_algorithm(SignatureAlgorithm::RS256));
+        assert!(allowed_algorithm(SignatureAlgorithm::ES256));
+        assert!(!allowed_algorithm(SignatureAlgorithm::None));
+        assert!(!allowed_algorithm(SignatureAlgorithm::HS256));
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Context, Result};
+use openid::Bearer;
+use tokio::sync::Mutex;
+
+use super::{http::RequestBuilderExt, metrics, OidcHandler};
+
+/// Tokens are renewed this long before they expire
+const RENEW_BEFORE_EXPIRY: chrono::Duration = chrono::Duration::seconds(30);
+
+/// Lifetime assumed for tokens issued without `expires_in`
+const DEFAULT_LIFETIME: chrono::Duration = chrono::Duration::seconds(5 * 60);
+
+/// Token per scope, each behind its own lock so concurrent callers for a scope share one request
+pub(super) type ClientCredentialsCache = HashMap<String, Arc<Mutex<Option<Bearer>>>>;
+
+impl OidcHandler {
+    /// Requests (or reuses a cached) access token for this client itself, for service-to-service calls
+    pub async fn client_credentials_token(&self, scopes: &[&str]) -> Result<Bearer> {
+        let scope = scopes.join(" ");
+        let slot = self
+            .client_credentials
+            .lock()
+            .await
+            .entry(scope.clone())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(bearer) = &*slot {
+            let fresh = bearer
+                .expires
+                .is_some_and(|x| x - RENEW_BEFORE_EXPIRY > self.clock.now());
+            if fresh {
+                return Ok(bearer.clone());
+            }
+        }
+
+        let (http_client, token_endpoint) = {
+            let client = self.client().await;
+            (
+                client.1.http_client.clone(),
+                client.1.config().token_endpoint.clone(),
+            )
+        };
+        let mut form = vec![("grant_type", "client_credentials")];
+        if !scope.is_empty() {
+            form.push(("scope", scope.as_str()));
+        }
+        let observation = metrics::observe(&self.config.name, "client_credentials");
+        let result = async {
+            Ok::<_, anyhow::Error>(
+                http_client
+                    .post(token_endpoint)
+                    .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+                    .form(&form)
+                    .timeout_opt(self.config.timeouts.token)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("client credentials grant rejected")?
+                    .json::<Bearer>()
+                    .await?,
+            )
+        }
+        .await;
+        let mut bearer = observation.finish_with(result)?;
+        if bearer.expires.is_none() {
+            bearer.expires = Some(self.clock.now() + DEFAULT_LIFETIME);
+        }
+        *slot = Some(bearer.clone());
+        Ok(bearer)
+    }
+}
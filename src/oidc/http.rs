@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use super::OidcConfig;
+
+/// HTTP client for every request made on behalf of `config`, honoring its proxy and CA bundle
+pub(super) fn http_client(config: &OidcConfig) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.clone()).context("invalid OIDC proxy")?);
+    }
+    if let Some(ca_bundle) = &config.ca_bundle {
+        let pem = std::fs::read_to_string(ca_bundle)
+            .with_context(|| format!("failed to read CA bundle {}", ca_bundle.display()))?;
+        const END: &str = "-----END CERTIFICATE-----";
+        for certificate in pem.split_inclusive(END).filter(|x| x.contains(END)) {
+            builder = builder.add_root_certificate(
+                reqwest::Certificate::from_pem(certificate.trim().as_bytes())
+                    .with_context(|| format!("invalid certificate in {}", ca_bundle.display()))?,
+            );
+        }
+    }
+    Ok(builder.build()?)
+}
+
+pub(super) trait RequestBuilderExt {
+    fn timeout_opt(self, timeout: Option<Duration>) -> Self;
+}
+
+impl RequestBuilderExt for reqwest::RequestBuilder {
+    fn timeout_opt(self, timeout: Option<Duration>) -> Self {
+        match timeout {
+            Some(timeout) => self.timeout(timeout),
+            None => self,
+        }
+    }
+}
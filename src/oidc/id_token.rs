@@ -0,0 +1,94 @@
+use anyhow::{bail, Result};
+use biscuit::SingleOrMultiple;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use super::OidcHandler;
+
+/// ID token claims checked in addition to the registered ones
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub(super) struct SessionClaims {
+    pub nonce: Option<String>,
+    pub auth_time: Option<i64>,
+    pub azp: Option<String>,
+}
+
+pub(super) fn audience_matches(audience: &SingleOrMultiple<String>, allowed: &[String]) -> bool {
+    match audience {
+        SingleOrMultiple::Single(x) => allowed.contains(x),
+        SingleOrMultiple::Multiple(x) => x.iter().any(|x| allowed.contains(x)),
+    }
+}
+
+pub(super) fn generate_nonce() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+impl OidcHandler {
+    /// Configured audiences, or the client id
+    pub(super) fn audiences(&self) -> Vec<String> {
+        if self.config.audiences.is_empty() {
+            vec![self.config.client_id.clone()]
+        } else {
+            self.config.audiences.clone()
+        }
+    }
+
+    pub(super) fn clock_skew(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.config.clock_skew).unwrap_or(chrono::Duration::zero())
+    }
+
+    /// Checks `exp` and `iat` against the clock, allowing for the configured skew
+    pub(super) fn check_times(&self, expiry: i64, issued_at: i64) -> Result<()> {
+        let now = self.clock.now().timestamp();
+        let skew = self.clock_skew().num_seconds();
+        if expiry + skew <= now {
+            bail!("ID token expired");
+        }
+        if issued_at - skew > now {
+            bail!("ID token issued in the future");
+        }
+        Ok(())
+    }
+
+    /// Requires `azp` to be our client id when the token has several audiences
+    pub(super) fn check_authorized_party(
+        &self,
+        audience: &SingleOrMultiple<String>,
+        azp: Option<&str>,
+    ) -> Result<()> {
+        if let SingleOrMultiple::Multiple(audience) = audience {
+            if audience.len() > 1 && azp != Some(self.config.client_id.as_str()) {
+                bail!("ID token authorized party mismatch");
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the nonce sent with the authorization request and the configured `max_age`
+    pub(super) fn check_session_claims(
+        &self,
+        claims: &SessionClaims,
+        nonce: Option<&str>,
+    ) -> Result<()> {
+        if let Some(nonce) = nonce {
+            if claims.nonce.as_deref() != Some(nonce) {
+                bail!("ID token nonce mismatch");
+            }
+        }
+        if let Some(max_age) = self.config.max_age {
+            let Some(auth_time) = claims.auth_time else {
+                bail!("ID token missing auth_time, but max_age is configured");
+            };
+            let max_age = max_age.as_secs() as i64 + self.clock_skew().num_seconds();
+            if auth_time + max_age < self.clock.now().timestamp() {
+                bail!("ID token auth_time exceeds max_age");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use super::{http::RequestBuilderExt, metrics, OidcHandler};
+
+/// RFC 7662 token introspection response
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub token_type: Option<String>,
+    pub exp: Option<i64>,
+    pub iat: Option<i64>,
+    pub nbf: Option<i64>,
+    pub sub: Option<String>,
+    /// Either a single string or an array of strings
+    pub aud: Option<Value>,
+    pub iss: Option<String>,
+    pub jti: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct IntrospectionDiscovery {
+    introspection_endpoint: Option<Url>,
+}
+
+impl OidcHandler {
+    async fn introspection_endpoint(&self) -> Result<Url> {
+        if let Some(endpoints) = &self.config.endpoints {
+            return endpoints
+                .introspection_endpoint
+                .clone()
+                .ok_or_else(|| anyhow!("no introspection_endpoint configured"));
+        }
+        let endpoint = self
+            .introspection_endpoint
+            .get_or_try_init(|| async {
+                let http_client = self.client().await.1.http_client.clone();
+                let mut discovery = self.config.issuer.clone();
+                discovery
+                    .path_segments_mut()
+                    .map_err(|_| anyhow!("invalid issuer URL"))?
+                    .pop_if_empty()
+                    .extend(&[".well-known", "openid-configuration"]);
+                let discovery: IntrospectionDiscovery = http_client
+                    .get(discovery)
+                    .timeout_opt(self.config.timeouts.discovery)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok::<_, anyhow::Error>(discovery.introspection_endpoint)
+            })
+            .await?;
+        endpoint
+            .clone()
+            .ok_or_else(|| anyhow!("OIDC provider does not advertise an introspection_endpoint"))
+    }
+
+    /// Validates an opaque access token with the provider. Inactive tokens are not an error, check `active`.
+    pub async fn introspect(&self, token: &str) -> Result<IntrospectionResponse> {
+        let endpoint = self.introspection_endpoint().await?;
+        let http_client = self.client().await.1.http_client.clone();
+        let observation = metrics::observe(&self.config.name, "introspection");
+        let result = async {
+            Ok::<_, anyhow::Error>(
+                http_client
+                    .post(endpoint)
+                    .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+                    .form(&[("token", token)])
+                    .timeout_opt(self.config.timeouts.token)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("token introspection rejected")?
+                    .json()
+                    .await?,
+            )
+        }
+        .await;
+        observation.finish_with(result)
+    }
+}
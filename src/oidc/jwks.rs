@@ -0,0 +1,120 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use biscuit::{jwk::JWKSet, Empty};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use openid::Client;
+use tokio::sync::RwLock;
+
+use super::{http::RequestBuilderExt, metrics, OidcConfig, OidcHandler};
+
+/// Minimum time between refetches triggered by an unknown `kid`
+const UNKNOWN_KID_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Keys of the provider. Fetches are timed with the monotonic clock, as they rate limit refetches rather than
+/// validate tokens.
+pub(super) struct JwksCache {
+    keys: Option<JWKSet<Empty>>,
+    last_fetch: Instant,
+}
+
+impl JwksCache {
+    pub(super) fn new(keys: Option<JWKSet<Empty>>) -> Self {
+        Self {
+            keys,
+            last_fetch: Instant::now(),
+        }
+    }
+
+    /// Takes keys fetched during rediscovery
+    pub(super) fn replace(&mut self, keys: Option<JWKSet<Empty>>) {
+        if keys.is_some() {
+            self.keys = keys;
+            self.last_fetch = Instant::now();
+        }
+    }
+}
+
+async fn refresh(
+    client: &RwLock<(DateTime<Utc>, Client)>,
+    cache: &RwLock<JwksCache>,
+    config: &OidcConfig,
+) -> Result<()> {
+    let (http_client, jwks_uri) = {
+        let client = client.read().await;
+        let Some(jwks_uri) = client.1.config().jwks_uri.clone() else {
+            return Ok(());
+        };
+        (client.1.http_client.clone(), jwks_uri)
+    };
+    cache.write().await.last_fetch = Instant::now();
+    let observation = metrics::observe(&config.name, "jwks");
+    let result = async {
+        Ok::<_, anyhow::Error>(
+            http_client
+                .get(jwks_uri)
+                .timeout_opt(config.timeouts.discovery)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<JWKSet<Empty>>()
+                .await?,
+        )
+    }
+    .await;
+    let keys = observation.finish_with(result)?;
+    // keep the client in sync so `validate_code` sees rotated keys too
+    client.write().await.1.jwks = Some(keys.clone());
+    cache.write().await.keys = Some(keys);
+    Ok(())
+}
+
+impl OidcHandler {
+    /// Refreshes the JWKS every `refresh_cycle` until all clones of this handler are dropped
+    pub(super) fn spawn_jwks_refresh(&self) {
+        let client = Arc::downgrade(&self.client);
+        let cache = Arc::downgrade(&self.jwks);
+        let refresh_cycle = self.config.refresh_cycle;
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(refresh_cycle).await;
+                let (Some(client), Some(cache)) = (client.upgrade(), cache.upgrade()) else {
+                    break;
+                };
+                if let Err(e) = refresh(&client, &cache, &config).await {
+                    warn!("failed to refresh OIDC JWKS: {e:#}");
+                }
+            }
+        });
+    }
+
+    pub(super) async fn jwks(&self) -> Option<JWKSet<Empty>> {
+        self.jwks.read().await.keys.clone()
+    }
+
+    /// Refetches the JWKS if `kid` is not known, rate limited. Returns the key set to use.
+    pub(super) async fn jwks_for_kid(&self, kid: Option<&str>) -> Option<JWKSet<Empty>> {
+        let keys = self.jwks().await;
+        let Some(kid) = kid else {
+            return keys;
+        };
+        if keys.as_ref().is_some_and(|x| x.find(kid).is_some()) {
+            return keys;
+        }
+        let recently_fetched =
+            self.jwks.read().await.last_fetch.elapsed() < UNKNOWN_KID_REFETCH_INTERVAL;
+        if recently_fetched {
+            return keys;
+        }
+        info!("refetching OIDC JWKS for unknown kid {kid}");
+        if let Err(e) = refresh(&self.client, &self.jwks, &self.config).await {
+            warn!("failed to refresh OIDC JWKS: {e:#}");
+        }
+        self.jwks().await
+    }
+}
@@ -0,0 +1,138 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Form, Router,
+};
+use futures::future::BoxFuture;
+use http::{header::CACHE_CONTROL, HeaderValue};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use super::OidcController;
+use crate::errors::{ApiError, ApiResult};
+
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// A provider-initiated logout, local sessions matching `sub` and/or `sid` should be ended
+#[derive(Clone, Debug)]
+pub struct LogoutEvent {
+    pub provider: String,
+    pub sub: Option<String>,
+    pub sid: Option<String>,
+}
+
+pub type LogoutCallback = Arc<dyn Fn(LogoutEvent) -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Serialize, Deserialize)]
+struct LogoutTokenClaims {
+    events: HashMap<String, Value>,
+    sid: Option<String>,
+    nonce: Option<String>,
+}
+
+#[derive(Clone)]
+struct LogoutState {
+    controller: Arc<OidcController>,
+    callback: LogoutCallback,
+}
+
+#[derive(Deserialize)]
+struct BackchannelLogout {
+    logout_token: String,
+}
+
+#[derive(Deserialize)]
+struct FrontchannelLogout {
+    iss: Option<Url>,
+    sid: Option<String>,
+}
+
+impl OidcController {
+    /// Routes `POST /:provider/backchannel-logout` and `GET /:provider/frontchannel-logout`, invoking `callback` for each validated logout
+    pub fn session_logout_router<S>(self: Arc<Self>, callback: LogoutCallback) -> Router<S> {
+        Router::new()
+            .route("/:provider/backchannel-logout", post(backchannel_logout))
+            .route("/:provider/frontchannel-logout", get(frontchannel_logout))
+            .with_state(LogoutState {
+                controller: self,
+                callback,
+            })
+    }
+}
+
+async fn backchannel_logout(
+    State(state): State<LogoutState>,
+    Path(provider): Path<String>,
+    Form(form): Form<BackchannelLogout>,
+) -> ApiResult<([(http::HeaderName, HeaderValue); 1], ())> {
+    let handler = state
+        .controller
+        .handler(&provider)
+        .ok_or(ApiError::NotFound)?;
+    // logout tokens are addressed to our client, not the audiences accepted for bearer tokens
+    let claims = handler
+        .decode_jwt::<LogoutTokenClaims>(
+            &form.logout_token,
+            std::slice::from_ref(&handler.config.client_id),
+        )
+        .await
+        .map_err(|e| {
+            debug!("rejected logout token: {e}");
+            ApiError::BadRequest("invalid logout token".to_string())
+        })?;
+    if !claims.private.events.contains_key(BACKCHANNEL_LOGOUT_EVENT) {
+        return Err(ApiError::BadRequest(
+            "logout token missing backchannel-logout event".to_string(),
+        ));
+    }
+    if claims.registered.issued_at.is_none() {
+        return Err(ApiError::BadRequest("logout token missing iat".to_string()));
+    }
+    if claims.private.nonce.is_some() {
+        return Err(ApiError::BadRequest(
+            "logout token must not contain a nonce".to_string(),
+        ));
+    }
+    let event = LogoutEvent {
+        provider,
+        sub: claims.registered.subject,
+        sid: claims.private.sid,
+    };
+    if event.sub.is_none() && event.sid.is_none() {
+        return Err(ApiError::BadRequest(
+            "logout token must contain sub or sid".to_string(),
+        ));
+    }
+    (state.callback)(event).await;
+    Ok(([(CACHE_CONTROL, HeaderValue::from_static("no-store"))], ()))
+}
+
+async fn frontchannel_logout(
+    State(state): State<LogoutState>,
+    Path(provider): Path<String>,
+    Query(query): Query<FrontchannelLogout>,
+) -> ApiResult<([(http::HeaderName, HeaderValue); 1], ())> {
+    let handler = state
+        .controller
+        .handler(&provider)
+        .ok_or(ApiError::NotFound)?;
+    if let Some(iss) = &query.iss {
+        if *iss != handler.config.issuer {
+            return Err(ApiError::BadRequest("issuer mismatch".to_string()));
+        }
+    }
+    if query.sid.is_none() {
+        return Err(ApiError::BadRequest("missing sid".to_string()));
+    }
+    (state.callback)(LogoutEvent {
+        provider,
+        sub: None,
+        sid: query.sid,
+    })
+    .await;
+    Ok(([(CACHE_CONTROL, HeaderValue::from_static("no-store"))], ()))
+}
@@ -0,0 +1,109 @@
+#[cfg(feature = "prometheus")]
+use std::{sync::OnceLock, time::Instant};
+
+#[cfg(feature = "prometheus")]
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+
+#[cfg(feature = "prometheus")]
+fn requests_total() -> &'static IntCounterVec {
+    static COUNTER: OnceLock<IntCounterVec> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter_vec!(
+            "oidc_requests_total",
+            "Requests to OIDC providers by outcome",
+            &["provider", "operation", "outcome"]
+        )
+        .unwrap()
+    })
+}
+
+#[cfg(feature = "prometheus")]
+fn request_duration() -> &'static HistogramVec {
+    static HISTOGRAM: OnceLock<HistogramVec> = OnceLock::new();
+    HISTOGRAM.get_or_init(|| {
+        register_histogram_vec!(
+            "oidc_request_duration_seconds",
+            "Latency of requests to OIDC providers",
+            &["provider", "operation"]
+        )
+        .unwrap()
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Outcome {
+    Success,
+    /// The provider refused the grant, i.e. an expired code or refresh token
+    Rejected,
+    Timeout,
+    Error,
+}
+
+impl Outcome {
+    pub(super) fn of<T>(result: &anyhow::Result<T>) -> Self {
+        let Err(e) = result else {
+            return Outcome::Success;
+        };
+        let timed_out = e.downcast_ref::<tokio::time::error::Elapsed>().is_some()
+            || e.downcast_ref::<reqwest::Error>()
+                .is_some_and(|x| x.is_timeout());
+        if timed_out {
+            Outcome::Timeout
+        } else {
+            Outcome::Error
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Rejected => "rejected",
+            Outcome::Timeout => "timeout",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// Records the latency and outcome of a single request to a provider
+pub(super) struct Observation {
+    #[cfg(feature = "prometheus")]
+    provider: String,
+    #[cfg(feature = "prometheus")]
+    operation: &'static str,
+    #[cfg(feature = "prometheus")]
+    start: Instant,
+}
+
+#[cfg_attr(not(feature = "prometheus"), allow(unused_variables))]
+pub(super) fn observe(provider: &str, operation: &'static str) -> Observation {
+    Observation {
+        #[cfg(feature = "prometheus")]
+        provider: provider.to_string(),
+        #[cfg(feature = "prometheus")]
+        operation,
+        #[cfg(feature = "prometheus")]
+        start: Instant::now(),
+    }
+}
+
+impl Observation {
+    #[cfg_attr(not(feature = "prometheus"), allow(unused_variables))]
+    pub(super) fn finish(self, outcome: Outcome) {
+        #[cfg(feature = "prometheus")]
+        {
+            request_duration()
+                .with_label_values(&[&self.provider, self.operation])
+                .observe(self.start.elapsed().as_secs_f64());
+            requests_total()
+                .with_label_values(&[&self.provider, self.operation, outcome.as_str()])
+                .inc();
+        }
+    }
+
+    /// Finishes with the outcome of `result`, passing it through
+    pub(super) fn finish_with<T>(self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        self.finish(Outcome::of(&result));
+        result
+    }
+}
@@ -0,0 +1,69 @@
+use std::{marker::PhantomData, time::Duration};
+
+use axum::extract::FromRequestParts;
+use http::{header::SET_COOKIE, request::Parts, HeaderName, HeaderValue};
+use jwt::FromBase64;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use crate::auth::SameSite;
+use crate::{
+    auth::{AuthParam, CookieConfig},
+    errors::{ApiError, ApiResult},
+};
+
+/// Cookie holding the session token
+pub type SessionCookieConfig = CookieConfig;
+
+/// Signs `T` with the [`crate::auth::AuthConfig`] of `P` and reads it back from the session cookie
+pub trait OidcSessionParam<T: Serialize + DeserializeOwned + FromBase64>: AuthParam<T> {
+    fn cookie() -> SessionCookieConfig;
+
+    /// Lifetime of the session token and cookie, defaults to the TTL of the [`crate::auth::AuthConfig`]
+    fn ttl() -> Duration {
+        Self::config().ttl()
+    }
+}
+
+/// Session established after `validate_code`, carrying the selected claims in a signed cookie
+pub struct OidcSession<T, P>(pub T, pub PhantomData<P>);
+
+impl<T, P> OidcSession<T, P>
+where
+    T: Serialize + DeserializeOwned + FromBase64,
+    P: OidcSessionParam<T>,
+{
+    /// `Set-Cookie` header starting a session for `value`, i.e. claims selected from the validated ID token,
+    /// expiring after [`OidcSessionParam::ttl`]
+    pub fn issue(value: &T) -> ApiResult<[(HeaderName, HeaderValue); 1]> {
+        let ttl = P::ttl();
+        let mut cookie = P::cookie();
+        cookie.max_age = Some(ttl);
+        let token = P::config().issue(value, ttl)?;
+        Ok([(SET_COOKIE, cookie.set_cookie(&token)?)])
+    }
+
+    /// `Set-Cookie` header ending the session
+    pub fn clear() -> ApiResult<[(HeaderName, HeaderValue); 1]> {
+        Ok([(SET_COOKIE, P::cookie().clear_cookie()?)])
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, P, S> FromRequestParts<S> for OidcSession<T, P>
+where
+    T: Serialize + DeserializeOwned + FromBase64 + Send + Sync,
+    P: OidcSessionParam<T>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let cookie = P::cookie();
+        let Some(token) = cookie.find(req) else {
+            return Err(ApiError::Unauthorized("missing session".to_string()));
+        };
+        let out = P::config_for(req)?.authenticate(token).await?;
+        P::authenticated(req, &out).await?;
+        Ok(Self(out, PhantomData))
+    }
+}
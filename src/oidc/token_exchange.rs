@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use openid::Bearer;
+
+use super::{http::RequestBuilderExt, metrics, OidcHandler};
+
+const TOKEN_EXCHANGE_GRANT: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+impl OidcHandler {
+    /// RFC 8693 token exchange, swapping an inbound access token for one scoped to `requested_audience`
+    pub async fn exchange_token(
+        &self,
+        subject_token: &str,
+        requested_audience: Option<&str>,
+    ) -> Result<Bearer> {
+        let (http_client, token_endpoint) = {
+            let client = self.client().await;
+            (
+                client.1.http_client.clone(),
+                client.1.config().token_endpoint.clone(),
+            )
+        };
+        let mut form = vec![
+            ("grant_type", TOKEN_EXCHANGE_GRANT),
+            ("subject_token", subject_token),
+            ("subject_token_type", ACCESS_TOKEN_TYPE),
+            ("requested_token_type", ACCESS_TOKEN_TYPE),
+        ];
+        if let Some(audience) = requested_audience {
+            form.push(("audience", audience));
+        }
+        let observation = metrics::observe(&self.config.name, "token_exchange");
+        let result = async {
+            Ok::<_, anyhow::Error>(
+                http_client
+                    .post(token_endpoint)
+                    .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+                    .form(&form)
+                    .timeout_opt(self.config.timeouts.token)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("token exchange rejected")?
+                    .json()
+                    .await?,
+            )
+        }
+        .await;
+        observation.finish_with(result)
+    }
+}
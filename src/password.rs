@@ -0,0 +1,200 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use rand::RngCore;
+
+use crate::{
+    auth::constant_time_eq,
+    errors::{ApiError, ApiResult, FieldError},
+};
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Argon2id parameters for password hashes in PHC format, i.e. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+/// Hashing is deliberately slow, call it from `spawn_blocking`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PasswordHasher {
+    /// Memory cost in KiB
+    pub m: u32,
+    /// Number of passes
+    pub t: u32,
+    /// Degree of parallelism
+    pub p: u32,
+}
+
+impl Default for PasswordHasher {
+    /// OWASP recommended minimum: m = 19 MiB, t = 2, p = 1
+    fn default() -> Self {
+        Self {
+            m: 19 * 1024,
+            t: 2,
+            p: 1,
+        }
+    }
+}
+
+struct ParsedHash {
+    params: PasswordHasher,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+fn parse_params(params: &str) -> impl Iterator<Item = Result<(&str, &str)>> {
+    params
+        .split(',')
+        .map(|x| x.split_once('=').context("malformed hash parameter"))
+}
+
+fn parse_hash(hash: &str) -> Result<ParsedHash> {
+    let mut parts = hash.split('$');
+    if parts.next() != Some("") {
+        bail!("not a PHC string");
+    }
+    if parts.next() != Some("argon2id") {
+        bail!("unsupported password hash");
+    }
+    if parts.next() != Some("v=19") {
+        bail!("unsupported argon2id version");
+    }
+    let mut params = PasswordHasher::default();
+    for param in parse_params(parts.next().context("missing argon2id parameters")?) {
+        let (name, value) = param?;
+        match name {
+            "m" => params.m = value.parse()?,
+            "t" => params.t = value.parse()?,
+            "p" => params.p = value.parse()?,
+            _ => bail!("unknown argon2id parameter {name}"),
+        }
+    }
+    let (Some(salt), Some(hash), None) = (parts.next(), parts.next(), parts.next()) else {
+        bail!("malformed PHC string");
+    };
+    Ok(ParsedHash {
+        params,
+        salt: STANDARD_NO_PAD.decode(salt)?,
+        hash: STANDARD_NO_PAD.decode(hash)?,
+    })
+}
+
+impl PasswordHasher {
+    fn derive(&self, password: &str, salt: &[u8], len: usize) -> Result<Vec<u8>> {
+        let mut out = vec![0; len];
+        let params = Params::new(self.m, self.t, self.p, Some(len)).map_err(|e| anyhow!("{e}"))?;
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut out)
+            .map_err(|e| anyhow!("{e}"))?;
+        Ok(out)
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String> {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let hash = self.derive(password, &salt, HASH_LEN)?;
+        Ok(format!(
+            "$argon2id$v=19$m={},t={},p={}${}${}",
+            self.m,
+            self.t,
+            self.p,
+            STANDARD_NO_PAD.encode(salt),
+            STANDARD_NO_PAD.encode(hash)
+        ))
+    }
+
+    /// Whether `hash` was made with other parameters, and should be replaced after the next successful login
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        parse_hash(hash).map_or(true, |x| x.params != *self)
+    }
+}
+
+/// [`PasswordHasher::hash`] with the default parameters
+pub fn hash_password(password: &str) -> Result<String> {
+    PasswordHasher::default().hash(password)
+}
+
+/// Checks `password` against a hash from [`PasswordHasher::hash`], with the parameters stored in the hash. Malformed hashes never match.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let Ok(parsed) = parse_hash(hash) else {
+        return false;
+    };
+    parsed
+        .params
+        .derive(password, &parsed.salt, parsed.hash.len())
+        .is_ok_and(|x| constant_time_eq(&x, &parsed.hash))
+}
+
+/// Requirements on new passwords, reported as [`ApiError::Validation`] of the `password` field
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Bounds hashing cost
+    pub max_length: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+        }
+    }
+}
+
+fn invalid(message: String, code: &str) -> ApiError {
+    ApiError::Validation(vec![FieldError {
+        field: "password".to_string(),
+        message,
+        code: code.to_string(),
+    }])
+}
+
+impl PasswordPolicy {
+    pub fn check(&self, password: &str) -> ApiResult<()> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(invalid(
+                format!("must be at least {} characters", self.min_length),
+                "length",
+            ));
+        }
+        if length > self.max_length {
+            return Err(invalid(
+                format!("must be at most {} characters", self.max_length),
+                "length",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rejects passwords found in the Have I Been Pwned breach corpus. Only the first 5 hex characters of the
+    /// password's SHA-1 are sent (k-anonymity).
+    #[cfg(feature = "hibp")]
+    pub async fn check_breached(&self, password: &str) -> ApiResult<()> {
+        use sha1::{Digest, Sha1};
+
+        let digest: String = Sha1::digest(password.as_bytes())
+            .iter()
+            .map(|x| format!("{x:02X}"))
+            .collect();
+        let (prefix, suffix) = digest.split_at(5);
+        let body = reqwest::Client::new()
+            .get(format!("https://api.pwnedpasswords.com/range/{prefix}"))
+            .header("Add-Padding", "true")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let breached = body.lines().any(|line| {
+            line.split_once(':')
+                .is_some_and(|(hash, count)| hash == suffix && count.trim() != "0")
+        });
+        if breached {
+            return Err(invalid(
+                "appears in a known data breach".to_string(),
+                "breached",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// `(label, DER)` for each PEM block in `pem`
+pub fn parse_pem(pem: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut blocks = vec![];
+    let mut current: Option<(String, String)> = None;
+    for line in pem.lines().map(str::trim) {
+        if let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|x| x.strip_suffix("-----"))
+        {
+            current = Some((label.to_string(), String::new()));
+        } else if let Some(label) = line
+            .strip_prefix("-----END ")
+            .and_then(|x| x.strip_suffix("-----"))
+        {
+            let Some((begin, body)) = current.take() else {
+                bail!("unmatched PEM END {label}");
+            };
+            if begin != label {
+                bail!("PEM BEGIN {begin} closed by END {label}");
+            }
+            let der = STANDARD
+                .decode(body)
+                .with_context(|| format!("invalid base64 in PEM {label}"))?;
+            blocks.push((begin, der));
+        } else if let Some((_, body)) = &mut current {
+            body.push_str(line);
+        }
+    }
+    if let Some((label, _)) = current {
+        bail!("unterminated PEM {label}");
+    }
+    Ok(blocks)
+}
@@ -0,0 +1,102 @@
+use std::sync::{Arc, OnceLock};
+
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "auth")]
+use serde_json::Value;
+
+use crate::errors::{ApiError, ApiResult};
+
+/// How the [`Principal`] of a request authenticated
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Token,
+    ApiKey,
+    OidcBearer,
+    ClientCert,
+}
+
+/// Identity of the current request, attached as an extension by the authenticating extractors so other
+/// extractors and middleware can consume it without knowing the claims type
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Principal {
+    pub subject: String,
+    pub display_name: Option<String>,
+    pub scopes: Vec<String>,
+    pub method: AuthMethod,
+}
+
+impl Principal {
+    pub fn new(subject: impl Into<String>, method: AuthMethod) -> Self {
+        Self {
+            subject: subject.into(),
+            display_name: None,
+            scopes: vec![],
+            method,
+        }
+    }
+
+    pub fn with_display_name(mut self, display_name: impl Into<String>) -> Self {
+        self.display_name = Some(display_name.into());
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|x| x == scope)
+    }
+
+    /// Principal of JWT-style claims: `sub`, `name` or `preferred_username`, and `scope`/`permissions`. `None`
+    /// without `sub`.
+    #[cfg(feature = "auth")]
+    pub(crate) fn from_claims(claims: &Value, method: AuthMethod) -> Option<Self> {
+        let subject = claims.get("sub")?.as_str()?;
+        let display_name = ["name", "preferred_username"]
+            .into_iter()
+            .find_map(|x| claims.get(x).and_then(Value::as_str));
+        Some(Self {
+            subject: subject.to_string(),
+            display_name: display_name.map(str::to_string),
+            scopes: crate::auth::scopes_of(claims),
+            method,
+        })
+    }
+
+    /// Attaches the principal to `req`, and to the [`PrincipalSlot`] of an outer middleware if present
+    pub fn attach(self, req: &mut Parts) {
+        if let Some(slot) = req.extensions.get::<PrincipalSlot>() {
+            let _ = slot.0.set(self.clone());
+        }
+        req.extensions.insert(self);
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        req.extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| ApiError::Unauthorized("unauthenticated".to_string()))
+    }
+}
+
+/// Inserted by middleware wrapping the router (i.e. [`crate::logger::Logger`]) to learn the [`Principal`]
+/// authenticated further in, since extensions added to the request don't flow back out
+#[derive(Clone, Debug, Default)]
+pub struct PrincipalSlot(Arc<OnceLock<Principal>>);
+
+impl PrincipalSlot {
+    /// The first principal attached while handling the request
+    pub fn get(&self) -> Option<&Principal> {
+        self.0.get()
+    }
+}
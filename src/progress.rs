@@ -0,0 +1,141 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{body, response::Response};
+use http::HeaderMap;
+use http_body::{Body, SizeHint};
+use hyper::body::Buf;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub bytes_sent: u64,
+    /// From the `size_hint` of the wrapped body, if exact
+    pub total_bytes: Option<u64>,
+    pub elapsed: Duration,
+    /// Set on the final callback, either at end of stream or when the body is dropped early
+    pub complete: bool,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Reports how much of the wrapped body has been polled out, i.e. for large downloads or per-user transfer accounting
+#[pin_project::pin_project(PinnedDrop)]
+pub struct ProgressBody<B> {
+    #[pin]
+    inner: B,
+    callback: ProgressCallback,
+    start: Instant,
+    bytes_sent: u64,
+    total_bytes: Option<u64>,
+    complete: bool,
+}
+
+impl<B: Body> ProgressBody<B> {
+    pub fn new(inner: B, callback: ProgressCallback) -> Self {
+        Self {
+            total_bytes: inner.size_hint().exact(),
+            inner,
+            callback,
+            start: Instant::now(),
+            bytes_sent: 0,
+            complete: false,
+        }
+    }
+}
+
+/// Wraps the body of `response` in a [`ProgressBody`]
+pub fn with_progress(
+    response: Response,
+    callback: impl Fn(&Progress) + Send + Sync + 'static,
+) -> Response {
+    let callback: ProgressCallback = Arc::new(callback);
+    response.map(|x| body::boxed(ProgressBody::new(x, callback)))
+}
+
+impl<B> ProgressBody<B> {
+    fn report(
+        callback: &ProgressCallback,
+        start: &Instant,
+        bytes_sent: u64,
+        total_bytes: Option<u64>,
+        complete: bool,
+    ) {
+        callback(&Progress {
+            bytes_sent,
+            total_bytes,
+            elapsed: start.elapsed(),
+            complete,
+        });
+    }
+}
+
+#[pin_project::pinned_drop]
+impl<B> PinnedDrop for ProgressBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        if !self.complete {
+            Self::report(
+                &self.callback,
+                &self.start,
+                self.bytes_sent,
+                self.total_bytes,
+                true,
+            );
+        }
+    }
+}
+
+impl<B: Body> Body for ProgressBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.project();
+        let result = this.inner.poll_data(cx);
+        match &result {
+            Poll::Ready(Some(Ok(data))) => {
+                *this.bytes_sent += data.remaining() as u64;
+                Self::report(
+                    this.callback,
+                    this.start,
+                    *this.bytes_sent,
+                    *this.total_bytes,
+                    false,
+                );
+            }
+            Poll::Ready(None) => {
+                *this.complete = true;
+                Self::report(
+                    this.callback,
+                    this.start,
+                    *this.bytes_sent,
+                    *this.total_bytes,
+                    true,
+                );
+            }
+            _ => (),
+        }
+        result
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        self.project().inner.poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    convert::Infallible,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::BoxBody,
+    extract::{ConnectInfo, MatchedPath},
+    response::{IntoResponse, Response},
+};
+use futures::Future;
+use http::{request::Parts, HeaderMap, HeaderName, HeaderValue, Request};
+use ipnet::IpNet;
+use log::warn;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    clock::{self, SharedClock},
+    errors::ApiError,
+    principal::Principal,
+};
+
+mod store;
+pub use store::{Decision, MemoryRateLimitStore, RateLimitState, RateLimitStore};
+
+/// How a [`Quota`] refills
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitAlgorithm {
+    /// Refills continuously, allowing bursts up to the limit
+    TokenBucket,
+    /// Counts requests in the current and previous period, weighting the previous by its overlap
+    SlidingWindow,
+}
+
+/// `limit` requests per `period`, a `limit` of 0 denies every request without a `Retry-After`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: u32,
+    pub period: Duration,
+    pub algorithm: RateLimitAlgorithm,
+}
+
+impl Quota {
+    /// Token bucket quota
+    pub fn new(limit: u32, period: Duration) -> Self {
+        Self {
+            limit,
+            period,
+            algorithm: RateLimitAlgorithm::TokenBucket,
+        }
+    }
+
+    pub fn per_second(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    pub fn with_algorithm(mut self, algorithm: RateLimitAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+}
+
+pub type RateLimitKeyFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+/// What requests are counted together
+#[derive(Clone)]
+pub enum RateLimitKey {
+    /// Client IP, taken from `X-Forwarded-For` when the peer is a trusted proxy
+    Ip,
+    /// Subject of the [`Principal`], falling back to the client IP for anonymous requests. Requires the principal
+    /// to be attached before the layer runs, i.e. by an [`crate::auth::AuthLayer`] wrapping it.
+    Principal,
+    /// Matched route, limiting all clients together
+    Route,
+    /// `None` skips rate limiting for the request
+    Custom(Arc<RateLimitKeyFn>),
+}
+
+impl fmt::Debug for RateLimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimitKey::Ip => f.write_str("Ip"),
+            RateLimitKey::Principal => f.write_str("Principal"),
+            RateLimitKey::Route => f.write_str("Route"),
+            RateLimitKey::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+struct RateLimitConfig {
+    store: Box<dyn RateLimitStore>,
+    quota: Quota,
+    key: RateLimitKey,
+    routes: Vec<(String, Quota)>,
+    trusted_proxies: Vec<IpNet>,
+    clock: SharedClock,
+}
+
+/// Route of the request, as matched by the router if known
+pub(crate) fn route(req: &Parts) -> &str {
+    req.extensions
+        .get::<MatchedPath>()
+        .map(|x| x.as_str())
+        .unwrap_or_else(|| req.uri.path())
+}
+
+impl RateLimitConfig {
+    fn client_ip(&self, req: &Parts) -> Option<IpAddr> {
+        let peer = req.extensions.get::<ConnectInfo<SocketAddr>>()?.0.ip();
+        let trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|x| x.contains(ip));
+        if !trusted(&peer) {
+            return Some(peer);
+        }
+        // the rightmost address not added by one of our proxies
+        let mut client = peer;
+        for forwarded in req.headers.get_all("x-forwarded-for").iter().rev() {
+            let Ok(forwarded) = forwarded.to_str() else {
+                break;
+            };
+            for ip in forwarded.rsplit(',') {
+                let Ok(ip) = ip.trim().parse() else {
+                    return Some(client);
+                };
+                client = ip;
+                if !trusted(&client) {
+                    return Some(client);
+                }
+            }
+        }
+        Some(client)
+    }
+
+    fn key(&self, req: &Parts) -> Option<String> {
+        let ip = || self.client_ip(req).map(|x| format!("ip:{x}"));
+        match &self.key {
+            RateLimitKey::Ip => ip(),
+            RateLimitKey::Principal => match req.extensions.get::<Principal>() {
+                Some(principal) => Some(format!("principal:{}", principal.subject)),
+                None => ip(),
+            },
+            RateLimitKey::Route => Some(String::new()),
+            RateLimitKey::Custom(key) => key(req).map(|x| format!("custom:{x}")),
+        }
+    }
+
+    /// Quota of the request and the key counting it, `None` if not limited
+    fn quota(&self, req: &Parts) -> Option<(Quota, String)> {
+        let key = self.key(req)?;
+        let route = route(req);
+        match self.routes.iter().find(|(x, _)| x == route) {
+            Some((route, quota)) => Some((*quota, format!("route:{route}|{key}"))),
+            None if matches!(self.key, RateLimitKey::Route) => {
+                Some((self.quota, format!("route:{route}")))
+            }
+            None => Some((self.quota, key)),
+        }
+    }
+}
+
+/// Rounded up, so clients waiting that long are allowed
+fn header_seconds(duration: Duration) -> HeaderValue {
+    HeaderValue::from(duration.as_secs() + u64::from(duration.subsec_nanos() > 0))
+}
+
+fn rate_limit_headers(decision: &Decision) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        HeaderName::from_static("ratelimit-limit"),
+        HeaderValue::from(decision.limit),
+    );
+    headers.insert(
+        HeaderName::from_static("ratelimit-remaining"),
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        HeaderName::from_static("ratelimit-reset"),
+        header_seconds(decision.reset),
+    );
+    headers
+}
+
+/// Limits requests per client, principal, or route, answering `429 Too Many Requests` with `Retry-After` once a
+/// [`Quota`] is used up. Responses carry `RateLimit-Limit`, `RateLimit-Remaining`, and `RateLimit-Reset`.
+/// Requests are let through if the store fails.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    config: Arc<RateLimitConfig>,
+}
+
+impl RateLimitLayer {
+    /// Limits each client IP to `quota`
+    pub fn new(store: impl RateLimitStore, quota: Quota) -> Self {
+        Self {
+            config: Arc::new(RateLimitConfig {
+                store: Box::new(store),
+                quota,
+                key: RateLimitKey::Ip,
+                routes: vec![],
+                trusted_proxies: vec![],
+                clock: clock::system(),
+            }),
+        }
+    }
+
+    fn config_mut(&mut self) -> &mut RateLimitConfig {
+        Arc::get_mut(&mut self.config).expect("RateLimitLayer configured after being cloned")
+    }
+
+    pub fn with_key(mut self, key: RateLimitKey) -> Self {
+        self.config_mut().key = key;
+        self
+    }
+
+    /// Separate `quota` for requests to `route`, a route as passed to the router or a path when layered outside
+    /// of it
+    pub fn with_route(mut self, route: impl Into<String>, quota: Quota) -> Self {
+        self.config_mut().routes.push((route.into(), quota));
+        self
+    }
+
+    /// Peers in `proxy` are trusted to report the client in `X-Forwarded-For`
+    pub fn with_trusted_proxy(mut self, proxy: IpNet) -> Self {
+        self.config_mut().trusted_proxies.push(proxy);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.config_mut().clock = clock;
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RateLimit {
+            config: self.config.clone(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    config: Arc<RateLimitConfig>,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RateLimit<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let config = self.config.clone();
+        // the ready service must handle this request, leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let decision = match config.quota(&parts) {
+                Some((quota, key)) => {
+                    match config.store.acquire(&key, &quota, config.clock.now()).await {
+                        Ok(decision) => Some(decision),
+                        Err(e) => {
+                            warn!("rate limit store failed, allowing request: {e:#}");
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
+            let Some(decision) = decision else {
+                return inner.call(Request::from_parts(parts, body)).await;
+            };
+            if !decision.allowed {
+                let mut response = ApiError::TooManyRequests {
+                    retry_after: decision.retry_after,
+                }
+                .into_response();
+                response.headers_mut().extend(rate_limit_headers(&decision));
+                return Ok(response);
+            }
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            response.headers_mut().extend(rate_limit_headers(&decision));
+            Ok(response)
+        })
+    }
+}
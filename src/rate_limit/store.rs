@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use super::{Quota, RateLimitAlgorithm};
+
+/// Outcome of taking one request from a [`Quota`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the quota is fully available again
+    pub reset: Duration,
+    /// Until the next request would be allowed, set when denied
+    pub retry_after: Option<Duration>,
+}
+
+/// Counters for [`super::RateLimitLayer`], shared between instances to limit across a deployment. Each call
+/// must take one request from `key` atomically, i.e. in a script for Redis.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync + 'static {
+    async fn acquire(&self, key: &str, quota: &Quota, now: DateTime<Utc>) -> Result<Decision>;
+}
+
+/// State of one key, as kept by [`MemoryRateLimitStore`]
+#[derive(Clone, Copy, Debug)]
+pub enum RateLimitState {
+    TokenBucket {
+        tokens: f64,
+        updated: DateTime<Utc>,
+    },
+    SlidingWindow {
+        /// Index of the current window since the epoch
+        window: i64,
+        current: u32,
+        previous: u32,
+    },
+}
+
+/// Negative values are zero, infinite and oversized ones are capped so they still fit a `Retry-After`
+fn seconds(x: f64) -> Duration {
+    Duration::try_from_secs_f64(x.max(0.0)).unwrap_or(Duration::from_secs(u32::MAX.into()))
+}
+
+impl RateLimitState {
+    /// Takes one request from `quota`, starting from a full quota for `None`
+    pub fn acquire(state: Option<Self>, quota: &Quota, now: DateTime<Utc>) -> (Self, Decision) {
+        if quota.limit == 0 {
+            // never refills, so there is no time to retry after
+            let state = match quota.algorithm {
+                RateLimitAlgorithm::TokenBucket => RateLimitState::TokenBucket {
+                    tokens: 0.0,
+                    updated: now,
+                },
+                RateLimitAlgorithm::SlidingWindow => RateLimitState::SlidingWindow {
+                    window: 0,
+                    current: 0,
+                    previous: 0,
+                },
+            };
+            let decision = Decision {
+                allowed: false,
+                limit: 0,
+                remaining: 0,
+                reset: Duration::ZERO,
+                retry_after: None,
+            };
+            return (state, decision);
+        }
+        let limit = quota.limit as f64;
+        let period = quota.period.as_secs_f64().max(f64::EPSILON);
+        match quota.algorithm {
+            RateLimitAlgorithm::TokenBucket => {
+                let rate = limit / period;
+                let mut tokens = match state {
+                    Some(RateLimitState::TokenBucket { tokens, updated }) => {
+                        let elapsed = (now - updated).num_milliseconds().max(0) as f64 / 1000.0;
+                        (tokens + elapsed * rate).min(limit)
+                    }
+                    _ => limit,
+                };
+                let allowed = tokens >= 1.0;
+                if allowed {
+                    tokens -= 1.0;
+                }
+                let decision = Decision {
+                    allowed,
+                    limit: quota.limit,
+                    remaining: tokens.floor() as u32,
+                    reset: seconds((limit - tokens) / rate),
+                    retry_after: (!allowed).then(|| seconds((1.0 - tokens) / rate)),
+                };
+                (
+                    RateLimitState::TokenBucket {
+                        tokens,
+                        updated: now,
+                    },
+                    decision,
+                )
+            }
+            RateLimitAlgorithm::SlidingWindow => {
+                let period_ms = (period * 1000.0) as i64;
+                let now_ms = now.timestamp_millis();
+                let window = now_ms.div_euclid(period_ms.max(1));
+                let (mut current, previous) = match state {
+                    Some(RateLimitState::SlidingWindow {
+                        window: last,
+                        current,
+                        previous,
+                    }) => match window - last {
+                        0 => (current, previous),
+                        1 => (0, current),
+                        _ => (0, 0),
+                    },
+                    _ => (0, 0),
+                };
+                let into_window = (now_ms - window * period_ms) as f64 / 1000.0;
+                let weight = 1.0 - into_window / period;
+                let estimate = previous as f64 * weight + current as f64;
+                let allowed = estimate + 1.0 <= limit;
+                if allowed {
+                    current += 1;
+                }
+                let retry_after = (!allowed).then(|| {
+                    let room = limit - current as f64 - 1.0;
+                    if room < 0.0 || previous == 0 {
+                        // only once the current window becomes the previous one
+                        seconds(period - into_window)
+                    } else {
+                        seconds(period * (1.0 - room / previous as f64) - into_window)
+                    }
+                });
+                let used = previous as f64 * weight + current as f64;
+                let reset = if current > 0 {
+                    2.0 * period - into_window
+                } else if previous > 0 {
+                    period - into_window
+                } else {
+                    0.0
+                };
+                let decision = Decision {
+                    allowed,
+                    limit: quota.limit,
+                    remaining: (limit - used.ceil()).max(0.0) as u32,
+                    reset: seconds(reset),
+                    retry_after,
+                };
+                (
+                    RateLimitState::SlidingWindow {
+                        window,
+                        current,
+                        previous,
+                    },
+                    decision,
+                )
+            }
+        }
+    }
+}
+
+const PRUNE_INTERVAL: u64 = 1024;
+
+/// Counters in process memory, not shared between instances
+pub struct MemoryRateLimitStore {
+    states: Mutex<HashMap<String, (RateLimitState, DateTime<Utc>)>>,
+    calls: AtomicU64,
+}
+
+impl Default for MemoryRateLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self {
+            states: Mutex::new(HashMap::new()),
+            calls: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn acquire(&self, key: &str, quota: &Quota, now: DateTime<Utc>) -> Result<Decision> {
+        let mut states = self.states.lock().unwrap();
+        if self
+            .calls
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(PRUNE_INTERVAL)
+        {
+            states.retain(|_, (_, expires)| *expires > now);
+        }
+        let state = states.get(key).map(|(state, _)| *state);
+        let (state, decision) = RateLimitState::acquire(state, quota, now);
+        // idle keys are back to a full quota after two periods at most
+        let expires = chrono::Duration::from_std(quota.period.saturating_mul(2))
+            .ok()
+            .and_then(|x| now.checked_add_signed(x))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        states.insert(key.to_string(), (state, expires));
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(ms: i64) -> DateTime<Utc> {
+        // on a window boundary for any whole second period
+        Utc.timestamp_millis_opt(1_700_000_000_000 + ms).unwrap()
+    }
+
+    fn take(
+        state: Option<RateLimitState>,
+        quota: &Quota,
+        ms: i64,
+    ) -> (Option<RateLimitState>, Decision) {
+        let (state, decision) = RateLimitState::acquire(state, quota, at(ms));
+        (Some(state), decision)
+    }
+
+    #[test]
+    fn token_bucket_limit() {
+        let quota = Quota::new(2, Duration::from_secs(2));
+        let (state, first) = take(None, &quota, 0);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset, Duration::from_secs(1));
+        assert_eq!(first.retry_after, None);
+
+        let (state, second) = take(state, &quota, 0);
+        assert!(second.allowed);
+        assert_eq!(second.remaining, 0);
+        assert_eq!(second.reset, Duration::from_secs(2));
+
+        let (state, denied) = take(state, &quota, 0);
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        assert_eq!(denied.retry_after, Some(Duration::from_secs(1)));
+
+        assert!(!take(state, &quota, 999).1.allowed);
+        let (_, retried) = take(state, &quota, 1000);
+        assert!(retried.allowed);
+        assert_eq!(retried.remaining, 0);
+
+        // refills up to the limit only
+        let (_, idle) = take(state, &quota, 3_600_000);
+        assert!(idle.allowed);
+        assert_eq!(idle.remaining, 1);
+    }
+
+    #[test]
+    fn sliding_window_limit() {
+        let quota = Quota::new(2, Duration::from_secs(10))
+            .with_algorithm(RateLimitAlgorithm::SlidingWindow);
+        let (state, first) = take(None, &quota, 0);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset, Duration::from_secs(20));
+
+        let (state, second) = take(state, &quota, 0);
+        assert!(second.allowed);
+        assert_eq!(second.remaining, 0);
+
+        // no room in the current window until it becomes the previous one
+        let (full, denied) = take(state, &quota, 0);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Some(Duration::from_secs(10)));
+
+        // the previous window weighs in until enough of it has slid out
+        let (_, denied) = take(full, &quota, 10_000);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Some(Duration::from_secs(5)));
+        assert!(!take(full, &quota, 14_999).1.allowed);
+        let (_, retried) = take(full, &quota, 15_000);
+        assert!(retried.allowed);
+        assert_eq!(retried.remaining, 0);
+
+        let (_, idle) = take(full, &quota, 20_000);
+        assert!(idle.allowed);
+        assert_eq!(idle.remaining, 1);
+    }
+
+    #[test]
+    fn zero_limit() {
+        for algorithm in [
+            RateLimitAlgorithm::TokenBucket,
+            RateLimitAlgorithm::SlidingWindow,
+        ] {
+            let quota = Quota::new(0, Duration::from_secs(1)).with_algorithm(algorithm);
+            let (state, decision) = take(None, &quota, 0);
+            assert!(!decision.allowed);
+            assert_eq!(decision.retry_after, None);
+            assert!(!take(state, &quota, 3_600_000).1.allowed);
+        }
+    }
+}
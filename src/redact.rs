@@ -0,0 +1,123 @@
+use std::{
+    collections::HashSet,
+    sync::{OnceLock, RwLock},
+};
+
+use http::{
+    header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE},
+    HeaderMap, HeaderName, HeaderValue, Uri,
+};
+
+pub const REDACTED: &str = "[redacted]";
+
+struct Registry {
+    headers: HashSet<HeaderName>,
+    query_params: HashSet<String>,
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        RwLock::new(Registry {
+            headers: [
+                AUTHORIZATION,
+                PROXY_AUTHORIZATION,
+                COOKIE,
+                SET_COOKIE,
+                HeaderName::from_static("x-api-key"),
+            ]
+            .into_iter()
+            .collect(),
+            query_params: [
+                "code",
+                "state",
+                "access_token",
+                "refresh_token",
+                "id_token",
+                "id_token_hint",
+                "token",
+                "client_secret",
+                "password",
+                "secret",
+                "api_key",
+                "apikey",
+                "key",
+                "signature",
+                "sig",
+                "x-amz-credential",
+                "x-amz-signature",
+                "x-amz-security-token",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        })
+    })
+}
+
+/// Marks a header as sensitive for every telemetry path in this crate
+pub fn register_header(name: HeaderName) {
+    registry().write().unwrap().headers.insert(name);
+}
+
+/// Marks a query parameter as sensitive for every telemetry path in this crate
+pub fn register_query_param(name: impl Into<String>) {
+    registry()
+        .write()
+        .unwrap()
+        .query_params
+        .insert(name.into().to_ascii_lowercase());
+}
+
+pub fn is_sensitive_header(name: &HeaderName) -> bool {
+    registry().read().unwrap().headers.contains(name)
+}
+
+pub fn is_sensitive_query_param(name: &str) -> bool {
+    registry()
+        .read()
+        .unwrap()
+        .query_params
+        .contains(&name.to_ascii_lowercase())
+}
+
+/// Copies `headers`, replacing the value of each sensitive header
+pub fn redact_headers(headers: &HeaderMap) -> HeaderMap {
+    let registry = registry().read().unwrap();
+    let mut out = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        if registry.headers.contains(name) {
+            out.append(name.clone(), HeaderValue::from_static(REDACTED));
+        } else {
+            out.append(name.clone(), value.clone());
+        }
+    }
+    out
+}
+
+/// Rewrites a raw query string, replacing the value of each sensitive parameter
+pub fn redact_query(query: &str) -> String {
+    let registry = registry().read().unwrap();
+    let mut out = url::form_urlencoded::Serializer::new(String::new());
+    for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if registry.query_params.contains(&name.to_ascii_lowercase()) {
+            out.append_pair(&name, REDACTED);
+        } else {
+            out.append_pair(&name, &value);
+        }
+    }
+    out.finish()
+}
+
+/// Copies `uri`, rewriting its query per [`redact_query`]
+pub fn redact_uri(uri: &Uri) -> Uri {
+    let Some(query) = uri.query().filter(|x| !x.is_empty()) else {
+        return uri.clone();
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = format!("{}?{}", uri.path(), redact_query(query))
+        .parse()
+        .or_else(|_| uri.path().parse())
+        .ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| Uri::from_static("/"))
+}
@@ -0,0 +1,195 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use tokio::sync::watch;
+
+type Changed<C> = Box<dyn Fn(&C, &C) -> bool + Send + Sync>;
+type Apply<C> = Box<dyn Fn(&C) -> Result<()> + Send + Sync>;
+type Parse<C> = Box<dyn Fn(&str) -> Result<C> + Send + Sync>;
+
+struct Section<C> {
+    name: String,
+    changed: Changed<C>,
+    /// `None` if changes to this section require a restart
+    apply: Option<Apply<C>>,
+    /// Config this section was last applied from, kept across failed applies and restart-required changes so they
+    /// are retried and reported again on the next reload
+    current: Arc<C>,
+}
+
+/// Outcome of a single reload, by section name
+#[derive(Clone, Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub requires_restart: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.requires_restart.is_empty() && self.failed.is_empty()
+    }
+}
+
+/// Watches a config file and applies the changed sections that can be hot-reloaded (i.e. through the watch channels
+/// consumed by `TlsIncoming`), reporting the ones that need a restart.
+pub struct ReloadManager<C> {
+    path: PathBuf,
+    parse: Parse<C>,
+    sections: Vec<Section<C>>,
+    current: Arc<C>,
+    modified: Option<SystemTime>,
+    /// Set while a section failed to apply, to retry without waiting for the file to change
+    retry: bool,
+}
+
+impl<C: Send + Sync + 'static> ReloadManager<C> {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        current: C,
+        parse: impl Fn(&str) -> Result<C> + Send + Sync + 'static,
+    ) -> Self {
+        let path = path.into();
+        Self {
+            modified: std::fs::metadata(&path).and_then(|x| x.modified()).ok(),
+            path,
+            parse: Box::new(parse),
+            sections: vec![],
+            current: Arc::new(current),
+            retry: false,
+        }
+    }
+
+    /// Publishes the extracted section to `sender` whenever it changes
+    pub fn hot<T: PartialEq + Send + Sync + 'static>(
+        self,
+        name: impl Into<String>,
+        extract: impl Fn(&C) -> T + Send + Sync + 'static,
+        sender: watch::Sender<T>,
+    ) -> Self {
+        let extract = Arc::new(extract);
+        let extract2 = extract.clone();
+        self.hot_with(
+            name,
+            move |old, new| extract(old) != extract(new),
+            move |new| {
+                sender.send_replace(extract2(new));
+                Ok(())
+            },
+        )
+    }
+
+    pub fn hot_with(
+        mut self,
+        name: impl Into<String>,
+        changed: impl Fn(&C, &C) -> bool + Send + Sync + 'static,
+        apply: impl Fn(&C) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.sections.push(Section {
+            name: name.into(),
+            changed: Box::new(changed),
+            apply: Some(Box::new(apply)),
+            current: self.current.clone(),
+        });
+        self
+    }
+
+    /// Changes to this section are reported but not applied, i.e. listen addresses
+    pub fn restart_required<T: PartialEq>(
+        mut self,
+        name: impl Into<String>,
+        extract: impl Fn(&C) -> T + Send + Sync + 'static,
+    ) -> Self {
+        self.sections.push(Section {
+            name: name.into(),
+            changed: Box::new(move |old, new| extract(old) != extract(new)),
+            apply: None,
+            current: self.current.clone(),
+        });
+        self
+    }
+
+    /// Rereads the config file and applies whatever changed, regardless of modification time. Sections that failed
+    /// to apply or require a restart are compared against their last applied config, so they're retried and
+    /// reported on each reload until that succeeds or the change is reverted.
+    pub fn reload(&mut self) -> Result<ReloadReport> {
+        let raw = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        let new = Arc::new(
+            (self.parse)(&raw)
+                .with_context(|| format!("failed to parse {}", self.path.display()))?,
+        );
+        let mut report = ReloadReport::default();
+        for section in &mut self.sections {
+            if !(section.changed)(&section.current, &new) {
+                section.current = new.clone();
+                continue;
+            }
+            match &section.apply {
+                None => report.requires_restart.push(section.name.clone()),
+                Some(apply) => match apply(&new) {
+                    Ok(()) => {
+                        report.applied.push(section.name.clone());
+                        section.current = new.clone();
+                    }
+                    Err(e) => report.failed.push((section.name.clone(), format!("{e:#}"))),
+                },
+            }
+        }
+        self.current = new;
+        Ok(report)
+    }
+
+    fn poll(&mut self) -> Option<ReloadReport> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|x| x.modified())
+            .ok();
+        if modified.is_none() || (modified == self.modified && !self.retry) {
+            return None;
+        }
+        self.modified = modified;
+        match self.reload() {
+            Ok(report) => {
+                self.retry = !report.failed.is_empty();
+                if !report.applied.is_empty() {
+                    info!("hot-reloaded config sections: {:?}", report.applied);
+                }
+                if !report.requires_restart.is_empty() {
+                    warn!(
+                        "config sections changed but require a restart: {:?}",
+                        report.requires_restart
+                    );
+                }
+                for (name, e) in &report.failed {
+                    error!("failed to reload config section {name}: {e}");
+                }
+                Some(report)
+            }
+            Err(e) => {
+                error!("failed to reload config: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Polls the config file for modifications, publishing a report for each reload
+    pub fn spawn(mut self, poll_interval: Duration) -> watch::Receiver<ReloadReport> {
+        let (sender, receiver) = watch::channel(ReloadReport::default());
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(poll_interval).await;
+                if let Some(report) = self.poll() {
+                    if sender.send(report).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+}
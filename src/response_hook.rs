@@ -0,0 +1,136 @@
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::extract::MatchedPath;
+use futures::Future;
+use http::{Extensions, HeaderMap, Method, Request, Response, StatusCode, Uri};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::redact::{redact_headers, redact_uri};
+
+/// Request metadata captured before the inner service consumes the request
+#[derive(Clone, Debug)]
+pub struct RequestInfo {
+    pub method: Method,
+    /// Request URI, with sensitive query parameters replaced per [`crate::redact`]
+    pub uri: Uri,
+    pub matched_path: Option<String>,
+    /// Request headers, with sensitive values replaced per [`crate::redact`]
+    pub headers: HeaderMap,
+}
+
+impl RequestInfo {
+    pub(crate) fn new(
+        method: &Method,
+        uri: &Uri,
+        extensions: &Extensions,
+        headers: &HeaderMap,
+    ) -> Self {
+        Self {
+            method: method.clone(),
+            uri: redact_uri(uri),
+            matched_path: extensions
+                .get::<MatchedPath>()
+                .map(|x| x.as_str().to_string()),
+            headers: redact_headers(headers),
+        }
+    }
+}
+
+pub type ResponseHookFn = Arc<dyn Fn(&RequestInfo, StatusCode, &mut HeaderMap) + Send + Sync>;
+
+/// Runs a closure over the headers of every successful response, i.e. to add server/region headers or strip internal ones
+#[derive(Clone)]
+pub struct ResponseHookLayer {
+    hook: ResponseHookFn,
+}
+
+impl ResponseHookLayer {
+    pub fn new(
+        hook: impl Fn(&RequestInfo, StatusCode, &mut HeaderMap) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            hook: Arc::new(hook),
+        }
+    }
+}
+
+impl<S> Layer<S> for ResponseHookLayer {
+    type Service = ResponseHook<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ResponseHook::new(self.hook.clone(), service)
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseHook<S> {
+    hook: ResponseHookFn,
+    inner: S,
+}
+
+impl<S> ResponseHook<S> {
+    pub fn new(hook: ResponseHookFn, inner: S) -> Self {
+        Self { hook, inner }
+    }
+}
+
+#[pin_project::pin_project]
+pub struct ResponseHookFuture<S, ReqBody, ResBody>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    info: Option<RequestInfo>,
+    hook: ResponseHookFn,
+    #[pin]
+    inner: S::Future,
+}
+
+impl<S, ReqBody, ResBody> Future for ResponseHookFuture<S, ReqBody, ResBody>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Output = <S::Future as Future>::Output;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        match this.inner.poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(mut response)) => {
+                if let Some(info) = this.info.take() {
+                    let status = response.status();
+                    (this.hook)(&info, status, response.headers_mut());
+                }
+                Poll::Ready(Ok(response))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for ResponseHook<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: fmt::Display + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = ResponseHookFuture<S, ReqBody, ResBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let info = RequestInfo::new(req.method(), req.uri(), req.extensions(), req.headers());
+        ResponseHookFuture {
+            info: Some(info),
+            hook: self.hook.clone(),
+            inner: self.inner.call(req),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use axum::{
+    body::BoxBody,
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use futures::Future;
+use hmac::{Hmac, Mac};
+use http::{header::SET_COOKIE, request::Parts, HeaderValue, Request};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    auth::CookieConfig,
+    clock::{self, SharedClock},
+    errors::{ApiError, ApiResult},
+};
+
+#[cfg(feature = "session-redis")]
+mod redis_store;
+mod store;
+#[cfg(feature = "session-redis")]
+pub use redis_store::RedisStore;
+pub use store::{MemoryStore, SessionRecord, SessionStore};
+
+pub type SessionData = HashMap<String, Value>;
+
+fn generate_session_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Default)]
+struct State {
+    id: Option<String>,
+    expires: Option<DateTime<Utc>>,
+    data: SessionData,
+    changed: bool,
+    destroyed: bool,
+    /// Previous ID to delete after [`Session::cycle_id`]
+    stale_id: Option<String>,
+}
+
+/// Session of the current request, loaded by [`SessionLayer`] and saved after the response if changed
+#[derive(Clone)]
+pub struct Session(Arc<Mutex<State>>);
+
+impl Session {
+    /// `None` until the session is first saved
+    pub fn id(&self) -> Option<String> {
+        self.0.lock().unwrap().id.clone()
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> ApiResult<Option<T>> {
+        let state = self.0.lock().unwrap();
+        let Some(value) = state.data.get(key) else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_value(value.clone())?))
+    }
+
+    pub fn insert<T: Serialize>(&self, key: impl Into<String>, value: &T) -> ApiResult<()> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.0.lock().unwrap();
+        state.data.insert(key.into(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.0.lock().unwrap();
+        let out = state.data.remove(key);
+        state.changed |= out.is_some();
+        out
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.data.clear();
+        state.changed = true;
+    }
+
+    /// Deletes the session from the store and the client, i.e. on logout
+    pub fn destroy(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.data.clear();
+        state.destroyed = true;
+    }
+
+    /// Moves the data to a new ID, i.e. on login to prevent session fixation
+    pub fn cycle_id(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(id) = state.id.take() {
+            state.stale_id = Some(id);
+        }
+        state.changed = true;
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Session {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        req.extensions
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| ApiError::Other(anyhow!("Session extracted without SessionLayer")))
+    }
+}
+
+struct SessionConfig {
+    store: Arc<dyn SessionStore>,
+    key: Hmac<Sha256>,
+    cookie: CookieConfig,
+    ttl: Duration,
+    rolling: bool,
+    clock: SharedClock,
+}
+
+impl SessionConfig {
+    fn sign(&self, id: &str) -> String {
+        let mut mac = self.key.clone();
+        mac.update(id.as_bytes());
+        format!(
+            "{id}.{}",
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    /// Session ID of a cookie value with a valid signature
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let mut mac = self.key.clone();
+        mac.update(id.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        Some(id)
+    }
+
+    async fn load(&self, req: &Parts) -> ApiResult<State> {
+        let Some(id) = self.cookie.find(req).and_then(|x| self.verify(x)) else {
+            return Ok(State::default());
+        };
+        match self.store.load(id).await.map_err(ApiError::Other)? {
+            Some(record) if record.expires > self.clock.now() => Ok(State {
+                id: Some(id.to_string()),
+                expires: Some(record.expires),
+                data: record.data,
+                ..Default::default()
+            }),
+            _ => Ok(State::default()),
+        }
+    }
+
+    /// Persists the session, returning the `Set-Cookie` value if the cookie must change
+    async fn commit(&self, session: &Session) -> ApiResult<Option<HeaderValue>> {
+        let (id, stale_id, changed, destroyed, expires, data) = {
+            let state = session.0.lock().unwrap();
+            let data = (state.changed || self.rolling).then(|| state.data.clone());
+            (
+                state.id.clone(),
+                state.stale_id.clone(),
+                state.changed,
+                state.destroyed,
+                state.expires,
+                data,
+            )
+        };
+        if destroyed {
+            let Some(id) = id.or(stale_id) else {
+                return Ok(None);
+            };
+            self.store.delete(&id).await.map_err(ApiError::Other)?;
+            return Ok(Some(self.cookie.clear_cookie()?));
+        }
+        let renew = self.rolling && id.is_some();
+        let Some(data) = data.filter(|_| changed || renew) else {
+            return Ok(None);
+        };
+        if id.is_none() && stale_id.is_none() && data.is_empty() {
+            return Ok(None);
+        }
+        let expires = match expires {
+            Some(expires) if !self.rolling => expires,
+            _ => {
+                self.clock.now()
+                    + chrono::Duration::from_std(self.ttl).map_err(|e| ApiError::Other(e.into()))?
+            }
+        };
+        let record = SessionRecord { data, expires };
+        if let Some(stale_id) = &stale_id {
+            self.store.delete(stale_id).await.map_err(ApiError::Other)?;
+        }
+        let id = id.unwrap_or_else(generate_session_id);
+        self.store
+            .save(&id, &record)
+            .await
+            .map_err(ApiError::Other)?;
+        {
+            let mut state = session.0.lock().unwrap();
+            state.id = Some(id.clone());
+            state.stale_id = None;
+        }
+        let mut cookie = self.cookie.clone();
+        cookie.max_age = Some(
+            (record.expires - self.clock.now())
+                .to_std()
+                .unwrap_or_default(),
+        );
+        Ok(Some(cookie.set_cookie(&self.sign(&id))?))
+    }
+}
+
+/// Loads the [`Session`] named by a signed ID cookie for each request, saving it to the store after the response
+#[derive(Clone)]
+pub struct SessionLayer {
+    config: Arc<SessionConfig>,
+}
+
+impl SessionLayer {
+    /// Session IDs are signed with `key`. Sessions expire after a day and use the default [`CookieConfig`]
+    /// named `session`.
+    pub fn new(store: impl SessionStore, key: &[u8]) -> Self {
+        Self {
+            config: Arc::new(SessionConfig {
+                store: Arc::new(store),
+                key: Hmac::new_from_slice(key).unwrap(),
+                cookie: CookieConfig::default(),
+                ttl: Duration::from_secs(24 * 60 * 60),
+                rolling: false,
+                clock: clock::system(),
+            }),
+        }
+    }
+
+    fn config_mut(&mut self) -> &mut SessionConfig {
+        Arc::get_mut(&mut self.config).expect("SessionLayer configured after being cloned")
+    }
+
+    pub fn with_cookie(mut self, cookie: CookieConfig) -> Self {
+        self.config_mut().cookie = cookie;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.config_mut().ttl = ttl;
+        self
+    }
+
+    /// Renews the expiry on every request with the session instead of only when it's created or cycled
+    pub fn with_rolling(mut self, rolling: bool) -> Self {
+        self.config_mut().rolling = rolling;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.config_mut().clock = clock;
+        self
+    }
+}
+
+impl<S> Layer<S> for SessionLayer {
+    type Service = SessionService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        SessionService {
+            config: self.config.clone(),
+            inner: service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionService<S> {
+    config: Arc<SessionConfig>,
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for SessionService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let config = self.config.clone();
+        // the ready service must handle this request, leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let state = match config.load(&parts).await {
+                Ok(state) => state,
+                Err(e) => return Ok(e.into_response()),
+            };
+            let session = Session(Arc::new(Mutex::new(state)));
+            parts.extensions.insert(session.clone());
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            match config.commit(&session).await {
+                Ok(Some(cookie)) => {
+                    response.headers_mut().append(SET_COOKIE, cookie);
+                }
+                Ok(None) => (),
+                Err(e) => return Ok(e.into_response()),
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use crate::clock::{self, SharedClock};
+
+use super::{SessionRecord, SessionStore};
+
+/// Sessions in Redis, shared between instances. Records are written with a TTL of their remaining lifetime, so
+/// Redis drops them once they expire.
+pub struct RedisStore {
+    clock: SharedClock,
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisStore {
+    /// Connects to `url`, i.e. `redis://127.0.0.1/`
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        Ok(Self::new(ConnectionManager::new(client).await?))
+    }
+
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self {
+            clock: clock::system(),
+            connection,
+            prefix: "session:".to_string(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Prepended to session IDs to form keys, defaults to `session:`
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{id}", self.prefix)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for RedisStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>> {
+        let raw: Option<String> = self.connection.clone().get(self.key(id)).await?;
+        Ok(raw.map(|x| serde_json::from_str(&x)).transpose()?)
+    }
+
+    async fn save(&self, id: &str, record: &SessionRecord) -> Result<()> {
+        let ttl = (record.expires - self.clock.now()).num_milliseconds();
+        let mut connection = self.connection.clone();
+        if ttl <= 0 {
+            connection.del::<_, ()>(self.key(id)).await?;
+            return Ok(());
+        }
+        connection
+            .pset_ex::<_, _, ()>(self.key(id), serde_json::to_string(record)?, ttl as u64)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.connection.clone().del::<_, ()>(self.key(id)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{self, SharedClock};
+
+use super::SessionData;
+
+/// Session data with the time it lapses unless saved again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub data: SessionData,
+    pub expires: DateTime<Utc>,
+}
+
+/// Persistence for [`super::SessionLayer`]. Stores may drop records after they expire, expired records they
+/// still return are ignored.
+#[async_trait::async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>>;
+
+    async fn save(&self, id: &str, record: &SessionRecord) -> Result<()>;
+
+    async fn delete(&self, id: &str) -> Result<()>;
+}
+
+const PRUNE_INTERVAL: u64 = 1024;
+
+/// Sessions in process memory, lost on restart and not shared between instances
+pub struct MemoryStore {
+    clock: SharedClock,
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+    saves: AtomicU64,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            clock: clock::system(),
+            sessions: Mutex::new(HashMap::new()),
+            saves: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Includes expired sessions until they are pruned, every 1024 saves
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for MemoryStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>> {
+        Ok(self.sessions.lock().unwrap().get(id).cloned())
+    }
+
+    async fn save(&self, id: &str, record: &SessionRecord) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if self
+            .saves
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(PRUNE_INTERVAL)
+        {
+            let now = self.clock.now();
+            sessions.retain(|_, x| x.expires > now);
+        }
+        sessions.insert(id.to_string(), record.clone());
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::errors::{ApiError, ApiResult};
+
+/// Effective configuration flattened to dotted paths, i.e. `oidc.google.client_id`
+pub type ConfigSnapshot = BTreeMap<String, Value>;
+
+pub type SnapshotSource = Arc<dyn Fn() -> Value + Send + Sync>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigChange {
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoredSnapshot {
+    pub id: usize,
+    pub taken_at: DateTime<Utc>,
+    pub snapshot: ConfigSnapshot,
+}
+
+/// Collects the runtime configuration of registered subsystems for operators to snapshot and diff across deploys
+#[derive(Default)]
+pub struct SnapshotRegistry {
+    sources: RwLock<IndexMap<String, SnapshotSource>>,
+    stored: RwLock<Vec<StoredSnapshot>>,
+}
+
+impl SnapshotRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sources must not include secrets, see [`crate::redact::REDACTED`]
+    pub fn register(
+        &self,
+        name: impl Into<String>,
+        source: impl Fn() -> Value + Send + Sync + 'static,
+    ) {
+        self.sources
+            .write()
+            .unwrap()
+            .insert(name.into(), Arc::new(source));
+    }
+
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        let mut out = ConfigSnapshot::new();
+        for (name, source) in self.sources.read().unwrap().iter() {
+            flatten(name.clone(), source(), &mut out);
+        }
+        out
+    }
+
+    /// Stores the current snapshot for later diffing
+    pub fn store(&self) -> StoredSnapshot {
+        let mut stored = self.stored.write().unwrap();
+        let snapshot = StoredSnapshot {
+            id: stored.len(),
+            taken_at: Utc::now(),
+            snapshot: self.snapshot(),
+        };
+        stored.push(snapshot.clone());
+        snapshot
+    }
+
+    /// `"current"` refers to a fresh snapshot
+    pub fn get(&self, id: &str) -> Option<ConfigSnapshot> {
+        if id == "current" {
+            return Some(self.snapshot());
+        }
+        let id: usize = id.parse().ok()?;
+        self.stored
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|x| x.snapshot.clone())
+    }
+
+    /// Routes `GET /config`, `GET|POST /config/snapshots`, `GET /config/diff/:from/:to` and `POST /config/diff`
+    pub fn router<S>(self: Arc<Self>) -> Router<S> {
+        Router::new()
+            .route("/config", get(current))
+            .route("/config/snapshots", get(list).post(store))
+            .route("/config/diff/:from/:to", get(diff_stored))
+            .route("/config/diff", post(diff_supplied))
+            .with_state(self)
+    }
+}
+
+fn flatten(path: String, value: Value, out: &mut ConfigSnapshot) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                flatten(format!("{path}.{key}"), value, out);
+            }
+        }
+        value => {
+            out.insert(path, value);
+        }
+    }
+}
+
+/// Keys whose values differ, were added, or were removed between `before` and `after`
+pub fn diff(before: &ConfigSnapshot, after: &ConfigSnapshot) -> BTreeMap<String, ConfigChange> {
+    let mut out = BTreeMap::new();
+    for (key, value) in before {
+        if after.get(key) != Some(value) {
+            out.insert(
+                key.clone(),
+                ConfigChange {
+                    before: Some(value.clone()),
+                    after: after.get(key).cloned(),
+                },
+            );
+        }
+    }
+    for (key, value) in after {
+        if !before.contains_key(key) {
+            out.insert(
+                key.clone(),
+                ConfigChange {
+                    before: None,
+                    after: Some(value.clone()),
+                },
+            );
+        }
+    }
+    out
+}
+
+async fn current(State(registry): State<Arc<SnapshotRegistry>>) -> Json<ConfigSnapshot> {
+    Json(registry.snapshot())
+}
+
+async fn list(State(registry): State<Arc<SnapshotRegistry>>) -> Json<Vec<StoredSnapshot>> {
+    Json(registry.stored.read().unwrap().clone())
+}
+
+async fn store(State(registry): State<Arc<SnapshotRegistry>>) -> Json<StoredSnapshot> {
+    Json(registry.store())
+}
+
+async fn diff_stored(
+    State(registry): State<Arc<SnapshotRegistry>>,
+    Path((from, to)): Path<(String, String)>,
+) -> ApiResult<Json<BTreeMap<String, ConfigChange>>> {
+    let from = registry.get(&from).ok_or(ApiError::NotFound)?;
+    let to = registry.get(&to).ok_or(ApiError::NotFound)?;
+    Ok(Json(diff(&from, &to)))
+}
+
+/// Diffs a supplied snapshot (i.e. the expected state from a deploy) against the current one
+async fn diff_supplied(
+    State(registry): State<Arc<SnapshotRegistry>>,
+    Json(expected): Json<ConfigSnapshot>,
+) -> Json<BTreeMap<String, ConfigChange>> {
+    Json(diff(&expected, &registry.snapshot()))
+}
@@ -0,0 +1,123 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+
+use axum::response::{IntoResponse, Response};
+use futures::Future;
+use http::{Request, StatusCode};
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_counter, IntCounter};
+use tokio::time::Sleep;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::errors::ApiError;
+
+#[cfg(feature = "prometheus")]
+fn timeouts_total() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter!("request_timeouts_total", "Requests cut off by TimeoutLayer").unwrap()
+    })
+}
+
+/// Overrides the limit of [`TimeoutLayer`] for a request when inserted before it runs, i.e. by a middleware
+/// choosing limits per path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestTimeout(pub Duration);
+
+/// Extension on responses produced by [`TimeoutLayer`], holding the limit that was hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut(pub Duration);
+
+/// Answers `504 Gateway Timeout` with an [`crate::errors::ErrorBody`] when the inner service takes longer than
+/// the given limit, dropping its future. Requires a tokio runtime with the time driver.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeoutLayer(pub Duration);
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        Self(timeout)
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Timeout::new(service, self.0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S> Timeout<S> {
+    pub fn new(inner: S, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+#[pin_project::pin_project]
+pub struct TimeoutFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    sleep: Sleep,
+    timeout: Duration,
+}
+
+impl<F, E> Future for TimeoutFuture<F>
+where
+    F: Future<Output = Result<Response, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            return Poll::Ready(result);
+        }
+        if this.sleep.poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        #[cfg(feature = "prometheus")]
+        timeouts_total().inc();
+        let mut response = ApiError::Status(StatusCode::GATEWAY_TIMEOUT).into_response();
+        response.extensions_mut().insert(TimedOut(*this.timeout));
+        Poll::Ready(Ok(response))
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Timeout<S>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = TimeoutFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let timeout = req
+            .extensions()
+            .get::<RequestTimeout>()
+            .map_or(self.timeout, |x| x.0);
+        TimeoutFuture {
+            inner: self.inner.call(req),
+            sleep: tokio::time::sleep(timeout),
+            timeout,
+        }
+    }
+}
@@ -1,36 +1,97 @@
-use std::{
-    net::SocketAddr,
-    pin::Pin,
-    sync::Arc,
-    task::{Context, Poll},
-    time::Duration,
-};
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
 
+use crate::{
+    connection::{serve_connections, ConnectionLimits, ConnectionMetrics, TrackedConnection},
+    incoming::AcceptLoop,
+};
 use anyhow::Result;
+use axum::Router;
 use futures::Stream;
-use hyper::server::{
-    accept::Accept,
-    conn::{AddrIncoming, AddrStream},
-};
-use log::{error, warn};
-use rustls::{server::Acceptor, ServerConfig};
-use tokio::sync::{mpsc, watch};
+use log::{debug, error, warn};
+use rustls::{server::Acceptor, Certificate, ServerConfig};
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
 use tokio_rustls::{server::TlsStream, LazyConfigAcceptor};
-use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 
-pub struct TlsIncoming {
-    incoming: StreamWrapper,
-    tls_config: watch::Receiver<Option<Arc<ServerConfig>>>,
-}
+mod accept_queue;
+#[cfg(feature = "acme")]
+mod acme;
+mod cert_watcher;
+mod client_auth;
+mod connection_info;
+#[cfg(feature = "encrypted-keys")]
+mod encrypted;
+mod handshake;
+mod make_service;
+mod metrics;
+#[cfg(feature = "ocsp")]
+mod ocsp;
+mod pem;
+mod plaintext;
+mod redirect;
+mod server_config;
+mod session_tickets;
+mod sni;
+pub use accept_queue::AcceptBackpressure;
+use accept_queue::{accept_queue, Sent};
+#[cfg(feature = "acme")]
+pub use acme::*;
+pub use cert_watcher::CertWatcher;
+pub use client_auth::*;
+pub use connection_info::TlsConnectionInfo;
+#[cfg(feature = "encrypted-keys")]
+pub use encrypted::*;
+pub use handshake::HandshakeLimits;
+use handshake::HandshakeThrottle;
+pub use make_service::*;
+use metrics::HandshakeFailure;
+#[cfg(feature = "ocsp")]
+pub use ocsp::*;
+pub use pem::*;
+pub use plaintext::PlaintextResponse;
+pub use redirect::HttpRedirectServer;
+pub use server_config::*;
+pub use session_tickets::SessionTickets;
+pub use sni::*;
 
-struct StreamWrapper(AddrIncoming);
+pub use crate::incoming::{
+    AcceptAlert, AcceptAlertHandler, AcceptErrorKind, AcceptPolicy, ClientStream, ConnectionFilter,
+    ListenAddr, Verdict,
+};
 
-impl Stream for StreamWrapper {
-    type Item = Result<AddrStream, std::io::Error>;
+/// Colon separated SHA-256 of a DER certificate, as shown by most tooling
+pub fn certificate_fingerprint(certificate: &Certificate) -> String {
+    Sha256::digest(&certificate.0)
+        .iter()
+        .map(|x| format!("{x:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.0).poll_accept(cx)
-    }
+/// Serves `router` over TLS on `listen` until `shutdown` is set to `true`, then waits for open connections to finish.
+/// Requests carry `ConnectInfo<SocketAddr>`, [`TlsConnectionInfo`], and [`ClientCert`] as with [`TlsMakeService`].
+pub async fn serve_tls(
+    listen: SocketAddr,
+    tls_config: watch::Receiver<Option<Arc<ServerConfig>>>,
+    router: Router,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let (_, server) = TlsIncoming::new(listen, true, None, tls_config)?
+        .with_shutdown(shutdown)
+        .serve(router);
+    server.await
+}
+
+pub struct TlsIncoming {
+    accept: AcceptLoop,
+    tls_config: watch::Receiver<Option<Arc<ServerConfig>>>,
+    connection_limits: ConnectionLimits,
+    connection_metrics: ConnectionMetrics,
+    shutdown: Option<watch::Receiver<bool>>,
+    handshake: HandshakeThrottle,
+    plaintext_response: PlaintextResponse,
+    accept_capacity: usize,
+    backpressure: AcceptBackpressure,
 }
 
 impl TlsIncoming {
@@ -40,50 +101,193 @@ impl TlsIncoming {
         keepalive: Option<Duration>,
         tls_config: watch::Receiver<Option<Arc<ServerConfig>>>,
     ) -> Result<Self> {
-        let mut incoming = AddrIncoming::bind(&listen)?;
-        incoming.set_nodelay(nodelay);
-        incoming.set_keepalive(keepalive);
+        Self::new_multi([listen.into()], nodelay, keepalive, tls_config)
+    }
 
+    /// Listens on every address (i.e. both `0.0.0.0:443` and `[::]:443`, plus a Unix domain socket), accepting from
+    /// all of them into one stream. `nodelay` and `keepalive` only apply to TCP.
+    pub fn new_multi(
+        listen: impl IntoIterator<Item = ListenAddr>,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+        tls_config: watch::Receiver<Option<Arc<ServerConfig>>>,
+    ) -> Result<Self> {
         Ok(Self {
-            incoming: StreamWrapper(incoming),
+            accept: AcceptLoop::bind(listen, nodelay, keepalive)?,
             tls_config,
+            connection_limits: ConnectionLimits::default(),
+            connection_metrics: metrics::connection_metrics(),
+            shutdown: None,
+            handshake: HandshakeThrottle::new(HandshakeLimits::default()),
+            plaintext_response: PlaintextResponse::None,
+            accept_capacity: 10,
+            backpressure: AcceptBackpressure::default(),
         })
     }
 
-    pub fn start(mut self) -> impl Stream<Item = Result<TlsStream<AddrStream>, std::io::Error>> {
-        let (sender, receiver) = mpsc::channel::<Result<TlsStream<AddrStream>, std::io::Error>>(10);
+    pub fn with_accept_policy(mut self, accept_policy: AcceptPolicy) -> Self {
+        self.accept.set_accept_policy(accept_policy);
+        self
+    }
+
+    /// Idle timeout and maximum lifetime enforced on each handshaked connection
+    pub fn with_connection_limits(mut self, connection_limits: ConnectionLimits) -> Self {
+        self.connection_limits = connection_limits;
+        self
+    }
+
+    /// Replaces the shared `tls_connections_*` metrics
+    pub fn with_connection_metrics(mut self, connection_metrics: ConnectionMetrics) -> Self {
+        self.connection_metrics = connection_metrics;
+        self
+    }
+
+    /// Evaluated before the TLS handshake. Unix domain socket clients aren't filtered.
+    pub fn with_filter(mut self, filter: ConnectionFilter) -> Self {
+        self.accept.set_filter(filter);
+        self
+    }
+
+    /// Handshaked connections waiting for the server, and what to do when more arrive. Defaults to 10, blocking.
+    pub fn with_accept_queue(mut self, capacity: usize, backpressure: AcceptBackpressure) -> Self {
+        self.accept_capacity = capacity;
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Stops accepting once `shutdown` is set to `true`. The stream returned by `start` ends after in-flight
+    /// handshakes complete, letting the hyper server drain.
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn with_handshake_limits(mut self, limits: HandshakeLimits) -> Self {
+        self.handshake = HandshakeThrottle::new(limits);
+        self
+    }
+
+    /// Answers clients speaking plain HTTP to this port instead of failing the handshake
+    pub fn with_plaintext_response(mut self, plaintext_response: PlaintextResponse) -> Self {
+        self.plaintext_response = plaintext_response;
+        self
+    }
+
+    /// Bound addresses, with the port chosen by the OS for any listening on port 0
+    pub fn local_addrs(&self) -> &[ListenAddr] {
+        self.accept.local_addrs()
+    }
+
+    /// First bound TCP address
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.accept.local_addr()
+    }
+
+    /// Serves `router` over [`TlsMakeService`] until the shutdown signal, if any, then waits for open connections to
+    /// finish. Returns the bound address, i.e. for connecting to a port 0 listener, along with the server future.
+    pub fn serve(
+        self,
+        router: Router,
+    ) -> (Option<SocketAddr>, impl Future<Output = Result<()>> + Send) {
+        let local_addr = self.local_addr();
+        let shutdown = self.shutdown.clone();
+        let make_service = TlsMakeService::new(router);
+        let server = serve_connections(
+            self.start(),
+            move |connection| make_service.connection_service(connection),
+            shutdown,
+        );
+        (local_addr, server)
+    }
+
+    pub fn start(
+        mut self,
+    ) -> impl Stream<Item = Result<TrackedConnection<TlsStream<ClientStream>>, std::io::Error>>
+    {
+        let (sender, receiver) = accept_queue(self.accept_capacity, self.backpressure);
         tokio::spawn(async move {
+            let mut shutdown = self.shutdown.take();
             loop {
-                let client = match self.incoming.next().await {
-                    Some(Ok(x)) => x,
-                    Some(Err(e)) => {
-                        error!("error during accepting TCP client: {e}");
-                        continue;
-                    }
-                    None => break,
+                let Some(mut client) = self.accept.accept(&mut shutdown).await else {
+                    debug!("TLS acceptor on {:?} shutting down", self.accept.listen());
+                    break;
                 };
+                metrics::accepted();
+                if !self.accept.admit(&client) {
+                    metrics::filtered();
+                    debug!(
+                        "rejected TLS connection from {:?} by filter",
+                        client.remote_addr()
+                    );
+                    continue;
+                }
                 let Some(server_config) = self.tls_config.borrow().clone() else {
+                    metrics::no_config();
                     warn!("inbound TLS connection dropped (no certificates loaded, but were configured)");
-                    continue
+                    continue;
+                };
+
+                let remote = client.remote_addr().map(|x| x.ip());
+                let permit = match self.handshake.admit(remote) {
+                    Ok(x) => x,
+                    Err(rejection) => {
+                        handshake::rejected(remote, rejection);
+                        continue;
+                    }
                 };
 
-                let lazy = LazyConfigAcceptor::new(Acceptor::default(), client);
+                let plaintext_response = self.plaintext_response;
                 let sender = sender.clone();
+                let connection_limits = self.connection_limits.clone();
+                let connection_metrics = self.connection_metrics.clone();
+                let throttle = self.handshake.clone();
                 tokio::spawn(async move {
-                    let accepted = match lazy.await {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!("error during TLS init: {e}");
+                    let handshake = async move {
+                        if plaintext::answer_plaintext(&mut client, plaintext_response).await {
+                            metrics::handshake_failed(HandshakeFailure::Plaintext);
+                            return None;
+                        }
+                        let lazy = LazyConfigAcceptor::new(Acceptor::default(), client);
+                        let accepted = match lazy.await {
+                            Ok(x) => x,
+                            Err(e) => {
+                                metrics::handshake_failed(HandshakeFailure::classify(&e));
+                                error!("error during TLS init: {e}");
+                                return None;
+                            }
+                        };
+                        match accepted.into_stream(server_config).await {
+                            Ok(x) => Some(x),
+                            Err(e) => {
+                                // handled here, as hyper stops serving on any error from the accept stream
+                                metrics::handshake_failed(HandshakeFailure::classify(&e));
+                                debug!("TLS handshake failed: {e}");
+                                None
+                            }
+                        }
+                    };
+                    let tls_stream = match throttle.timeout(handshake).await {
+                        Ok(Some(x)) => x,
+                        Ok(None) => return,
+                        Err(rejection) => {
+                            handshake::rejected(remote, rejection);
                             return;
                         }
                     };
-                    let tls_stream = accepted.into_stream(server_config).await;
-                    if sender.send(tls_stream).await.is_err() {
-                        error!("TLS acceptor hung");
+                    drop(permit);
+                    let tls_stream =
+                        TrackedConnection::new(tls_stream, connection_limits, connection_metrics);
+                    match sender.send(Ok(tls_stream)).await {
+                        Sent::Queued => (),
+                        Sent::Dropped(_) => {
+                            metrics::queue_dropped();
+                            warn!("TLS accept queue full, dropped connection");
+                        }
+                        Sent::Closed(_) => error!("TLS acceptor hung"),
                     }
                 });
             }
         });
-        ReceiverStream::new(receiver)
+        receiver
     }
 }
@@ -0,0 +1,264 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures::Stream;
+use tokio::sync::Notify;
+
+/// What [`super::TlsIncoming`] does with a handshaked connection when the server isn't taking them as fast as they
+/// arrive and the accept queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AcceptBackpressure {
+    /// Hold the connection until there is room
+    #[default]
+    Block,
+    /// Close the new connection
+    Drop,
+    /// Close the longest queued connection to make room
+    ShedOldest,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiver_closed: bool,
+    receiver: Option<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    backpressure: AcceptBackpressure,
+    space: Notify,
+}
+
+pub(super) enum Sent<T> {
+    Queued,
+    /// `T` wasn't queued, or was evicted, under [`AcceptBackpressure::Drop`] or [`AcceptBackpressure::ShedOldest`]
+    Dropped(T),
+    /// The receiver is gone
+    Closed(T),
+}
+
+pub(super) struct AcceptSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub(super) struct AcceptReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub(super) fn accept_queue<T>(
+    capacity: usize,
+    backpressure: AcceptBackpressure,
+) -> (AcceptSender<T>, AcceptReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver_closed: false,
+            receiver: None,
+        }),
+        capacity: capacity.max(1),
+        backpressure,
+        space: Notify::new(),
+    });
+    (
+        AcceptSender {
+            shared: shared.clone(),
+        },
+        AcceptReceiver { shared },
+    )
+}
+
+impl<T> AcceptSender<T> {
+    pub(super) async fn send(&self, item: T) -> Sent<T> {
+        loop {
+            let notified = self.shared.space.notified();
+            let mut notified = std::pin::pin!(notified);
+            // registered before checking, so a slot freed in between isn't missed
+            notified.as_mut().enable();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.receiver_closed {
+                    return Sent::Closed(item);
+                }
+                if state.items.len() < self.shared.capacity {
+                    state.items.push_back(item);
+                    if let Some(waker) = state.receiver.take() {
+                        waker.wake();
+                    }
+                    return Sent::Queued;
+                }
+                match self.shared.backpressure {
+                    AcceptBackpressure::Block => (),
+                    AcceptBackpressure::Drop => return Sent::Dropped(item),
+                    AcceptBackpressure::ShedOldest => {
+                        let oldest = state.items.pop_front().expect("full queue is empty");
+                        state.items.push_back(item);
+                        return Sent::Dropped(oldest);
+                    }
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+impl<T> Clone for AcceptSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for AcceptSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.receiver.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Stream for AcceptReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(item) = state.items.pop_front() {
+            self.shared.space.notify_one();
+            return Poll::Ready(Some(item));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        state.receiver = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for AcceptReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiver_closed = true;
+        state.items.clear();
+        self.shared.space.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{
+        task::{self, ArcWake},
+        FutureExt, StreamExt,
+    };
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl CountingWaker {
+        fn woken(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    fn poll(receiver: &mut AcceptReceiver<u32>, waker: &Arc<CountingWaker>) -> Poll<Option<u32>> {
+        let waker = task::waker(waker.clone());
+        Pin::new(receiver).poll_next(&mut Context::from_waker(&waker))
+    }
+
+    fn send(sender: &AcceptSender<u32>, item: u32) -> Sent<u32> {
+        sender.send(item).now_or_never().expect("send blocked")
+    }
+
+    #[test]
+    fn send_wakes_receiver() {
+        let (sender, mut receiver) = accept_queue(2, AcceptBackpressure::Block);
+        let waker = Arc::new(CountingWaker::default());
+        assert_eq!(poll(&mut receiver, &waker), Poll::Pending);
+        assert!(matches!(send(&sender, 1), Sent::Queued));
+        assert_eq!(waker.woken(), 1);
+        assert_eq!(poll(&mut receiver, &waker), Poll::Ready(Some(1)));
+    }
+
+    #[test]
+    fn queued_before_close() {
+        let (sender, mut receiver) = accept_queue(2, AcceptBackpressure::Block);
+        let other = sender.clone();
+        assert!(matches!(send(&sender, 1), Sent::Queued));
+        assert!(matches!(send(&other, 2), Sent::Queued));
+        let waker = Arc::new(CountingWaker::default());
+        drop(sender);
+        drop(other);
+        assert_eq!(poll(&mut receiver, &waker), Poll::Ready(Some(1)));
+        assert_eq!(poll(&mut receiver, &waker), Poll::Ready(Some(2)));
+        assert_eq!(poll(&mut receiver, &waker), Poll::Ready(None));
+    }
+
+    #[test]
+    fn last_sender_wakes_receiver() {
+        let (sender, mut receiver) = accept_queue::<u32>(1, AcceptBackpressure::Block);
+        let other = sender.clone();
+        let waker = Arc::new(CountingWaker::default());
+        assert_eq!(poll(&mut receiver, &waker), Poll::Pending);
+        drop(sender);
+        assert_eq!(waker.woken(), 0);
+        drop(other);
+        assert_eq!(waker.woken(), 1);
+        assert_eq!(poll(&mut receiver, &waker), Poll::Ready(None));
+    }
+
+    #[test]
+    fn blocked_send_resumes_on_space() {
+        let (sender, mut receiver) = accept_queue(1, AcceptBackpressure::Block);
+        assert!(matches!(send(&sender, 1), Sent::Queued));
+        let mut blocked = Box::pin(sender.send(2));
+        assert!(blocked.as_mut().now_or_never().is_none());
+        assert_eq!(receiver.next().now_or_never(), Some(Some(1)));
+        assert!(matches!(blocked.now_or_never(), Some(Sent::Queued)));
+        assert_eq!(receiver.next().now_or_never(), Some(Some(2)));
+    }
+
+    #[test]
+    fn blocked_send_closes_with_receiver() {
+        let (sender, receiver) = accept_queue(1, AcceptBackpressure::Block);
+        assert!(matches!(send(&sender, 1), Sent::Queued));
+        let mut blocked = Box::pin(sender.send(2));
+        assert!(blocked.as_mut().now_or_never().is_none());
+        drop(receiver);
+        assert!(matches!(blocked.now_or_never(), Some(Sent::Closed(2))));
+        assert!(matches!(send(&sender, 3), Sent::Closed(3)));
+    }
+
+    #[test]
+    fn full_queue_backpressure() {
+        let (sender, mut receiver) = accept_queue(1, AcceptBackpressure::Drop);
+        assert!(matches!(send(&sender, 1), Sent::Queued));
+        assert!(matches!(send(&sender, 2), Sent::Dropped(2)));
+        assert_eq!(receiver.next().now_or_never(), Some(Some(1)));
+
+        let (sender, mut receiver) = accept_queue(1, AcceptBackpressure::ShedOldest);
+        assert!(matches!(send(&sender, 1), Sent::Queued));
+        assert!(matches!(send(&sender, 2), Sent::Dropped(1)));
+        assert_eq!(receiver.next().now_or_never(), Some(Some(2)));
+    }
+}
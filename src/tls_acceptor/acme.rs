@@ -0,0 +1,637 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use log::{error, info, warn};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName};
+use ring::{
+    digest::{digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, PrivateKey, ServerConfig,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{watch, Mutex, OnceCell};
+use url::Url;
+use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+use super::{certified_key, parse_pem};
+
+pub const LETS_ENCRYPT_PRODUCTION: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AcmeConfig {
+    #[serde(default = "default_directory")]
+    pub directory: Url,
+    /// i.e. `mailto:admin@example.com`
+    #[serde(default)]
+    pub contact: Vec<String>,
+    pub domains: Vec<String>,
+    /// Agreement to the CA's terms of service, sent with the account registration. Must be `true`.
+    pub terms_of_service_agreed: bool,
+    /// Holds the account key and the current certificate across restarts
+    pub cache_dir: PathBuf,
+    /// Answers challenges over HTTP-01 (see [`AcmeManager::http01_key_authorization`]) instead of TLS-ALPN-01
+    #[serde(default)]
+    pub http01: bool,
+    /// Renews this long before the certificate's `notAfter`
+    #[serde(default = "default_renew_before")]
+    pub renew_before: Duration,
+    /// Delay before retrying a failed order
+    #[serde(default = "default_retry_interval")]
+    pub retry_interval: Duration,
+}
+
+fn default_directory() -> Url {
+    LETS_ENCRYPT_PRODUCTION.parse().unwrap()
+}
+
+fn default_renew_before() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+fn default_retry_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: Url,
+    new_account: Url,
+    new_order: Url,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<Url>,
+    finalize: Url,
+    certificate: Option<Url>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: Url,
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCertificate {
+    domains: Vec<String>,
+    issued_at: DateTime<Utc>,
+    certificate_pem: String,
+    private_key_pem: String,
+}
+
+impl CachedCertificate {
+    fn certified_key(&self) -> Result<Arc<CertifiedKey>> {
+        let certificates = parse_pem(&self.certificate_pem)?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| Certificate(der))
+            .collect();
+        let key = parse_pem(&self.private_key_pem)?
+            .into_iter()
+            .next()
+            .map(|(_, der)| PrivateKey(der))
+            .ok_or_else(|| anyhow!("missing private key"))?;
+        certified_key(certificates, &key)
+    }
+
+    /// `notAfter` of the end entity certificate
+    fn not_after(&self) -> Result<DateTime<Utc>> {
+        let (_, der) = parse_pem(&self.certificate_pem)?
+            .into_iter()
+            .find(|(label, _)| label == "CERTIFICATE")
+            .ok_or_else(|| anyhow!("missing certificate"))?;
+        let (_, certificate) =
+            X509Certificate::from_der(&der).context("invalid end entity certificate")?;
+        Utc.timestamp_opt(certificate.validity().not_after.timestamp(), 0)
+            .single()
+            .ok_or_else(|| anyhow!("certificate notAfter out of range"))
+    }
+}
+
+/// Writes `contents` readable only by the owner, through a temporary file renamed into place
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&temp)
+        .with_context(|| format!("failed to create {}", temp.display()))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("failed to write {}", temp.display()))?;
+    std::fs::rename(&temp, path)
+        .with_context(|| format!("failed to rename {} into place", temp.display()))
+}
+
+/// Serves the issued certificate, or the TLS-ALPN-01 challenge certificate for validation connections
+struct AcmeResolver {
+    certificate: Option<Arc<CertifiedKey>>,
+    challenges: Arc<RwLock<HashMap<String, Arc<CertifiedKey>>>>,
+}
+
+impl ResolvesServerCert for AcmeResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let is_challenge = client_hello
+            .alpn()
+            .is_some_and(|mut x| x.any(|protocol| protocol == ACME_TLS_ALPN));
+        if is_challenge {
+            let server_name = client_hello.server_name()?;
+            return self.challenges.read().unwrap().get(server_name).cloned();
+        }
+        self.certificate.clone()
+    }
+}
+
+/// Obtains and renews certificates for `domains` from an ACME CA (RFC 8555), i.e. Let's Encrypt
+pub struct AcmeManager {
+    config: AcmeConfig,
+    http: reqwest::Client,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    alpn_protocols: Vec<Vec<u8>>,
+    directory: OnceCell<Directory>,
+    account: OnceCell<String>,
+    nonce: Mutex<Option<String>>,
+    tls_alpn_challenges: Arc<RwLock<HashMap<String, Arc<CertifiedKey>>>>,
+    http01_challenges: RwLock<HashMap<String, String>>,
+}
+
+impl AcmeManager {
+    /// Loads (or generates) the account key in `cache_dir`
+    pub fn new(config: AcmeConfig) -> Result<Self> {
+        if config.domains.is_empty() {
+            bail!("no ACME domains configured");
+        }
+        if !config.terms_of_service_agreed {
+            bail!(
+                "the ACME CA's terms of service must be agreed to with `terms_of_service_agreed`"
+            );
+        }
+        std::fs::create_dir_all(&config.cache_dir)
+            .with_context(|| format!("failed to create {}", config.cache_dir.display()))?;
+        let rng = SystemRandom::new();
+        let key_path = config.cache_dir.join("account.pk8");
+        let pkcs8 = match std::fs::read(&key_path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| anyhow!("failed to generate ACME account key"))?;
+                write_private(&key_path, pkcs8.as_ref())?;
+                pkcs8.as_ref().to_vec()
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", key_path.display()))
+            }
+        };
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8)
+            .map_err(|e| anyhow!("invalid ACME account key {}: {e}", key_path.display()))?;
+        Ok(Self {
+            config,
+            http: reqwest::Client::new(),
+            key,
+            rng,
+            alpn_protocols: vec![b"http/1.1".to_vec()],
+            directory: OnceCell::new(),
+            account: OnceCell::new(),
+            nonce: Mutex::new(None),
+            tls_alpn_challenges: Default::default(),
+            http01_challenges: Default::default(),
+        })
+    }
+
+    /// ALPN protocols of the published `ServerConfig`, defaults to `http/1.1`
+    pub fn with_alpn_protocols(mut self, alpn_protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = alpn_protocols;
+        self
+    }
+
+    /// Response body for `GET /.well-known/acme-challenge/{token}` during HTTP-01 validation
+    pub fn http01_key_authorization(&self, token: &str) -> Option<String> {
+        self.http01_challenges.read().unwrap().get(token).cloned()
+    }
+
+    fn server_config(&self, certificate: Option<Arc<CertifiedKey>>) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(AcmeResolver {
+                certificate,
+                challenges: self.tls_alpn_challenges.clone(),
+            }));
+        config.alpn_protocols = self.alpn_protocols.clone();
+        if !self.config.http01 {
+            config.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+        }
+        Arc::new(config)
+    }
+
+    fn cache_path(&self) -> PathBuf {
+        self.config.cache_dir.join("certificate.json")
+    }
+
+    fn load_cached(&self) -> Option<CachedCertificate> {
+        let raw = std::fs::read_to_string(self.cache_path()).ok()?;
+        let cached: CachedCertificate = match serde_json::from_str(&raw) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("ignoring invalid cached ACME certificate: {e}");
+                return None;
+            }
+        };
+        if cached.domains != self.config.domains {
+            info!("ACME domains changed, ignoring cached certificate");
+            return None;
+        }
+        Some(cached)
+    }
+
+    /// Publishes a `ServerConfig` immediately (so TLS-ALPN-01 challenges can be answered), then orders a certificate
+    /// if none is cached and renews it `renew_before` its `notAfter`
+    pub fn spawn(self: Arc<Self>) -> watch::Receiver<Option<Arc<ServerConfig>>> {
+        let cached = self.load_cached();
+        let certificate = cached.as_ref().and_then(|x| match x.certified_key() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                warn!("ignoring invalid cached ACME certificate: {e:#}");
+                None
+            }
+        });
+        let mut renew_at = certificate
+            .as_ref()
+            .and(cached.as_ref())
+            .and_then(|x| self.renew_at(x));
+        let (sender, receiver) = watch::channel(Some(self.server_config(certificate)));
+        tokio::spawn(async move {
+            loop {
+                if let Some(renew_at) = renew_at {
+                    if let Ok(wait) = (renew_at - Utc::now()).to_std() {
+                        tokio::time::sleep(wait).await;
+                    }
+                }
+                if sender.is_closed() {
+                    break;
+                }
+                match self.issue().await {
+                    Ok(cached) => {
+                        info!("issued ACME certificate for {:?}", self.config.domains);
+                        if let Err(e) = write_private(
+                            &self.cache_path(),
+                            serde_json::to_string(&cached).unwrap().as_bytes(),
+                        ) {
+                            error!("failed to cache ACME certificate: {e:#}");
+                        }
+                        match cached.certified_key() {
+                            Ok(certificate) => {
+                                sender.send_replace(Some(self.server_config(Some(certificate))));
+                                renew_at = Some(self.renew_at(&cached).unwrap_or_else(|| {
+                                    Utc::now()
+                                        + chrono::Duration::from_std(self.config.retry_interval)
+                                            .unwrap_or(chrono::Duration::zero())
+                                }));
+                            }
+                            Err(e) => {
+                                error!("ACME CA returned an unusable certificate: {e:#}");
+                                tokio::time::sleep(self.config.retry_interval).await;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("failed to obtain ACME certificate: {e:#}");
+                        tokio::time::sleep(self.config.retry_interval).await;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+
+    /// `renew_before` the `notAfter` of `cached`, but no earlier than halfway through its validity
+    fn renew_at(&self, cached: &CachedCertificate) -> Option<DateTime<Utc>> {
+        let not_after = match cached.not_after() {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("failed to read ACME certificate expiry: {e:#}");
+                return None;
+            }
+        };
+        let renew_before = chrono::Duration::from_std(self.config.renew_before)
+            .unwrap_or(chrono::Duration::zero())
+            .min((not_after - cached.issued_at) / 2)
+            .max(chrono::Duration::zero());
+        Some(not_after - renew_before)
+    }
+
+    async fn directory(&self) -> Result<&Directory> {
+        self.directory
+            .get_or_try_init(|| async {
+                Ok::<_, anyhow::Error>(
+                    self.http
+                        .get(self.config.directory.clone())
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await?,
+                )
+            })
+            .await
+            .context("failed to fetch ACME directory")
+    }
+
+    async fn nonce(&self) -> Result<String> {
+        if let Some(nonce) = self.nonce.lock().await.take() {
+            return Ok(nonce);
+        }
+        let response = self
+            .http
+            .head(self.directory().await?.new_nonce.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        replay_nonce(&response).ok_or_else(|| anyhow!("ACME CA did not return a nonce"))
+    }
+
+    fn jwk(&self) -> Value {
+        // uncompressed point, 0x04 || x || y
+        let public_key = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&public_key[33..]),
+        })
+    }
+
+    fn key_authorization(&self, token: &str) -> String {
+        let jwk = self.jwk();
+        // RFC 7638 thumbprint, members in lexicographic order without whitespace
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap(),
+            jwk["y"].as_str().unwrap()
+        );
+        let thumbprint = URL_SAFE_NO_PAD.encode(digest(&SHA256, canonical.as_bytes()));
+        format!("{token}.{thumbprint}")
+    }
+
+    /// JWS-signed POST, `payload` of `None` is a POST-as-GET. Signs with the account URL once registered.
+    async fn post(&self, url: &Url, payload: Option<&Value>) -> Result<reqwest::Response> {
+        let kid = self.account.get().cloned();
+        let mut retried = false;
+        loop {
+            let mut protected = json!({
+                "alg": "ES256",
+                "nonce": self.nonce().await?,
+                "url": url.as_str(),
+            });
+            match &kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.jwk(),
+            }
+            let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+            let payload = payload
+                .map(|x| URL_SAFE_NO_PAD.encode(x.to_string()))
+                .unwrap_or_default();
+            let signature = self
+                .key
+                .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+                .map_err(|_| anyhow!("failed to sign ACME request"))?;
+            let response = self
+                .http
+                .post(url.clone())
+                .header("content-type", "application/jose+json")
+                .body(
+                    json!({
+                        "protected": protected,
+                        "payload": payload,
+                        "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                    })
+                    .to_string(),
+                )
+                .send()
+                .await?;
+            if let Some(nonce) = replay_nonce(&response) {
+                *self.nonce.lock().await = Some(nonce);
+            }
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status();
+            let problem: Value = response.json().await.unwrap_or_default();
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            bail!("ACME request to {url} failed with {status}: {problem}");
+        }
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &Url,
+        payload: Option<&Value>,
+    ) -> Result<T> {
+        Ok(self.post(url, payload).await?.json().await?)
+    }
+
+    async fn account(&self) -> Result<()> {
+        let new_account = self.directory().await?.new_account.clone();
+        self.account
+            .get_or_try_init(|| async {
+                let response = self
+                    .post(
+                        &new_account,
+                        Some(&json!({
+                            "termsOfServiceAgreed": self.config.terms_of_service_agreed,
+                            "contact": self.config.contact,
+                        })),
+                    )
+                    .await?;
+                location(&response)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn issue(&self) -> Result<CachedCertificate> {
+        self.account().await?;
+        let new_order = self.directory().await?.new_order.clone();
+        let identifiers: Vec<Value> = self
+            .config
+            .domains
+            .iter()
+            .map(|x| json!({ "type": "dns", "value": x }))
+            .collect();
+        let response = self
+            .post(&new_order, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let order_url = location(&response)?;
+        let order_url: Url = order_url.parse().context("invalid ACME order URL")?;
+        let mut order: Order = response.json().await?;
+
+        for authorization in &order.authorizations {
+            self.authorize(authorization).await?;
+        }
+
+        let mut params = CertificateParams::new(self.config.domains.clone());
+        params.distinguished_name = DistinguishedName::new();
+        let certificate = rcgen::Certificate::from_params(params)?;
+        let csr = certificate.serialize_request_der()?;
+
+        for _ in 0..POLL_ATTEMPTS {
+            match order.status.as_str() {
+                "ready" => {
+                    order = self
+                        .post_json(
+                            &order.finalize,
+                            Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(&csr) })),
+                        )
+                        .await?;
+                    continue;
+                }
+                "valid" => break,
+                "invalid" => bail!("ACME order {order_url} is invalid"),
+                _ => (),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+            order = self.post_json(&order_url, None).await?;
+        }
+        let Some(certificate_url) = &order.certificate else {
+            bail!("ACME order {order_url} did not complete");
+        };
+        let certificate_pem = self.post(certificate_url, None).await?.text().await?;
+
+        Ok(CachedCertificate {
+            domains: self.config.domains.clone(),
+            issued_at: Utc::now(),
+            certificate_pem,
+            private_key_pem: certificate.serialize_private_key_pem(),
+        })
+    }
+
+    async fn authorize(&self, url: &Url) -> Result<()> {
+        let authorization: Authorization = self.post_json(url, None).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value;
+        let kind = if self.config.http01 {
+            "http-01"
+        } else {
+            "tls-alpn-01"
+        };
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|x| x.kind == kind)
+            .ok_or_else(|| anyhow!("ACME CA does not offer {kind} for {domain}"))?;
+        let key_authorization = self.key_authorization(&challenge.token);
+
+        if self.config.http01 {
+            self.http01_challenges
+                .write()
+                .unwrap()
+                .insert(challenge.token.clone(), key_authorization);
+        } else {
+            let mut params = CertificateParams::new(vec![domain.clone()]);
+            params.custom_extensions = vec![CustomExtension::new_acme_identifier(
+                digest(&SHA256, key_authorization.as_bytes()).as_ref(),
+            )];
+            let certificate = rcgen::Certificate::from_params(params)?;
+            let certified = certified_key(
+                vec![Certificate(certificate.serialize_der()?)],
+                &PrivateKey(certificate.serialize_private_key_der()),
+            )?;
+            self.tls_alpn_challenges
+                .write()
+                .unwrap()
+                .insert(domain.clone(), certified);
+        }
+
+        let result = self.complete_challenge(url, &challenge.url, &domain).await;
+        if self.config.http01 {
+            self.http01_challenges
+                .write()
+                .unwrap()
+                .remove(&challenge.token);
+        } else {
+            self.tls_alpn_challenges.write().unwrap().remove(&domain);
+        }
+        result
+    }
+
+    async fn complete_challenge(
+        &self,
+        authorization: &Url,
+        challenge: &Url,
+        domain: &str,
+    ) -> Result<()> {
+        self.post(challenge, Some(&json!({}))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let authorization: Authorization = self.post_json(authorization, None).await?;
+            match authorization.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" => (),
+                status => bail!("ACME authorization for {domain} is {status}"),
+            }
+        }
+        bail!("timed out validating ACME authorization for {domain}")
+    }
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|x| x.to_str().ok())
+        .map(String::from)
+}
+
+fn location(response: &reqwest::Response) -> Result<String> {
+    response
+        .headers()
+        .get("location")
+        .and_then(|x| x.to_str().ok())
+        .map(String::from)
+        .ok_or_else(|| anyhow!("ACME response is missing a Location header"))
+}
@@ -0,0 +1,136 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio::sync::watch;
+
+use super::{load_certificates, load_private_key, ClientAuthConfig};
+#[cfg(feature = "encrypted-keys")]
+use super::{load_encrypted_private_key, Passphrase};
+
+type BuildConfig = Box<dyn Fn(Vec<Certificate>, PrivateKey) -> Result<ServerConfig> + Send + Sync>;
+
+/// Modification times of a certificate and key file, compared to detect changes
+fn modified(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|x| x.modified()).ok();
+    (modified(cert_path), modified(key_path))
+}
+
+/// Reloads a certificate chain and private key from PEM files when either changes, publishing a new
+/// `ServerConfig` for [`super::TlsIncoming`]
+pub struct CertWatcher {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    build: BuildConfig,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    #[cfg(feature = "encrypted-keys")]
+    passphrase: Option<Passphrase>,
+}
+
+impl CertWatcher {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            build: Box::new(|certificates, key| {
+                Ok(ServerConfig::builder()
+                    .with_safe_defaults()
+                    .with_no_client_auth()
+                    .with_single_cert(certificates, key)?)
+            }),
+            modified: (None, None),
+            #[cfg(feature = "encrypted-keys")]
+            passphrase: None,
+        }
+    }
+
+    /// Builds the `ServerConfig` from each loaded certificate chain and key, i.e. to set ALPN protocols
+    pub fn with_server_config(
+        mut self,
+        build: impl Fn(Vec<Certificate>, PrivateKey) -> Result<ServerConfig> + Send + Sync + 'static,
+    ) -> Self {
+        self.build = Box::new(build);
+        self
+    }
+
+    /// Requests client certificates (mTLS), replacing the default `ServerConfig`. The CA bundle is reloaded along with
+    /// the certificate.
+    pub fn with_client_auth(self, client_auth: ClientAuthConfig) -> Self {
+        self.with_server_config(move |certificates, key| {
+            Ok(client_auth
+                .configure(ServerConfig::builder().with_safe_defaults())?
+                .with_single_cert(certificates, key)?)
+        })
+    }
+
+    /// Decrypts a passphrase-protected PKCS#8 key
+    #[cfg(feature = "encrypted-keys")]
+    pub fn with_key_passphrase(mut self, passphrase: Passphrase) -> Self {
+        self.passphrase = Some(passphrase);
+        self
+    }
+
+    fn load_key(&self) -> Result<PrivateKey> {
+        #[cfg(feature = "encrypted-keys")]
+        if let Some(passphrase) = &self.passphrase {
+            return load_encrypted_private_key(&self.key_path, passphrase);
+        }
+        load_private_key(&self.key_path)
+    }
+
+    pub fn load(&self) -> Result<Arc<ServerConfig>> {
+        let certificates = load_certificates(&self.cert_path)?;
+        let key = self.load_key()?;
+        let config = (self.build)(certificates, key).with_context(|| {
+            format!(
+                "invalid certificate {} or key {}",
+                self.cert_path.display(),
+                self.key_path.display()
+            )
+        })?;
+        Ok(Arc::new(config))
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        modified(&self.cert_path, &self.key_path)
+    }
+
+    /// Loads the certificate now, then polls both files for modifications. A failed reload keeps the previous config.
+    pub fn spawn(mut self, poll_interval: Duration) -> watch::Receiver<Option<Arc<ServerConfig>>> {
+        self.modified = self.modified();
+        let initial = match self.load() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                error!("failed to load TLS certificate: {e:#}");
+                None
+            }
+        };
+        let (sender, receiver) = watch::channel(initial);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(poll_interval).await;
+                if sender.is_closed() {
+                    break;
+                }
+                let modified = self.modified();
+                if modified == self.modified {
+                    continue;
+                }
+                self.modified = modified;
+                match self.load() {
+                    Ok(config) => {
+                        info!("reloaded TLS certificate {}", self.cert_path.display());
+                        sender.send_replace(Some(config));
+                    }
+                    Err(e) => error!("failed to reload TLS certificate: {e:#}"),
+                }
+            }
+        });
+        receiver
+    }
+}
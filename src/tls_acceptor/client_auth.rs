@@ -0,0 +1,86 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use rustls::{
+    server::{
+        AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, WantsServerCert,
+    },
+    Certificate, ConfigBuilder, RootCertStore, ServerConfig, WantsVerifier,
+};
+use serde::{Deserialize, Serialize};
+
+use super::load_certificates;
+use crate::{
+    errors::{ApiError, ApiResult},
+    principal::{AuthMethod, Principal},
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMode {
+    /// Handshakes without a valid client certificate fail
+    Required,
+    /// Clients may connect anonymously, but presented certificates must be valid
+    Optional,
+}
+
+/// Verifies client certificates against `ca_bundle` (mTLS)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ClientAuthConfig {
+    pub mode: ClientAuthMode,
+    pub ca_bundle: PathBuf,
+}
+
+impl ClientAuthConfig {
+    /// Sets the client certificate verifier, i.e. on `ServerConfig::builder().with_safe_defaults()`
+    pub fn configure(
+        &self,
+        builder: ConfigBuilder<ServerConfig, WantsVerifier>,
+    ) -> Result<ConfigBuilder<ServerConfig, WantsServerCert>> {
+        let mut roots = RootCertStore::empty();
+        for certificate in load_certificates(&self.ca_bundle)? {
+            roots
+                .add(&certificate)
+                .map_err(|e| anyhow!("invalid client CA certificate: {e:?}"))?;
+        }
+        if roots.is_empty() {
+            bail!("no client CA certificates in {}", self.ca_bundle.display());
+        }
+        let verifier = match self.mode {
+            ClientAuthMode::Required => AllowAnyAuthenticatedClient::new(roots),
+            ClientAuthMode::Optional => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+        };
+        Ok(builder.with_client_cert_verifier(verifier))
+    }
+}
+
+/// The verified client certificate chain, end entity first. Attached to requests by [`super::TlsMakeService`].
+#[derive(Clone, Debug)]
+pub struct ClientCert(pub Arc<Vec<Certificate>>);
+
+impl ClientCert {
+    pub fn end_entity(&self) -> &Certificate {
+        &self.0[0]
+    }
+
+    pub fn fingerprint(&self) -> String {
+        super::certificate_fingerprint(self.end_entity())
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientCert {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        let cert = req
+            .extensions
+            .get::<ClientCert>()
+            .cloned()
+            .ok_or_else(|| ApiError::Unauthorized("missing client certificate".to_string()))?;
+        Principal::new(cert.fingerprint(), AuthMethod::ClientCert).attach(req);
+        Ok(cert)
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::anyhow;
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use rustls::{CipherSuite, ProtocolVersion, ServerConnection};
+
+use crate::errors::{ApiError, ApiResult};
+
+/// Negotiated parameters of the TLS session a request arrived on. Attached to requests by [`super::TlsMakeService`].
+#[derive(Clone, Debug)]
+pub struct TlsConnectionInfo {
+    /// SNI hostname sent by the client
+    pub server_name: Option<String>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    pub version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+}
+
+impl TlsConnectionInfo {
+    pub fn new(session: &ServerConnection) -> Self {
+        Self {
+            server_name: session.sni_hostname().map(String::from),
+            alpn_protocol: session.alpn_protocol().map(<[u8]>::to_vec),
+            version: session.protocol_version(),
+            cipher_suite: session.negotiated_cipher_suite().map(|x| x.suite()),
+        }
+    }
+
+    /// Whether HTTP/2 was negotiated through ALPN
+    pub fn is_h2(&self) -> bool {
+        self.alpn_protocol.as_deref() == Some(b"h2")
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TlsConnectionInfo {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> ApiResult<Self> {
+        req.extensions
+            .get::<TlsConnectionInfo>()
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::Other(anyhow!(
+                    "missing TlsConnectionInfo, not served by TlsMakeService"
+                ))
+            })
+    }
+}
@@ -0,0 +1,80 @@
+use std::{fmt, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use p12_keystore::KeyStore;
+use pkcs8::EncryptedPrivateKeyInfo;
+use rustls::{Certificate, PrivateKey};
+use serde::{Deserialize, Serialize};
+
+use super::{load_private_key, parse_pem};
+use crate::redact::REDACTED;
+
+/// Passphrase for encrypted key material, given inline or read from an environment variable on each load
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Passphrase {
+    Value(String),
+    Env(String),
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Passphrase::Value(_) => f.debug_tuple("Value").field(&REDACTED).finish(),
+            Passphrase::Env(name) => f.debug_tuple("Env").field(name).finish(),
+        }
+    }
+}
+
+impl Passphrase {
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            Passphrase::Value(value) => Ok(value.clone()),
+            Passphrase::Env(name) => std::env::var(name)
+                .with_context(|| format!("passphrase environment variable {name} not set")),
+        }
+    }
+}
+
+/// First passphrase-protected PKCS#8 key (`ENCRYPTED PRIVATE KEY`) in `path`, falling back to an unencrypted key
+pub fn load_encrypted_private_key(
+    path: impl AsRef<Path>,
+    passphrase: &Passphrase,
+) -> Result<PrivateKey> {
+    let path = path.as_ref();
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let Some((_, der)) = parse_pem(&pem)
+        .with_context(|| format!("failed to parse {}", path.display()))?
+        .into_iter()
+        .find(|(label, _)| label == "ENCRYPTED PRIVATE KEY")
+    else {
+        return load_private_key(path);
+    };
+    let passphrase = passphrase.resolve()?;
+    let key = EncryptedPrivateKeyInfo::try_from(der.as_slice())
+        .map_err(|e| anyhow!("invalid encrypted key in {}: {e}", path.display()))?
+        .decrypt(passphrase.as_bytes())
+        .map_err(|e| anyhow!("failed to decrypt {}: {e}", path.display()))?;
+    Ok(PrivateKey(key.as_bytes().to_vec()))
+}
+
+/// Certificate chain (end entity first) and private key from a PKCS#12 (`.p12`/`.pfx`) bundle
+pub fn load_pkcs12(
+    path: impl AsRef<Path>,
+    passphrase: &Passphrase,
+) -> Result<(Vec<Certificate>, PrivateKey)> {
+    let path = path.as_ref();
+    let der = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let keystore = KeyStore::from_pkcs12(&der, &passphrase.resolve()?)
+        .with_context(|| format!("failed to decrypt {}", path.display()))?;
+    let (_, chain) = keystore
+        .private_key_chain()
+        .with_context(|| format!("no private key in {}", path.display()))?;
+    let certificates = chain
+        .chain()
+        .iter()
+        .map(|x| Certificate(x.as_der().to_vec()))
+        .collect();
+    Ok((certificates, PrivateKey(chain.key().to_vec())))
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+
+use log::warn;
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Lapsed windows are pruned once the per-IP table grows to this, then the oldest ones if none had lapsed
+const RATE_TABLE_CAPACITY: usize = 4096;
+
+/// Protects the acceptor against clients that hold handshakes open or reconnect in a tight loop
+#[derive(Clone, Debug)]
+pub struct HandshakeLimits {
+    /// Drops clients that haven't completed the TLS handshake within this long
+    pub timeout: Option<Duration>,
+    /// Connections arriving while this many handshakes are in progress are dropped
+    pub max_concurrent: Option<usize>,
+    /// Maximum connections accepted from one IP within `rate_window`. IPv6 clients are counted per /64, which
+    /// a single host usually controls.
+    pub per_ip_rate: Option<u32>,
+    pub rate_window: Duration,
+}
+
+impl Default for HandshakeLimits {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            max_concurrent: None,
+            per_ip_rate: None,
+            rate_window: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum HandshakeRejection {
+    Timeout,
+    Concurrency,
+    Rate,
+}
+
+impl HandshakeRejection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeRejection::Timeout => "timeout",
+            HandshakeRejection::Concurrency => "concurrency",
+            HandshakeRejection::Rate => "rate",
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn rejected_total() -> &'static IntCounterVec {
+    static COUNTER: OnceLock<IntCounterVec> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter_vec!(
+            "tls_handshakes_rejected_total",
+            "TLS connections dropped by handshake limits",
+            &["reason"]
+        )
+        .unwrap()
+    })
+}
+
+/// `remote` is `None` for Unix domain socket clients
+pub(super) fn rejected(remote: Option<IpAddr>, rejection: HandshakeRejection) {
+    warn!(
+        "dropped TLS connection from {remote:?}: handshake {}",
+        rejection.as_str()
+    );
+    #[cfg(feature = "prometheus")]
+    rejected_total()
+        .with_label_values(&[rejection.as_str()])
+        .inc();
+}
+
+/// Key of `remote` in the rate table
+fn rate_key(remote: IpAddr) -> IpAddr {
+    match remote.to_canonical() {
+        IpAddr::V6(x) => IpAddr::V6(Ipv6Addr::from(u128::from(x) & (u128::MAX << 64))),
+        x => x,
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct HandshakeThrottle {
+    limits: HandshakeLimits,
+    concurrent: Option<Arc<Semaphore>>,
+    rates: Arc<Mutex<HashMap<IpAddr, (Instant, u32)>>>,
+}
+
+impl HandshakeThrottle {
+    pub(super) fn new(limits: HandshakeLimits) -> Self {
+        Self {
+            concurrent: limits.max_concurrent.map(|x| Arc::new(Semaphore::new(x))),
+            limits,
+            rates: Default::default(),
+        }
+    }
+
+    /// The returned permit must be held until the handshake completes. Unix domain socket clients (`remote` of
+    /// `None`) aren't rate limited. Connections rejected for concurrency don't count towards the rate.
+    pub(super) fn admit(
+        &self,
+        remote: Option<IpAddr>,
+    ) -> Result<Option<OwnedSemaphorePermit>, HandshakeRejection> {
+        let permit = match &self.concurrent {
+            Some(concurrent) => Some(
+                concurrent
+                    .clone()
+                    .try_acquire_owned()
+                    .map_err(|_| HandshakeRejection::Concurrency)?,
+            ),
+            None => None,
+        };
+        if let (Some(per_ip_rate), Some(remote)) = (self.limits.per_ip_rate, remote) {
+            let key = rate_key(remote);
+            let now = Instant::now();
+            let mut rates = self.rates.lock().unwrap();
+            if rates.len() >= RATE_TABLE_CAPACITY && !rates.contains_key(&key) {
+                let window = self.limits.rate_window;
+                rates.retain(|_, (start, _)| now.duration_since(*start) < window);
+                if rates.len() >= RATE_TABLE_CAPACITY {
+                    let oldest = rates
+                        .iter()
+                        .min_by_key(|(_, (start, _))| *start)
+                        .map(|(ip, _)| *ip);
+                    if let Some(oldest) = oldest {
+                        rates.remove(&oldest);
+                    }
+                }
+            }
+            let (start, count) = rates.entry(key).or_insert((now, 0));
+            if now.duration_since(*start) >= self.limits.rate_window {
+                *start = now;
+                *count = 0;
+            }
+            *count += 1;
+            if *count > per_ip_rate {
+                return Err(HandshakeRejection::Rate);
+            }
+        }
+        Ok(permit)
+    }
+
+    pub(super) async fn timeout<F: Future>(
+        &self,
+        handshake: F,
+    ) -> Result<F::Output, HandshakeRejection> {
+        match self.limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                .await
+                .map_err(|_| HandshakeRejection::Timeout),
+            None => Ok(handshake.await),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    convert::Infallible,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::extract::ConnectInfo;
+use futures::future::{ready, Ready};
+use http::Request;
+use tokio_rustls::server::TlsStream;
+use tower_service::Service;
+
+use super::{ClientCert, ClientStream, TlsConnectionInfo};
+use crate::connection::TrackedConnection;
+
+/// Makes a service per accepted TLS connection that attaches `ConnectInfo<SocketAddr>`, [`TlsConnectionInfo`], and the
+/// verified [`ClientCert`] (if any) to each request, i.e. `Server::builder(..).serve(TlsMakeService::new(router))`
+#[derive(Clone)]
+pub struct TlsMakeService<S> {
+    inner: S,
+}
+
+impl<S> TlsMakeService<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, S: Clone> Service<&'a TrackedConnection<TlsStream<ClientStream>>> for TlsMakeService<S> {
+    type Response = TlsConnectionService<S>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, connection: &'a TrackedConnection<TlsStream<ClientStream>>) -> Self::Future {
+        ready(Ok(self.connection_service(connection)))
+    }
+}
+
+impl<S: Clone> TlsMakeService<S> {
+    pub(super) fn connection_service(
+        &self,
+        connection: &TrackedConnection<TlsStream<ClientStream>>,
+    ) -> TlsConnectionService<S> {
+        let (io, session) = connection.get_ref().get_ref();
+        TlsConnectionService {
+            inner: self.inner.clone(),
+            // Unix domain socket clients are reported as localhost, as in `dispatch`
+            remote_addr: io
+                .remote_addr()
+                .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, 0))),
+            tls_info: TlsConnectionInfo::new(session),
+            client_cert: session
+                .peer_certificates()
+                .map(|x| ClientCert(Arc::new(x.to_vec()))),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsConnectionService<S> {
+    inner: S,
+    remote_addr: SocketAddr,
+    tls_info: TlsConnectionInfo,
+    client_cert: Option<ClientCert>,
+}
+
+impl<S, B> Service<Request<B>> for TlsConnectionService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        req.extensions_mut().insert(ConnectInfo(self.remote_addr));
+        req.extensions_mut().insert(self.tls_info.clone());
+        if let Some(client_cert) = &self.client_cert {
+            req.extensions_mut().insert(client_cert.clone());
+        }
+        self.inner.call(req)
+    }
+}
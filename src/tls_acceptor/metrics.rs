@@ -0,0 +1,163 @@
+use std::io;
+
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "prometheus")]
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+
+use crate::connection::ConnectionMetrics;
+
+#[cfg(feature = "prometheus")]
+fn accepted_total() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter!(
+            "tls_connections_accepted_total",
+            "Connections accepted by the TLS listener, before the handshake"
+        )
+        .unwrap()
+    })
+}
+
+#[cfg(feature = "prometheus")]
+fn handshake_failures_total() -> &'static IntCounterVec {
+    static COUNTER: OnceLock<IntCounterVec> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter_vec!(
+            "tls_handshake_failures_total",
+            "Failed TLS handshakes by cause",
+            &["reason"]
+        )
+        .unwrap()
+    })
+}
+
+#[cfg(feature = "prometheus")]
+fn no_config_total() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter!(
+            "tls_connections_no_config_total",
+            "Connections dropped because no certificate was loaded"
+        )
+        .unwrap()
+    })
+}
+
+#[cfg(feature = "prometheus")]
+fn filtered_total() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter!(
+            "tls_connections_filtered_total",
+            "Connections rejected by the connection filter before the handshake"
+        )
+        .unwrap()
+    })
+}
+
+#[cfg(feature = "prometheus")]
+fn queue_dropped_total() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        register_int_counter!(
+            "tls_accept_queue_dropped_total",
+            "Handshaked connections closed because the accept queue was full"
+        )
+        .unwrap()
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum HandshakeFailure {
+    /// Plain HTTP answered by [`super::PlaintextResponse`]
+    Plaintext,
+    /// Missing, invalid, or untrusted client certificate
+    Certificate,
+    /// No common protocol version, cipher suite, or ALPN protocol
+    Incompatible,
+    /// The client aborted with an alert
+    Alert,
+    Protocol,
+    /// The connection failed or was closed mid-handshake
+    Io,
+}
+
+impl HandshakeFailure {
+    pub(super) fn classify(error: &io::Error) -> Self {
+        let Some(error) = error
+            .get_ref()
+            .and_then(|x| x.downcast_ref::<rustls::Error>())
+        else {
+            return HandshakeFailure::Io;
+        };
+        match error {
+            rustls::Error::NoCertificatesPresented
+            | rustls::Error::InvalidCertificateEncoding
+            | rustls::Error::InvalidCertificateSignatureType
+            | rustls::Error::InvalidCertificateSignature
+            | rustls::Error::InvalidCertificateData(_)
+            | rustls::Error::InvalidSct(_) => HandshakeFailure::Certificate,
+            rustls::Error::PeerIncompatibleError(_)
+            | rustls::Error::NoApplicationProtocol
+            | rustls::Error::UnsupportedNameType => HandshakeFailure::Incompatible,
+            rustls::Error::AlertReceived(_) => HandshakeFailure::Alert,
+            _ => HandshakeFailure::Protocol,
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeFailure::Plaintext => "plaintext",
+            HandshakeFailure::Certificate => "certificate",
+            HandshakeFailure::Incompatible => "incompatible",
+            HandshakeFailure::Alert => "alert",
+            HandshakeFailure::Protocol => "protocol",
+            HandshakeFailure::Io => "io",
+        }
+    }
+}
+
+pub(super) fn accepted() {
+    #[cfg(feature = "prometheus")]
+    accepted_total().inc();
+}
+
+#[cfg_attr(not(feature = "prometheus"), allow(unused_variables))]
+pub(super) fn handshake_failed(failure: HandshakeFailure) {
+    #[cfg(feature = "prometheus")]
+    handshake_failures_total()
+        .with_label_values(&[failure.as_str()])
+        .inc();
+}
+
+pub(super) fn no_config() {
+    #[cfg(feature = "prometheus")]
+    no_config_total().inc();
+}
+
+pub(super) fn filtered() {
+    #[cfg(feature = "prometheus")]
+    filtered_total().inc();
+}
+
+pub(super) fn queue_dropped() {
+    #[cfg(feature = "prometheus")]
+    queue_dropped_total().inc();
+}
+
+/// Shared by every [`super::TlsIncoming`] without its own [`ConnectionMetrics`], exported as `tls_connections_open`
+/// etc. under the `prometheus` feature
+pub(super) fn connection_metrics() -> ConnectionMetrics {
+    #[cfg(feature = "prometheus")]
+    {
+        static METRICS: OnceLock<ConnectionMetrics> = OnceLock::new();
+        METRICS
+            .get_or_init(|| ConnectionMetrics::with_prometheus("tls_connections"))
+            .clone()
+    }
+    #[cfg(not(feature = "prometheus"))]
+    ConnectionMetrics::default()
+}
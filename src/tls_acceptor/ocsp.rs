@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::future::{select, Either};
+use log::{info, warn};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, ServerConfig,
+};
+use sha1::{Digest, Sha1};
+use tokio::sync::watch;
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::{GeneralName, ParsedExtension},
+    oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+    prelude::FromDer,
+};
+use yasna::{models::ObjectIdentifier, tags::TAG_GENERALIZEDTIME, BERReaderSeq, Tag};
+
+/// id-sha1, the CertID hash algorithm every responder supports
+const SHA1_OID: &[u64] = &[1, 3, 14, 3, 2, 26];
+
+/// OCSP responder URL from the authority information access extension of `certificate`
+fn responder_url(certificate: &X509Certificate) -> Option<String> {
+    certificate
+        .iter_extensions()
+        .find_map(|x| match x.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })?
+        .accessdescs
+        .iter()
+        .filter(|x| x.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP)
+        .find_map(|x| match x.access_location {
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+}
+
+/// DER `OCSPRequest` (RFC 6960) for `certificate` issued by `issuer`
+fn ocsp_request(certificate: &X509Certificate, issuer: &X509Certificate) -> Vec<u8> {
+    let name_hash = Sha1::digest(certificate.issuer().as_raw());
+    let key_hash = Sha1::digest(&issuer.public_key().subject_public_key.data);
+    yasna::construct_der(|w| {
+        // OCSPRequest, TBSRequest, requestList, Request
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next().write_sequence(|w| {
+                    w.next().write_sequence(|w| {
+                        // CertID
+                        w.next().write_sequence(|w| {
+                            w.next().write_sequence(|w| {
+                                w.next().write_oid(&ObjectIdentifier::from_slice(SHA1_OID));
+                                w.next().write_null();
+                            });
+                            w.next().write_bytes(&name_hash);
+                            w.next().write_bytes(&key_hash);
+                            w.next().write_bigint_bytes(certificate.raw_serial(), true);
+                        });
+                    });
+                });
+            });
+        });
+    })
+}
+
+/// Stapled responses of certificates no longer served are dropped after this long
+const UNUSED_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// DER `OCSPResponse` with the `nextUpdate` of its first response
+#[derive(Clone, Debug)]
+pub struct OcspResponse {
+    pub der: Vec<u8>,
+    /// `None` if the responder always has newer information
+    pub next_update: Option<DateTime<Utc>>,
+}
+
+/// `GeneralizedTime` as restricted by RFC 5280, `YYYYMMDDHHMMSSZ`
+fn read_time(r: &mut BERReaderSeq) -> yasna::ASN1Result<Vec<u8>> {
+    r.next()
+        .read_tagged_implicit(TAG_GENERALIZEDTIME, |r| r.read_bytes())
+}
+
+/// Checks that `response` is a successful `OCSPResponse`, returning the `nextUpdate` of its first response. The
+/// signature and status are left to clients.
+fn check_response(response: &[u8]) -> Result<Option<DateTime<Utc>>> {
+    let (status, bytes) = yasna::parse_der(response, |r| {
+        r.read_sequence(|r| {
+            let status = r.next().read_enum()?;
+            // ResponseBytes, the response type is always id-pkix-ocsp-basic
+            let bytes = r.read_optional(|r| {
+                r.read_tagged(Tag::context(0), |r| {
+                    r.read_sequence(|r| {
+                        r.next().read_oid()?;
+                        r.next().read_bytes()
+                    })
+                })
+            })?;
+            Ok((status, bytes))
+        })
+    })
+    .map_err(|e| anyhow::anyhow!("malformed OCSP response: {e}"))?;
+    if status != 0 {
+        bail!("OCSP responder returned status {status}");
+    }
+    let Some(bytes) = bytes else {
+        bail!("OCSP response has no body");
+    };
+    let mut next_update = None;
+    // BasicOCSPResponse, ResponseData
+    yasna::parse_der(&bytes, |r| {
+        r.read_sequence(|r| {
+            r.next().read_sequence(|r| {
+                r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+                // responderID, producedAt
+                r.next().read_der()?;
+                r.next().read_der()?;
+                r.next().read_sequence_of(|r| {
+                    // SingleResponse: certID, certStatus, thisUpdate
+                    r.read_sequence(|r| {
+                        r.next().read_der()?;
+                        r.next().read_der()?;
+                        read_time(r)?;
+                        let time = r.read_optional(|r| {
+                            r.read_tagged(Tag::context(0), |r| {
+                                r.read_tagged_implicit(TAG_GENERALIZEDTIME, |r| r.read_bytes())
+                            })
+                        })?;
+                        r.read_optional(|r| r.read_tagged(Tag::context(1), |r| r.read_der()))?;
+                        if next_update.is_none() {
+                            next_update = Some(time);
+                        }
+                        Ok(())
+                    })
+                })?;
+                r.read_optional(|r| r.read_tagged(Tag::context(1), |r| r.read_der()))?;
+                Ok(())
+            })?;
+            // signatureAlgorithm, signature, certs
+            r.next().read_der()?;
+            r.next().read_der()?;
+            r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+            Ok(())
+        })
+    })
+    .map_err(|e| anyhow::anyhow!("malformed basic OCSP response: {e}"))?;
+    let Some(next_update) = next_update else {
+        bail!("OCSP response has no certificate status");
+    };
+    next_update
+        .map(|x| {
+            let x = std::str::from_utf8(&x).ok()?;
+            NaiveDateTime::parse_from_str(x, "%Y%m%d%H%M%SZ")
+                .ok()
+                .map(|x| x.and_utc())
+        })
+        .map(|x| x.context("malformed OCSP nextUpdate"))
+        .transpose()
+}
+
+/// Fetches a fresh OCSP response for the end entity of `certificates`, which must include its issuer
+pub async fn fetch_ocsp(
+    client: &reqwest::Client,
+    certificates: &[Certificate],
+) -> Result<OcspResponse> {
+    let [end_entity, issuer, ..] = certificates else {
+        bail!("OCSP stapling requires the issuer certificate in the chain");
+    };
+    let (_, end_entity) =
+        X509Certificate::from_der(&end_entity.0).context("invalid end entity certificate")?;
+    let (_, issuer) = X509Certificate::from_der(&issuer.0).context("invalid issuer certificate")?;
+    let url = responder_url(&end_entity).context("certificate has no OCSP responder")?;
+    let response = client
+        .post(&url)
+        .header("content-type", "application/ocsp-request")
+        .body(ocsp_request(&end_entity, &issuer))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let next_update = check_response(&response).with_context(|| format!("from {url}"))?;
+    Ok(OcspResponse {
+        der: response.to_vec(),
+        next_update,
+    })
+}
+
+/// OCSP state of one end entity certificate
+struct Staple {
+    response: Option<OcspResponse>,
+    next_fetch: Instant,
+    fetching: bool,
+    last_used: Instant,
+}
+
+/// Staples shared by every config of an [`OcspStapler`], keyed by end entity certificate
+struct Staples {
+    client: reqwest::Client,
+    refresh_interval: Duration,
+    retry_interval: Duration,
+    staples: Mutex<HashMap<Vec<u8>, Staple>>,
+}
+
+impl Staples {
+    /// Current response for `key`, fetching a new one in the background when due
+    fn staple(self: &Arc<Self>, key: &CertifiedKey) -> Option<Vec<u8>> {
+        let end_entity = &key.cert.first()?.0;
+        let now = Instant::now();
+        let mut staples = self.staples.lock().unwrap();
+        if !staples.contains_key(end_entity) {
+            staples.retain(|_, x| now.duration_since(x.last_used) < UNUSED_EXPIRY);
+        }
+        let staple = staples.entry(end_entity.clone()).or_insert_with(|| Staple {
+            response: None,
+            next_fetch: now,
+            fetching: false,
+            last_used: now,
+        });
+        staple.last_used = now;
+        if !staple.fetching && now >= staple.next_fetch {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                staple.fetching = true;
+                runtime.spawn(self.clone().fetch(key.cert.clone()));
+            }
+        }
+        let response = staple.response.as_ref()?;
+        // a response past its nextUpdate may already be superseded by a revocation
+        if response.next_update.is_some_and(|x| x <= Utc::now()) {
+            return None;
+        }
+        Some(response.der.clone())
+    }
+
+    async fn fetch(self: Arc<Self>, certificates: Vec<Certificate>) {
+        let result = fetch_ocsp(&self.client, &certificates).await;
+        let mut staples = self.staples.lock().unwrap();
+        let Some(staple) = staples.get_mut(&certificates[0].0) else {
+            return;
+        };
+        staple.fetching = false;
+        match result {
+            Ok(response) => {
+                info!("refreshed OCSP staple");
+                // refresh halfway to nextUpdate if that's sooner
+                let refresh = response
+                    .next_update
+                    .and_then(|x| (x - Utc::now()).to_std().ok())
+                    .map(|x| (x / 2).min(self.refresh_interval))
+                    .unwrap_or(self.refresh_interval);
+                staple.next_fetch = Instant::now() + refresh.max(self.retry_interval);
+                staple.response = Some(response);
+            }
+            Err(e) => {
+                warn!("failed to fetch OCSP response: {e:#}");
+                staple.next_fetch = Instant::now() + self.retry_interval;
+            }
+        }
+    }
+}
+
+/// Wraps the resolver of a config to staple OCSP responses to the certificates it returns
+struct StaplingResolver {
+    inner: Arc<dyn ResolvesServerCert>,
+    staples: Arc<Staples>,
+}
+
+impl ResolvesServerCert for StaplingResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let key = self.inner.resolve(client_hello)?;
+        // self-signed certificates, i.e. for TLS-ALPN-01, have no responder
+        if key.cert.len() < 2 {
+            return Some(key);
+        }
+        match self.staples.staple(&key) {
+            Some(ocsp) => Some(Arc::new(CertifiedKey {
+                ocsp: Some(ocsp),
+                ..(*key).clone()
+            })),
+            None => Some(key),
+        }
+    }
+}
+
+/// Staples OCSP responses to the certificates of configs from an existing watch channel, i.e. of
+/// [`super::ServerConfigBuilder::watch`], [`super::CertWatcher::spawn`], or `AcmeManager::spawn`. Responses are
+/// fetched in the background once a certificate is first served, refreshed periodically, and no longer stapled
+/// past their `nextUpdate`.
+pub struct OcspStapler {
+    client: reqwest::Client,
+    refresh_interval: Duration,
+    retry_interval: Duration,
+}
+
+impl OcspStapler {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .context("failed to build OCSP client")?,
+            refresh_interval: Duration::from_secs(12 * 60 * 60),
+            retry_interval: Duration::from_secs(5 * 60),
+        })
+    }
+
+    /// Defaults to 12 hours, sooner if a response's `nextUpdate` is less than twice that away
+    pub fn with_refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    /// Defaults to 5 minutes
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Republishes each config of `configs` with its certificate resolver wrapped to staple responses. Stapled
+    /// responses carry over across reloads of the same certificate.
+    pub fn spawn(
+        self,
+        mut configs: watch::Receiver<Option<Arc<ServerConfig>>>,
+    ) -> watch::Receiver<Option<Arc<ServerConfig>>> {
+        let staples = Arc::new(Staples {
+            client: self.client,
+            refresh_interval: self.refresh_interval,
+            retry_interval: self.retry_interval,
+            staples: Default::default(),
+        });
+        let wrap = move |config: &Option<Arc<ServerConfig>>| {
+            let mut config = ServerConfig::clone(config.as_ref()?);
+            config.cert_resolver = Arc::new(StaplingResolver {
+                inner: config.cert_resolver.clone(),
+                staples: staples.clone(),
+            });
+            Some(Arc::new(config))
+        };
+        let (sender, receiver) = watch::channel(wrap(&configs.borrow_and_update()));
+        tokio::spawn(async move {
+            loop {
+                let changed = {
+                    let changed = std::pin::pin!(configs.changed());
+                    let closed = std::pin::pin!(sender.closed());
+                    matches!(select(changed, closed).await, Either::Left((Ok(()), _)))
+                };
+                if !changed {
+                    break;
+                }
+                sender.send_replace(wrap(&configs.borrow_and_update()));
+            }
+        });
+        receiver
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use rustls::{Certificate, PrivateKey};
+
+pub use crate::pem::parse_pem;
+
+pub fn load_certificates(path: impl AsRef<Path>) -> Result<Vec<Certificate>> {
+    let path = path.as_ref();
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let certificates: Vec<Certificate> = parse_pem(&pem)
+        .with_context(|| format!("failed to parse {}", path.display()))?
+        .into_iter()
+        .filter(|(label, _)| label == "CERTIFICATE")
+        .map(|(_, der)| Certificate(der))
+        .collect();
+    if certificates.is_empty() {
+        bail!("no certificates in {}", path.display());
+    }
+    Ok(certificates)
+}
+
+/// First PKCS#8, PKCS#1 (RSA), or SEC1 (EC) private key in `path`
+pub fn load_private_key(path: impl AsRef<Path>) -> Result<PrivateKey> {
+    let path = path.as_ref();
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    parse_pem(&pem)
+        .with_context(|| format!("failed to parse {}", path.display()))?
+        .into_iter()
+        .find(|(label, _)| {
+            matches!(
+                label.as_str(),
+                "PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY"
+            )
+        })
+        .map(|(_, der)| PrivateKey(der))
+        .with_context(|| format!("no private key in {}", path.display()))
+}
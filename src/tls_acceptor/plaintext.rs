@@ -0,0 +1,86 @@
+use futures::future::poll_fn;
+use http::uri::{Authority, PathAndQuery};
+use log::debug;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
+
+use super::ClientStream;
+
+/// Largest plaintext request head read when answering [`PlaintextResponse::Redirect`]
+const MAX_HEAD: usize = 8192;
+
+/// How to answer clients speaking plain HTTP to the TLS port
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaintextResponse {
+    /// Let the TLS handshake fail
+    #[default]
+    None,
+    /// `400 Bad Request` explaining that HTTPS is required
+    BadRequest,
+    /// `301` to the `https://` equivalent on the same host and port
+    Redirect,
+}
+
+const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: 44\r\nConnection: close\r\n\r\nThis server only accepts HTTPS connections.\n";
+
+/// `https://` location for a plain HTTP request head
+fn redirect_location(head: &[u8]) -> Option<String> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut lines = head.split("\r\n");
+    let path = lines.next()?.split(' ').nth(1)?;
+    let path: PathAndQuery = path.parse().ok()?;
+    if !path.as_str().starts_with('/') {
+        return None;
+    }
+    let host = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("host").then(|| value.trim())
+    })?;
+    let host: Authority = host.parse().ok()?;
+    Some(format!("https://{host}{path}"))
+}
+
+/// Peeks at the first byte of the connection, answering and closing it if it isn't a TLS handshake record but looks
+/// like an HTTP request. Returns whether the connection was answered.
+pub(super) async fn answer_plaintext(
+    stream: &mut ClientStream,
+    response: PlaintextResponse,
+) -> bool {
+    if response == PlaintextResponse::None {
+        return false;
+    }
+    let mut first = [0u8; 1];
+    let peeked = poll_fn(|cx| stream.poll_peek(cx, &mut ReadBuf::new(&mut first))).await;
+    // TLS handshake records start with 0x16, HTTP methods with an uppercase letter
+    if !matches!(peeked, Ok(1)) || !first[0].is_ascii_uppercase() {
+        return false;
+    }
+    debug!(
+        "answering plaintext HTTP request on TLS port from {:?}",
+        stream.remote_addr()
+    );
+
+    let location = if response == PlaintextResponse::Redirect {
+        let mut head = Vec::with_capacity(1024);
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|x| x == b"\r\n\r\n") && head.len() < MAX_HEAD {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => head.extend_from_slice(&buf[..n]),
+            }
+        }
+        redirect_location(&head)
+    } else {
+        None
+    };
+    let reply = match location {
+        Some(location) => format!(
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+        .into_bytes(),
+        None => BAD_REQUEST.to_vec(),
+    };
+    if stream.write_all(&reply).await.is_ok() {
+        stream.shutdown().await.ok();
+    }
+    true
+}
@@ -0,0 +1,122 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+#[cfg(feature = "acme")]
+use axum::{extract::Path, routing::get};
+use axum::{extract::State, http::Uri, routing::any, Router};
+use http::{header::HOST, HeaderMap};
+use hyper::server::conn::AddrIncoming;
+use tokio::sync::watch;
+use url::Url;
+
+#[cfg(feature = "acme")]
+use super::AcmeManager;
+use crate::{
+    errors::{ApiError, ApiResult, RedirectMode},
+    incoming::shutdown_signal,
+};
+
+struct RedirectState {
+    https_port: Option<u16>,
+}
+
+/// Answers plain HTTP requests (i.e. on port 80) with a permanent redirect to the HTTPS equivalent
+pub struct HttpRedirectServer {
+    incoming: AddrIncoming,
+    https_port: Option<u16>,
+    shutdown: Option<watch::Receiver<bool>>,
+    #[cfg(feature = "acme")]
+    acme: Option<Arc<AcmeManager>>,
+}
+
+impl HttpRedirectServer {
+    pub fn bind(listen: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            incoming: AddrIncoming::bind(&listen)?,
+            https_port: None,
+            shutdown: None,
+            #[cfg(feature = "acme")]
+            acme: None,
+        })
+    }
+
+    /// Port of the HTTPS listener if not 443
+    pub fn with_https_port(mut self, https_port: u16) -> Self {
+        self.https_port = Some(https_port).filter(|x| *x != 443);
+        self
+    }
+
+    /// Serves `/.well-known/acme-challenge/{token}` for HTTP-01 validation
+    #[cfg(feature = "acme")]
+    pub fn with_acme(mut self, acme: Arc<AcmeManager>) -> Self {
+        self.acme = Some(acme);
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    /// Runs until the shutdown signal, if any
+    pub async fn serve(self) -> Result<()> {
+        let state = Arc::new(RedirectState {
+            https_port: self.https_port,
+        });
+        #[allow(unused_mut)]
+        let mut router = Router::new().fallback(any(redirect)).with_state(state);
+        #[cfg(feature = "acme")]
+        if let Some(acme) = self.acme {
+            router = router.merge(
+                Router::new()
+                    .route("/.well-known/acme-challenge/:token", get(acme_challenge))
+                    .with_state(acme),
+            );
+        }
+        let mut shutdown = self.shutdown;
+        axum::Server::builder(self.incoming)
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(async move { shutdown_signal(&mut shutdown).await })
+            .await?;
+        Ok(())
+    }
+}
+
+async fn redirect(
+    State(state): State<Arc<RedirectState>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> ApiResult<()> {
+    let host = headers
+        .get(HOST)
+        .and_then(|x| x.to_str().ok())
+        .or_else(|| uri.host())
+        .ok_or_else(|| ApiError::BadRequest("missing Host header".to_string()))?;
+    // drop the plain HTTP port, keeping bracketed IPv6 literals intact
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    let path = uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+    let location = match state.https_port {
+        Some(port) => format!("https://{host}:{port}{path}"),
+        None => format!("https://{host}{path}"),
+    };
+    let location: Url = location
+        .parse()
+        .map_err(|_| ApiError::BadRequest("invalid Host header".to_string()))?;
+    Err(ApiError::Redirect(RedirectMode::MovedPermanently, location))
+}
+
+#[cfg(feature = "acme")]
+async fn acme_challenge(
+    State(acme): State<Arc<AcmeManager>>,
+    Path(token): Path<String>,
+) -> ApiResult<String> {
+    acme.http01_key_authorization(&token)
+        .ok_or(ApiError::NotFound)
+}
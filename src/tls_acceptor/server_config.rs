@@ -0,0 +1,185 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use rustls::{
+    version::{TLS12, TLS13},
+    Certificate, PrivateKey, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+    DEFAULT_CIPHER_SUITES,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use super::{load_certificates, load_private_key, CertWatcher, ClientAuthConfig, SessionTickets};
+#[cfg(feature = "encrypted-keys")]
+use super::{load_encrypted_private_key, Passphrase};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+static TLS12_AND_NEWER: [&SupportedProtocolVersion; 2] = [&TLS13, &TLS12];
+static TLS13_AND_NEWER: [&SupportedProtocolVersion; 1] = [&TLS13];
+
+impl TlsVersion {
+    fn and_newer(self) -> &'static [&'static SupportedProtocolVersion] {
+        match self {
+            TlsVersion::Tls12 => &TLS12_AND_NEWER,
+            TlsVersion::Tls13 => &TLS13_AND_NEWER,
+        }
+    }
+}
+
+/// Builds the `ServerConfig` for [`super::TlsIncoming`] from a PEM certificate chain and private key
+#[derive(Clone)]
+pub struct ServerConfigBuilder {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    http2: bool,
+    min_version: TlsVersion,
+    cipher_suites: Vec<SupportedCipherSuite>,
+    client_auth: Option<ClientAuthConfig>,
+    session_tickets: Option<Arc<SessionTickets>>,
+    #[cfg(feature = "encrypted-keys")]
+    passphrase: Option<Passphrase>,
+}
+
+impl ServerConfigBuilder {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            http2: true,
+            min_version: TlsVersion::default(),
+            cipher_suites: DEFAULT_CIPHER_SUITES.to_vec(),
+            client_auth: None,
+            session_tickets: None,
+            #[cfg(feature = "encrypted-keys")]
+            passphrase: None,
+        }
+    }
+
+    /// Advertises `h2` ahead of `http/1.1` over ALPN. Enabled by default, served through the `axum/http2` feature
+    /// the `tls` feature turns on.
+    pub fn with_http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    pub fn with_min_version(mut self, min_version: TlsVersion) -> Self {
+        self.min_version = min_version;
+        self
+    }
+
+    /// In order of preference, i.e. a subset of `rustls::ALL_CIPHER_SUITES`
+    pub fn with_cipher_suites(mut self, cipher_suites: Vec<SupportedCipherSuite>) -> Self {
+        self.cipher_suites = cipher_suites;
+        self
+    }
+
+    pub fn with_client_auth(mut self, client_auth: ClientAuthConfig) -> Self {
+        self.client_auth = Some(client_auth);
+        self
+    }
+
+    /// Shared by every config built, so tickets stay valid across certificate reloads
+    pub fn with_session_tickets(mut self, session_tickets: Arc<SessionTickets>) -> Self {
+        self.session_tickets = Some(session_tickets);
+        self
+    }
+
+    /// Decrypts a passphrase-protected PKCS#8 key
+    #[cfg(feature = "encrypted-keys")]
+    pub fn with_key_passphrase(mut self, passphrase: Passphrase) -> Self {
+        self.passphrase = Some(passphrase);
+        self
+    }
+
+    fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        let mut protocols = vec![];
+        if self.http2 {
+            protocols.push(b"h2".to_vec());
+        }
+        protocols.push(b"http/1.1".to_vec());
+        protocols
+    }
+
+    /// Builds a config for an already loaded certificate chain and key
+    pub fn build_with(
+        &self,
+        certificates: Vec<Certificate>,
+        key: PrivateKey,
+    ) -> Result<ServerConfig> {
+        self.build_with_ocsp(certificates, key, vec![])
+    }
+
+    /// Builds a config stapling the DER `ocsp` response, if not empty
+    pub fn build_with_ocsp(
+        &self,
+        certificates: Vec<Certificate>,
+        key: PrivateKey,
+        ocsp: Vec<u8>,
+    ) -> Result<ServerConfig> {
+        let builder = ServerConfig::builder()
+            .with_cipher_suites(&self.cipher_suites)
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(self.min_version.and_newer())
+            .context("no configured cipher suite supports the minimum TLS version")?;
+        let builder = match &self.client_auth {
+            Some(client_auth) => client_auth.configure(builder)?,
+            None => builder.with_no_client_auth(),
+        };
+        let mut config =
+            builder.with_single_cert_with_ocsp_and_sct(certificates, key, ocsp, vec![])?;
+        config.alpn_protocols = self.alpn_protocols();
+        if let Some(session_tickets) = &self.session_tickets {
+            session_tickets.apply(&mut config);
+        }
+        Ok(config)
+    }
+
+    pub(super) fn load(&self) -> Result<(Vec<Certificate>, PrivateKey)> {
+        let certificates = load_certificates(&self.cert_path)?;
+        #[cfg(feature = "encrypted-keys")]
+        let key = match &self.passphrase {
+            Some(passphrase) => load_encrypted_private_key(&self.key_path, passphrase)?,
+            None => load_private_key(&self.key_path)?,
+        };
+        #[cfg(not(feature = "encrypted-keys"))]
+        let key = load_private_key(&self.key_path)?;
+        Ok((certificates, key))
+    }
+
+    pub(super) fn context(&self) -> String {
+        format!(
+            "invalid certificate {} or key {}",
+            self.cert_path.display(),
+            self.key_path.display()
+        )
+    }
+
+    pub fn build(&self) -> Result<Arc<ServerConfig>> {
+        let (certificates, key) = self.load()?;
+        let config = self
+            .build_with(certificates, key)
+            .with_context(|| self.context())?;
+        Ok(Arc::new(config))
+    }
+
+    /// Builds the config now and again whenever the certificate or key changes, see [`CertWatcher`]
+    pub fn watch(self, poll_interval: Duration) -> watch::Receiver<Option<Arc<ServerConfig>>> {
+        #[allow(unused_mut)]
+        let mut watcher = CertWatcher::new(self.cert_path.clone(), self.key_path.clone());
+        #[cfg(feature = "encrypted-keys")]
+        if let Some(passphrase) = self.passphrase.clone() {
+            watcher = watcher.with_key_passphrase(passphrase);
+        }
+        watcher
+            .with_server_config(move |certificates, key| self.build_with(certificates, key))
+            .spawn(poll_interval)
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use rustls::{server::ProducesTickets, ServerConfig};
+
+const KEY_ID_LEN: usize = 4;
+
+struct TicketKey {
+    id: [u8; KEY_ID_LEN],
+    key: LessSafeKey,
+}
+
+impl TicketKey {
+    fn generate(rng: &SystemRandom) -> Result<Self> {
+        let mut id = [0u8; KEY_ID_LEN];
+        let mut key = [0u8; 32];
+        rng.fill(&mut id)
+            .and_then(|_| rng.fill(&mut key))
+            .map_err(|_| anyhow!("failed to generate session ticket key"))?;
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key)
+            .map_err(|_| anyhow!("invalid session ticket key"))?;
+        Ok(Self {
+            id,
+            key: LessSafeKey::new(key),
+        })
+    }
+}
+
+struct Keys {
+    current: TicketKey,
+    previous: Option<TicketKey>,
+    rotated_at: Instant,
+}
+
+/// Issues TLS session tickets for resumption without a full handshake, rotating the key every `rotation` and
+/// accepting tickets from the previous key until the next rotation. Apply one instance to every reloaded config so
+/// tickets survive certificate reloads.
+pub struct SessionTickets {
+    rotation: Duration,
+    rng: SystemRandom,
+    keys: RwLock<Keys>,
+}
+
+impl SessionTickets {
+    pub fn new(rotation: Duration) -> Result<Arc<Self>> {
+        let rng = SystemRandom::new();
+        Ok(Arc::new(Self {
+            rotation,
+            keys: RwLock::new(Keys {
+                current: TicketKey::generate(&rng)?,
+                previous: None,
+                rotated_at: Instant::now(),
+            }),
+            rng,
+        }))
+    }
+
+    pub fn apply(self: &Arc<Self>, config: &mut ServerConfig) {
+        config.ticketer = self.clone();
+    }
+
+    fn rotate_if_due(&self) {
+        if self.keys.read().unwrap().rotated_at.elapsed() < self.rotation {
+            return;
+        }
+        let mut keys = self.keys.write().unwrap();
+        let elapsed = keys.rotated_at.elapsed();
+        if elapsed < self.rotation {
+            return;
+        }
+        let Ok(key) = TicketKey::generate(&self.rng) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut keys.current, key);
+        // after a long idle period the previous key is also past its lifetime
+        keys.previous = (elapsed < self.rotation * 2).then_some(previous);
+        keys.rotated_at = Instant::now();
+    }
+}
+
+impl ProducesTickets for SessionTickets {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.rotation.as_secs().try_into().unwrap_or(u32::MAX)
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.rotate_if_due();
+        let keys = self.keys.read().unwrap();
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).ok()?;
+        let mut ticket = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + plain.len() + 16);
+        ticket.extend_from_slice(&keys.current.id);
+        ticket.extend_from_slice(&nonce);
+        let mut sealed = plain.to_vec();
+        keys.current
+            .key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&keys.current.id),
+                &mut sealed,
+            )
+            .ok()?;
+        ticket.extend_from_slice(&sealed);
+        Some(ticket)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.rotate_if_due();
+        if cipher.len() < KEY_ID_LEN + NONCE_LEN {
+            return None;
+        }
+        let (id, rest) = cipher.split_at(KEY_ID_LEN);
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+        let keys = self.keys.read().unwrap();
+        let key = [Some(&keys.current), keys.previous.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|x| x.id == id)?;
+        let mut plain = sealed.to_vec();
+        let len = key
+            .key
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).ok()?,
+                Aad::from(&key.id),
+                &mut plain,
+            )
+            .ok()?
+            .len();
+        plain.truncate(len);
+        Some(plain)
+    }
+}
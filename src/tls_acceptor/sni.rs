@@ -0,0 +1,81 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use tokio::sync::watch;
+
+use super::TlsIncoming;
+
+/// Certificates by lowercase server name. `*.example.com` entries match one extra label, and `*` matches clients
+/// without (or with an unknown) SNI.
+pub type SniCertificates = HashMap<String, Arc<CertifiedKey>>;
+
+pub fn certified_key(
+    certificates: Vec<Certificate>,
+    key: &PrivateKey,
+) -> Result<Arc<CertifiedKey>> {
+    let key = sign::any_supported_type(key).map_err(|_| anyhow!("unsupported private key type"))?;
+    Ok(Arc::new(CertifiedKey::new(certificates, key)))
+}
+
+/// Resolves certificates from the latest published [`SniCertificates`] on every handshake
+pub struct SniResolver {
+    certificates: watch::Receiver<SniCertificates>,
+}
+
+impl SniResolver {
+    pub fn new(certificates: watch::Receiver<SniCertificates>) -> Self {
+        Self { certificates }
+    }
+
+    pub fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let certificates = self.certificates.borrow();
+        let exact = server_name.map(|x| x.to_ascii_lowercase());
+        let wildcard = exact
+            .as_deref()
+            .and_then(|x| x.split_once('.'))
+            .map(|(_, parent)| format!("*.{parent}"));
+        exact
+            .iter()
+            .chain(wildcard.iter())
+            .find_map(|x| certificates.get(x))
+            .or_else(|| certificates.get("*"))
+            .cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let key = self.lookup(client_hello.server_name());
+        if key.is_none() {
+            debug!(
+                "no TLS certificate for server name {:?}",
+                client_hello.server_name()
+            );
+        }
+        key
+    }
+}
+
+impl TlsIncoming {
+    /// Selects the certificate per connection from the ClientHello SNI, so one listener can serve many domains
+    pub fn new_sni(
+        listen: SocketAddr,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+        certificates: watch::Receiver<SniCertificates>,
+    ) -> Result<Self> {
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SniResolver::new(certificates)));
+        // the receiver keeps the last value after the sender is dropped
+        let (_, tls_config) = watch::channel(Some(Arc::new(server_config)));
+        Self::new(listen, nodelay, keepalive, tls_config)
+    }
+}